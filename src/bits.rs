@@ -22,6 +22,12 @@ pub const fn u16_sms(word: u16, right: u8, length: u8, left: u8) -> u16 {
     ((word >> right) & u16_mask(length)) << left
 }
 
+/// Interprets the low 12 bits of the operand as a signed integer and
+/// sign-extends it to fill 32 bits again.
+pub const fn sign_extend_12bit(imm: u32) -> i32 {
+    (imm << 20) as i32 >> 20
+}
+
 pub trait SignExtend<T> {
     fn sign_extend(self) -> T;
 }
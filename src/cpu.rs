@@ -1,244 +1,1319 @@
+pub mod block;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod sbi;
+
+use self::block::{Block, Op};
 use crate::{
-    bits::SignExtend,
-    error::Result,
+    bits::{sign_extend_12bit, SignExtend},
+    csr::{
+        self, CsrFile, Privilege, MSTATUS_FS_INITIAL, MSTATUS_MIE,
+        MSTATUS_MPRV, MSTATUS_MXR, MSTATUS_SIE, MSTATUS_SUM, MSTATUS_TSR,
+        MSTATUS_TVM, MSTATUS_TW,
+    },
+    decode_cache::Decoded,
+    disasm::{Disassembly, Encoding},
+    error::{Error, Result},
+    float::{self, Precision, RoundingMode},
     instruction::{
-        BFunct, IFunct, Instruction, NeedMoreBytes, RFunct, SFunct, UOpcode,
+        AmoFunct, AmoWidth, CsrFunct, FArithFunct, FCompareFunct, FFmaFunct,
+        FMinMaxFunct, FSignFunct, Instruction, NeedMoreBytes,
     },
-    register::RegisterName,
-    Opts,
+    machine::Shared,
+    mmu::{self, Access, Mmu},
+    register::{FRegisterName, RegisterName},
+    trace::{Commit, Register, Store},
+    trap::{Exception, Interrupt},
+    virt::{self, Virt},
+    Opts, SbiImplementation,
+};
+use std::{
+    cell::{RefCell, RefMut},
+    cmp::Ordering,
+    io::{self, Write},
+    ops::{ControlFlow, Index, IndexMut},
+    rc::Rc,
+    time::Instant,
 };
-use std::ops::{Index, IndexMut};
 
-const STACK_SIZE: usize = 4096;
+/// HTIF device and command for writing a character to the console.
+const HTIF_CONSOLE_PUTCHAR: u64 = 0x0101;
+/// The most blocks that run one after another before the hart gives the
+/// other harts and devices a turn.
+const MAX_CHAINED_BLOCKS: usize = 64;
+
+/// Why the guest stopped running.
+#[derive(Debug)]
+pub enum ExitReason {
+    /// The guest called `exit` or `exit_group` with the given status.
+    Exit(i32),
+    /// The guest wrote an exit code to the HTIF `tohost` location.
+    Tohost(u64),
+    /// The guest was killed by an attached debugger.
+    Killed,
+    /// The guest powered off the machine with the given status.
+    Shutdown(i32),
+}
+
+impl ExitReason {
+    /// The status that the emulator should exit with.
+    pub const fn exit_code(&self) -> i32 {
+        match *self {
+            Self::Exit(status) => status,
+            Self::Tohost(code) => code as i32,
+            // Mimic a process that was killed by `SIGKILL`
+            Self::Killed => 128 + libc::SIGKILL,
+            Self::Shutdown(status) => status,
+        }
+    }
+}
+
+/// Whether a hart is running instructions.
+#[derive(Clone, Copy)]
+enum State {
+    Running,
+    /// Stalled until an interrupt becomes pending.
+    Waiting,
+    /// Stopped through the SBI until another hart starts it.
+    Stopped,
+}
 
+/// A single hart, which shares memory and devices with the other harts of the
+/// machine.
 pub struct Cpu {
-    opts: Opts,
+    opts: Rc<Opts>,
     zero: u64, // Never read from this
     registers: [u64; 31],
-    pc: *const u16,
-    old_pc: *const u16,
-    #[allow(dead_code)]
-    stack: Vec<u8>,
+    /// Single-precision values are NaN-boxed: stored with all upper bits set.
+    fregisters: [u64; 32],
+    pc: u64,
+    old_pc: u64,
+    /// Encoding of the instruction being run, for reporting illegal ones.
+    instruction_bits: u32,
+    shared: Rc<RefCell<Shared>>,
+    hart_id: usize,
+    state: State,
+    exit_reason: Option<ExitReason>,
+    privilege: Privilege,
+    csrs: CsrFile,
+    mmu: Mmu,
+    /// What the current instruction has done, if it's to be traced.
+    commit: Option<Commit>,
+    /// Set when a store overwrites instructions that have been decoded, which
+    /// may include the rest of the block being run.
+    code_written: bool,
+    /// The exception raised by compiled code, for the interpreter to take.
+    #[cfg(feature = "jit")]
+    jit_exception: Option<Exception>,
 }
 
 impl Cpu {
-    pub fn new(opts: Opts, pc: *const u16) -> Self {
-        let stack = Vec::<u8>::with_capacity(STACK_SIZE);
-        let mut registers: [u64; 31] = Default::default();
-        registers[1] = stack.as_ptr().wrapping_add(STACK_SIZE) as u64;
+    /// Creates the only hart of a Linux process, ready to jump to its entry
+    /// point with the given stack pointer.
+    pub fn with_process(
+        opts: Rc<Opts>,
+        shared: Rc<RefCell<Shared>>,
+        entry: u64,
+        sp: u64,
+    ) -> Self {
+        let mut cpu = Self::reset(opts, shared, 0, Instant::now(), entry);
+        cpu[RegisterName::X2] = sp;
+        cpu.privilege = Privilege::User;
+        // Linux lets new processes use floating point right away, and read
+        // the time and the other counters
+        cpu.csrs.mstatus |= MSTATUS_FS_INITIAL;
+        for csr in [csr::MCOUNTEREN, csr::SCOUNTEREN] {
+            cpu.csrs.write(csr, u64::MAX, Privilege::Machine);
+        }
+        cpu
+    }
+
+    /// Creates a hart of a machine, ready to jump into its firmware.
+    pub fn with_machine(
+        opts: Rc<Opts>,
+        shared: Rc<RefCell<Shared>>,
+        hart_id: usize,
+        epoch: Instant,
+        entry: u64,
+        dtb: u64,
+    ) -> Self {
+        let mut cpu = Self::reset(opts, shared, hart_id, epoch, entry);
+        // The hart ID and device tree address are passed to the firmware
+        cpu[RegisterName::A0] = hart_id as u64;
+        cpu[RegisterName::A1] = dtb;
+        if let Some(SbiImplementation::Builtin) = cpu.opts.sbi {
+            cpu.enter_sbi_payload();
+        }
+        cpu
+    }
+
+    /// Creates a hart whose real-time counter started counting at `epoch`,
+    /// in the state it is in when coming out of reset.
+    fn reset(
+        opts: Rc<Opts>,
+        shared: Rc<RefCell<Shared>>,
+        hart_id: usize,
+        epoch: Instant,
+        entry: u64,
+    ) -> Self {
+        let commit = opts.trace.is_some().then(Commit::new);
         Self {
             opts,
             zero: 0,
-            registers,
-            pc,
-            old_pc: pc,
-            stack,
+            registers: Default::default(),
+            fregisters: [0; 32],
+            pc: entry,
+            old_pc: entry,
+            instruction_bits: 0,
+            shared,
+            hart_id,
+            state: State::Running,
+            exit_reason: None,
+            privilege: Privilege::Machine,
+            csrs: CsrFile::new(epoch, hart_id as u64),
+            mmu: Mmu::new(),
+            commit,
+            code_written: false,
+            #[cfg(feature = "jit")]
+            jit_exception: None,
+        }
+    }
+
+    /// Runs a single instruction, returning the reason for exiting if the
+    /// guest wants to stop. Nothing happens if the hart isn't running.
+    ///
+    /// If the instruction raises an exception that the guest can't handle,
+    /// `pc` is left pointing at it.
+    pub fn step(&mut self) -> Result<Option<ExitReason>> {
+        if self.prepare_to_run() {
+            self.run_one()?;
+        }
+        Ok(self.exit_reason.take())
+    }
+
+    /// Like [`Cpu::step`], but runs a whole block of instructions, followed
+    /// by the blocks that it leads to, up to a limit.
+    pub fn run_blocks(&mut self) -> Result<Option<ExitReason>> {
+        if !self.prepare_to_run() {
+            return Ok(None);
+        }
+        let mut block = match self.block_at_pc() {
+            Ok(Some(block)) => block,
+            // Whatever keeps the block from being translated gets dealt with
+            // one instruction at a time
+            Ok(None) | Err(_) => {
+                self.run_one()?;
+                return Ok(self.exit_reason.take());
+            }
+        };
+        for _ in 0..MAX_CHAINED_BLOCKS {
+            if !self.run_block(&block)? || self.pending_interrupt().is_some() {
+                break;
+            }
+            match self.next_block(&block) {
+                Some(next) => block = next,
+                None => break,
+            }
+        }
+        Ok(self.exit_reason.take())
+    }
+
+    /// Handles SBI requests and interrupts, returning whether the hart gets
+    /// to run an instruction afterwards.
+    fn prepare_to_run(&mut self) -> bool {
+        if self.opts.sbi.is_some() {
+            self.handle_sbi_requests();
+        }
+        match self.state {
+            State::Running => {}
+            State::Waiting if self.csrs.mip & self.csrs.mie != 0 => {
+                self.state = State::Running;
+                if self.opts.sbi.is_some() {
+                    self.resume_from_suspend();
+                }
+            }
+            State::Waiting | State::Stopped => return false,
+        }
+        if let Some(interrupt) = self.pending_interrupt() {
+            // The interrupted instruction is run once the handler returns
+            self.enter_trap(interrupt.cause(), 0, self.pc);
+            return false;
         }
+        true
     }
 
-    pub unsafe fn run(&mut self) -> Result<()> {
-        loop {
-            unsafe { self.step() }?;
+    /// Runs the instruction at `pc`, trapping if it raises an exception.
+    fn run_one(&mut self) -> Result<()> {
+        match self.fetch_and_run() {
+            Ok(()) => {
+                self.csrs.instret = self.csrs.instret.wrapping_add(1);
+                self.log_commit()
+            }
+            Err(exception) => {
+                // Exceptions are precise, so the instruction has no effect
+                self.pc = self.old_pc;
+                self.take_trap(exception)
+            }
+        }
+    }
+
+    /// Runs the instructions of a block, returning whether the hart can go on
+    /// to the next block: none of them trapped, overwrote code or stopped the
+    /// hart.
+    fn run_block(&mut self, block: &Block) -> Result<bool> {
+        #[cfg(feature = "jit")]
+        if self.opts.jit {
+            if let Some(function) = self.native_code(block) {
+                return self.run_native(function);
+            }
+        }
+        self.code_written = false;
+        for op in block.ops() {
+            if let Err(exception) = self.run_op(op) {
+                self.take_trap(exception)?;
+                return Ok(false);
+            }
+            if self.code_written || self.exit_reason.is_some() {
+                return Ok(false);
+            }
         }
+        Ok(self.is_running())
     }
 
-    unsafe fn step(&mut self) -> Result<()> {
+    /// Runs an op as the instruction at `pc`. If it raises an exception,
+    /// `pc` is left pointing at it.
+    fn run_op(&mut self, op: &Op) -> Result<(), Exception> {
         self.old_pc = self.pc;
-        let low_half = unsafe { *self.pc };
-        let instruction = match Instruction::try_from(low_half) {
-            Ok(instruction) => {
-                self.pc = self.pc.wrapping_add(1);
-                instruction
+        self.instruction_bits = op.bits;
+        self.pc = self.pc.wrapping_add(op.len());
+        if let Err(exception) = op.run(self) {
+            self.pc = self.old_pc;
+            return Err(exception);
+        }
+        self.csrs.instret = self.csrs.instret.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Finds the block that starts at `pc`, translating it if it isn't
+    /// cached. Returns `None` if the first instruction can't be translated.
+    fn block_at_pc(&mut self) -> Result<Option<Rc<Block>>, Exception> {
+        if !self.pc.is_multiple_of(2) {
+            return Err(Exception::InstructionAddressMisaligned(self.pc));
+        }
+        let physical = self.translate(self.pc, Access::Fetch)?;
+        let mut shared = self.shared.borrow_mut();
+        if let Some(block) = shared.cached_block(physical) {
+            return Ok(Some(block));
+        }
+        let memory = &shared.memory;
+        let Some(block) =
+            Block::translate(|address| memory.read_u16(address).ok(), physical)
+        else {
+            return Ok(None);
+        };
+        let block = Rc::new(block);
+        shared.cache_block(physical, Rc::clone(&block));
+        Ok(Some(block))
+    }
+
+    /// Finds the block to run after `block`, linking the two so that the
+    /// next lookup is quicker.
+    fn next_block(&mut self, block: &Block) -> Option<Rc<Block>> {
+        let context = block::Context {
+            pc: self.pc,
+            privilege: self.privilege,
+            mmu_generation: self.mmu.generation(),
+        };
+        // Blocks on pages that have been written to are only dropped once
+        // they're looked up
+        if !self.shared.borrow().memory.has_written_code_pages() {
+            if let Some(next) = block.successor(context) {
+                return Some(next);
             }
-            Err(Ok(NeedMoreBytes)) => {
-                let high_half = unsafe { *self.pc.wrapping_add(1) };
-                let raw_instruction =
-                    u32::from(high_half) << 16 | u32::from(low_half);
-                self.pc = self.pc.wrapping_add(2);
-                Instruction::try_from(raw_instruction)?
+        }
+        let next = self.block_at_pc().ok()??;
+        block.link(context, &next);
+        Some(next)
+    }
+
+    /// Writes the instruction that just retired to the trace, if there is
+    /// one.
+    fn log_commit(&mut self) -> Result<()> {
+        let Some(destination) =
+            self.commit.as_ref().map(|commit| commit.destination)
+        else {
+            return Ok(());
+        };
+        let result = destination.map(|register| match register {
+            Register::X(reg) => (register, self[reg]),
+            Register::F(reg) => (register, self.fregisters[usize::from(reg)]),
+            Register::Csr(_) => unreachable!("results never go to CSRs"),
+        });
+        let commit = self.commit.as_mut().unwrap();
+        commit.writes.extend(result);
+        self.shared
+            .borrow_mut()
+            .trace
+            .as_mut()
+            .expect("tracing harts share a trace")
+            .log(self.hart_id, commit)
+            .map_err(Error::Trace)
+    }
+
+    pub const fn is_running(&self) -> bool {
+        matches!(self.state, State::Running)
+    }
+
+    /// Delivers an exception to the guest's trap handler, or handles it on
+    /// the host if there is none.
+    fn take_trap(&mut self, exception: Exception) -> Result<()> {
+        if self.trap_vector(exception.cause()) != 0 {
+            self.enter_trap(exception.cause(), exception.tval(), self.old_pc);
+            Ok(())
+        } else if exception.is_environment_call()
+            && self.shared.borrow().process.is_some()
+        {
+            self.syscall();
+            self.pc = self.old_pc.wrapping_add(4);
+            Ok(())
+        } else {
+            Err(Error::UnhandledTrap {
+                exception,
+                pc: self.old_pc,
+            })
+        }
+    }
+
+    /// The address of the guest's handler for a trap, or 0 if there is none.
+    const fn trap_vector(&self, cause: u64) -> u64 {
+        match self.trap_target(cause) {
+            Privilege::Supervisor => self.csrs.stvec,
+            _ => self.csrs.mtvec,
+        }
+    }
+
+    /// Whether misaligned accesses are split into byte accesses. They only
+    /// raise exceptions when a guest handler can take them.
+    fn splits_misaligned(&self, exception: &Exception) -> bool {
+        self.shared.borrow().process.is_some()
+            || self.trap_vector(exception.cause()) == 0
+    }
+
+    /// Transfers control to the trap handler for the given cause.
+    fn enter_trap(&mut self, cause: u64, tval: u64, epc: u64) {
+        let target = self.trap_target(cause);
+        self.pc =
+            self.csrs
+                .enter_trap(target, self.privilege, cause, tval, epc);
+        self.privilege = target;
+    }
+
+    /// Picks the privilege level that handles a trap, based on whether it
+    /// has been delegated to S-mode. Traps never go to a lower privilege
+    /// level than the current one.
+    const fn trap_target(&self, cause: u64) -> Privilege {
+        let delegated = if cause >> 63 == 1 {
+            self.csrs.mideleg
+        } else {
+            self.csrs.medeleg
+        };
+        if (self.privilege as u8) <= Privilege::Supervisor as u8
+            && delegated >> (cause & 0x3f) & 1 == 1
+        {
+            Privilege::Supervisor
+        } else {
+            Privilege::Machine
+        }
+    }
+
+    /// Finds the highest priority interrupt that is both pending and
+    /// enabled.
+    fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.csrs.mip & self.csrs.mie;
+        if pending == 0 {
+            return None;
+        }
+        // Interrupts for a more privileged mode are always enabled, and
+        // those for a less privileged mode never are
+        let enabled = |target: Privilege, enable_bit: u64| {
+            self.privilege < target
+                || self.privilege == target
+                    && self.csrs.mstatus & enable_bit != 0
+        };
+        let machine_enabled = enabled(Privilege::Machine, MSTATUS_MIE);
+        let supervisor_enabled = enabled(Privilege::Supervisor, MSTATUS_SIE);
+        Interrupt::ALL.into_iter().find(|interrupt| {
+            pending & interrupt.bit() != 0
+                && if self.csrs.mideleg & interrupt.bit() == 0 {
+                    machine_enabled
+                } else {
+                    supervisor_enabled
+                }
+        })
+    }
+
+    /// Emulates a Linux system call on behalf of the guest.
+    fn syscall(&mut self) {
+        let number = self[RegisterName::A7];
+        let args = [
+            RegisterName::A0,
+            RegisterName::A1,
+            RegisterName::A2,
+            RegisterName::A3,
+            RegisterName::A4,
+            RegisterName::A5,
+        ]
+        .map(|reg| self[reg]);
+        let mut shared = self.shared.borrow_mut();
+        let Shared {
+            process, memory, ..
+        } = &mut *shared;
+        let result = process
+            .as_mut()
+            .expect("system calls are only emulated for processes")
+            .syscall(memory, number, args);
+        drop(shared);
+        match result {
+            ControlFlow::Continue(ret) => {
+                if self.opts.verbose {
+                    eprintln!("Syscall {number} returned {}", ret as i64);
+                }
+                self[RegisterName::A0] = ret;
+            }
+            ControlFlow::Break(exit_reason) => {
+                self.exit_reason = Some(exit_reason);
+            }
+        }
+    }
+
+    /// Loads bytes from virtual memory.
+    fn load<const N: usize>(
+        &mut self,
+        address: u64,
+    ) -> Result<[u8; N], Exception> {
+        let bytes = if address.is_multiple_of(N as u64) {
+            self.load_aligned(address)?
+        } else {
+            let exception = Exception::LoadAddressMisaligned(address);
+            if !self.splits_misaligned(&exception) {
+                return Err(exception);
             }
-            Err(Err(err)) => return Err(err),
+            let mut bytes = [0; N];
+            for (offset, byte) in (0..).zip(&mut bytes) {
+                [*byte] = self.load_aligned(address.wrapping_add(offset))?;
+            }
+            bytes
         };
-        unsafe {
-            self.run_instruction(instruction);
+        if let Some(commit) = &mut self.commit {
+            commit.loads.push(address);
+        }
+        Ok(bytes)
+    }
+
+    /// Loads naturally aligned bytes from virtual memory.
+    fn load_aligned<const N: usize>(
+        &mut self,
+        address: u64,
+    ) -> Result<[u8; N], Exception> {
+        let physical = self.translate(address, Access::Load)?;
+        let mut shared = self.shared.borrow_mut();
+        if let Some((device, offset)) = shared
+            .virt
+            .as_mut()
+            .and_then(|virt| virt.device_at(physical))
+        {
+            let value = device
+                .read(offset, N)
+                .ok_or(Exception::LoadAccessFault(address))?;
+            drop(shared);
+            // Reads can have side effects, like claiming an interrupt
+            self.update_interrupts();
+            return Ok(value.to_le_bytes()[..N].try_into().unwrap());
+        }
+        shared
+            .memory
+            .slice(physical, N)
+            .map(|bytes| bytes.try_into().unwrap())
+            .map_err(|_| Exception::LoadAccessFault(address))
+    }
+
+    /// Stores bytes to virtual memory.
+    fn store<const N: usize>(
+        &mut self,
+        address: u64,
+        bytes: [u8; N],
+    ) -> Result<(), Exception> {
+        if address.is_multiple_of(N as u64) {
+            self.store_aligned(address, bytes)?;
+        } else {
+            let exception = Exception::StoreAddressMisaligned(address);
+            if !self.splits_misaligned(&exception) {
+                return Err(exception);
+            }
+            // Nothing gets written unless both pages can be
+            self.translate(address.wrapping_add(N as u64 - 1), Access::Store)?;
+            for (offset, byte) in (0..).zip(bytes) {
+                self.store_aligned(address.wrapping_add(offset), [byte])?;
+            }
+        }
+        if let Some(commit) = &mut self.commit {
+            let mut value = [0; 8];
+            value[..N].copy_from_slice(&bytes);
+            commit.stores.push(Store {
+                address,
+                value: u64::from_le_bytes(value),
+                size: N,
+            });
+        }
+        Ok(())
+    }
+
+    /// Stores naturally aligned bytes to virtual memory.
+    fn store_aligned<const N: usize>(
+        &mut self,
+        address: u64,
+        bytes: [u8; N],
+    ) -> Result<(), Exception> {
+        let physical = self.translate(address, Access::Store)?;
+        let mut value = [0; 8];
+        value[..N].copy_from_slice(&bytes);
+        let value = u64::from_le_bytes(value);
+        let mut shared = self.shared.borrow_mut();
+        if let Some(virt) = &mut shared.virt {
+            if let Some((device, offset)) = virt.device_at(physical) {
+                device
+                    .write(offset, N, value)
+                    .ok_or(Exception::StoreAccessFault(address))?;
+                if let Some(status) = virt.take_shutdown() {
+                    self.exit_reason = Some(ExitReason::Shutdown(status));
+                }
+                drop(shared);
+                self.update_interrupts();
+                return Ok(());
+            }
+        }
+        shared
+            .memory
+            .write_bytes(physical, &bytes)
+            .map_err(|_| Exception::StoreAccessFault(address))?;
+        shared.invalidate_reservations(physical);
+        self.code_written |= shared.memory.has_written_code_pages();
+        let is_tohost = shared.tohost == Some(physical);
+        drop(shared);
+        if is_tohost {
+            self.handle_tohost(physical)
+                .map_err(|_| Exception::StoreAccessFault(address))?;
         }
         Ok(())
     }
 
-    unsafe fn run_instruction(&mut self, instruction: Instruction) {
-        if self.opts.verbose {
-            eprintln!("Running: {instruction:?}");
+    /// Loads a sign-extended word or a doubleword from virtual memory.
+    fn load_atomic(
+        &mut self,
+        width: AmoWidth,
+        address: u64,
+    ) -> Result<u64, Exception> {
+        Ok(match width {
+            AmoWidth::Word => {
+                u32::from_le_bytes(self.load(address)?).sign_extend()
+            }
+            AmoWidth::Double => u64::from_le_bytes(self.load(address)?),
+        })
+    }
+
+    /// Stores a word or a doubleword to virtual memory.
+    fn store_atomic(
+        &mut self,
+        width: AmoWidth,
+        address: u64,
+        value: u64,
+    ) -> Result<(), Exception> {
+        match width {
+            AmoWidth::Word => self.store(address, (value as u32).to_le_bytes()),
+            AmoWidth::Double => self.store(address, value.to_le_bytes()),
+        }
+    }
+
+    /// Replaces a value in virtual memory with the result of `update`,
+    /// returning the old value. Exceptions are reported as if the access was
+    /// a store.
+    fn atomic_update(
+        &mut self,
+        width: AmoWidth,
+        address: u64,
+        update: impl FnOnce(u64) -> u64,
+    ) -> Result<u64, Exception> {
+        if !address.is_multiple_of(width.size()) {
+            return Err(Exception::StoreAddressMisaligned(address));
+        }
+        // Writable pages are always readable too
+        self.translate(address, Access::Store)?;
+        let old = self
+            .load_atomic(width, address)
+            .map_err(|_| Exception::StoreAccessFault(address))?;
+        self.store_atomic(width, address, update(old))?;
+        Ok(old)
+    }
+
+    /// Updates `mip` to reflect the interrupt lines of the machine's devices.
+    pub fn update_interrupts(&mut self) {
+        if let Some(virt) = &mut self.shared.borrow_mut().virt {
+            self.csrs.mip = self.csrs.mip & !virt::DEVICE_INTERRUPTS
+                | virt.update(self.hart_id);
+            // The built-in SBI implementation passes timer interrupts on to
+            // S-mode, where they stay pending until the timer is set again
+            let timer_fired =
+                self.csrs.mip & Interrupt::MachineTimer.bit() != 0;
+            if self.opts.sbi.is_some() && timer_fired {
+                self.csrs.mip |= Interrupt::SupervisorTimer.bit();
+            }
+        }
+    }
+
+    /// Stalls the hart until an interrupt becomes pending.
+    ///
+    /// Without a machine, nothing can raise an interrupt while the hart is
+    /// stalled, so waiting is the same as doing nothing.
+    fn wait_for_interrupt(&mut self) {
+        if self.shared.borrow().virt.is_some() {
+            self.state = State::Waiting;
+        }
+    }
+
+    fn virt_mut(&self) -> RefMut<'_, Virt> {
+        RefMut::map(self.shared.borrow_mut(), |shared| {
+            shared
+                .virt
+                .as_mut()
+                .expect("SBI calls are only handled on machines")
+        })
+    }
+
+    /// Translates a virtual address for the given kind of access.
+    fn translate(
+        &mut self,
+        address: u64,
+        access: Access,
+    ) -> Result<u64, Exception> {
+        let mstatus = self.csrs.mstatus;
+        // `mstatus.MPRV` makes loads and stores act as if they were done in
+        // the mode saved in `mstatus.MPP`
+        let privilege = if access != Access::Fetch
+            && self.privilege == Privilege::Machine
+            && mstatus & MSTATUS_MPRV != 0
+        {
+            self.csrs.mpp()
+        } else {
+            self.privilege
+        };
+        let context = mmu::Context {
+            satp: self.csrs.satp,
+            privilege,
+            sum: mstatus & MSTATUS_SUM != 0,
+            mxr: mstatus & MSTATUS_MXR != 0,
+        };
+        self.mmu.translate(
+            &mut self.shared.borrow_mut().memory,
+            &context,
+            address,
+            access,
+        )
+    }
+
+    /// Translates an address for the debugger, the way that a load or store
+    /// by the hart would, or returns `None` if that access would fault.
+    pub fn translate_for_debugger(
+        &mut self,
+        address: u64,
+        access: Access,
+    ) -> Option<u64> {
+        self.translate(address, access).ok()
+    }
+
+    pub const fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u64) {
+        self.pc = pc;
+    }
+
+    fn fetch_and_run(&mut self) -> Result<(), Exception> {
+        self.old_pc = self.pc;
+        if !self.pc.is_multiple_of(2) {
+            return Err(Exception::InstructionAddressMisaligned(self.pc));
+        }
+        let physical = self.translate(self.pc, Access::Fetch)?;
+        let cached = self.shared.borrow_mut().cached_instruction(physical);
+        let Decoded { instruction, bits } = match cached {
+            Some(decoded) => decoded,
+            None => {
+                let decoded = self.fetch_and_decode(physical)?;
+                self.shared
+                    .borrow_mut()
+                    .cache_instruction(physical, decoded);
+                decoded
+            }
+        };
+        self.instruction_bits = bits;
+        self.pc = self
+            .pc
+            .wrapping_add(if bits & 0b11 == 0b11 { 4 } else { 2 });
+        if self.opts.verbose || self.commit.is_some() {
+            // Printing and tracing share the one disassembly
+            let disassembly =
+                Disassembly::new(&instruction, self.old_pc, bits).to_string();
+            if self.opts.verbose {
+                if self.opts.harts > 1 {
+                    eprint!("hart {}: ", self.hart_id);
+                }
+                eprintln!(
+                    "{:016x}: {}  {disassembly}",
+                    self.old_pc,
+                    Encoding(bits)
+                );
+            }
+            if let Some(commit) = &mut self.commit {
+                commit.start(
+                    self.privilege,
+                    self.old_pc,
+                    bits,
+                    disassembly,
+                    &instruction,
+                );
+            }
         }
+        Op::new(Decoded { instruction, bits }).run(self)
+    }
+
+    /// Reads and decodes the instruction at `pc`, whose first half is at
+    /// the given physical address.
+    fn fetch_and_decode(
+        &mut self,
+        physical: u64,
+    ) -> Result<Decoded, Exception> {
+        let read = |cpu: &Self, physical, address| {
+            cpu.shared
+                .borrow()
+                .memory
+                .read_u16(physical)
+                .map_err(|_| Exception::InstructionAccessFault(address))
+        };
+        let low_half = read(self, physical, self.pc)?;
+        match Instruction::try_from(low_half) {
+            Ok(instruction) => Ok(Decoded {
+                instruction,
+                bits: low_half.into(),
+            }),
+            Err(Ok(NeedMoreBytes)) => {
+                // The two halves of an instruction may be on different pages
+                let address = self.pc.wrapping_add(2);
+                let physical = self.translate(address, Access::Fetch)?;
+                let high_half = read(self, physical, address)?;
+                let bits = u32::from(high_half) << 16 | u32::from(low_half);
+                Instruction::try_from(bits)
+                    .map(|instruction| Decoded { instruction, bits })
+                    .map_err(|_| Exception::IllegalInstruction(bits))
+            }
+            Err(Err(_)) => Err(Exception::IllegalInstruction(low_half.into())),
+        }
+    }
+
+    fn run_instruction(
+        &mut self,
+        instruction: Instruction,
+    ) -> Result<(), Exception> {
         match instruction {
-            Instruction::R {
+            // These are run by handlers of their own
+            Instruction::R { .. }
+            | Instruction::I { .. }
+            | Instruction::S { .. }
+            | Instruction::B { .. }
+            | Instruction::U { .. }
+            | Instruction::Jal { .. } => {
+                unreachable!("instruction has a handler")
+            }
+            Instruction::FLoad {
+                precision,
+                rd,
+                rs1,
+                imm,
+            } => {
+                self.require_fp()?;
+                let address = self[rs1]
+                    .wrapping_add_signed(sign_extend_12bit(imm).into());
+                let value = match precision {
+                    Precision::Single => {
+                        u32::from_le_bytes(self.load(address)?).into()
+                    }
+                    Precision::Double => {
+                        u64::from_le_bytes(self.load(address)?)
+                    }
+                };
+                self.write_fp(rd, precision, value);
+            }
+            Instruction::FStore {
+                precision,
+                rs2,
+                rs1,
+                imm,
+            } => {
+                self.require_fp()?;
+                let address = self[rs1].wrapping_add_signed(
+                    sign_extend_12bit(u32::from(imm)).into(),
+                );
+                // The raw bits are stored, whether NaN-boxed or not
+                let value = self.fregisters[usize::from(rs2)];
+                match precision {
+                    Precision::Single => {
+                        self.store(address, (value as u32).to_le_bytes())?;
+                    }
+                    Precision::Double => {
+                        self.store(address, value.to_le_bytes())?;
+                    }
+                }
+            }
+            Instruction::FArith {
                 funct,
+                precision,
+                rd,
+                rs1,
                 rs2,
+                rm,
+            } => {
+                let mut env = self.fp_env(rm)?;
+                let operation = match funct {
+                    FArithFunct::Add => float::add,
+                    FArithFunct::Sub => float::sub,
+                    FArithFunct::Mul => float::mul,
+                    FArithFunct::Div => float::div,
+                };
+                let result = operation(
+                    precision,
+                    self.read_fp(rs1, precision),
+                    self.read_fp(rs2, precision),
+                    &mut env,
+                );
+                self.write_fp(rd, precision, result);
+                self.csrs.accrue_fp_exceptions(env.flags);
+            }
+            Instruction::FSqrt {
+                precision,
+                rd,
+                rs1,
+                rm,
+            } => {
+                let mut env = self.fp_env(rm)?;
+                let result = float::sqrt(
+                    precision,
+                    self.read_fp(rs1, precision),
+                    &mut env,
+                );
+                self.write_fp(rd, precision, result);
+                self.csrs.accrue_fp_exceptions(env.flags);
+            }
+            Instruction::FSign {
+                funct,
+                precision,
+                rd,
                 rs1,
+                rs2,
+            } => {
+                self.require_fp()?;
+                let a = self.read_fp(rs1, precision);
+                let b = self.read_fp(rs2, precision);
+                let sign_bit = precision.sign_bit();
+                let sign = match funct {
+                    FSignFunct::Sgnj => b,
+                    FSignFunct::Sgnjn => !b,
+                    FSignFunct::Sgnjx => a ^ b,
+                } & sign_bit;
+                self.write_fp(rd, precision, a & !sign_bit | sign);
+            }
+            Instruction::FMinMax {
+                funct,
+                precision,
                 rd,
+                rs1,
+                rs2,
             } => {
-                let rs1 = self[rs1];
-                let rs2 = self[rs2];
-                self[rd] = match funct {
-                    RFunct::Add => (rs1 as i64).wrapping_add(rs2 as i64) as u64,
-                    RFunct::Sub => (rs1 as i64).wrapping_sub(rs2 as i64) as u64,
-                    RFunct::Sll => rs1.wrapping_shl(rs2 as u32),
-                    RFunct::Slt => u64::from((rs1 as i64) < rs2 as i64),
-                    RFunct::Sltu => u64::from(rs1 < rs2),
-                    RFunct::Xor => rs1 ^ rs2,
-                    RFunct::Srl => rs1.wrapping_shr(rs2 as u32),
-                    RFunct::Sra => (rs1 as i64).wrapping_shr(rs2 as u32) as u64,
-                    RFunct::Or => rs1 | rs2,
-                    RFunct::And => rs1 & rs2,
-                }
+                self.require_fp()?;
+                let mut env = float::Env::default();
+                let result = float::min_max(
+                    precision,
+                    self.read_fp(rs1, precision),
+                    self.read_fp(rs2, precision),
+                    matches!(funct, FMinMaxFunct::Max),
+                    &mut env,
+                );
+                self.write_fp(rd, precision, result);
+                self.csrs.accrue_fp_exceptions(env.flags);
             }
-            Instruction::I {
-                imm,
+            Instruction::FFma {
+                funct,
+                precision,
+                rd,
                 rs1,
+                rs2,
+                rs3,
+                rm,
+            } => {
+                let mut env = self.fp_env(rm)?;
+                let (negate_product, negate_addend) = match funct {
+                    FFmaFunct::Madd => (false, false),
+                    FFmaFunct::Msub => (false, true),
+                    FFmaFunct::Nmsub => (true, false),
+                    FFmaFunct::Nmadd => (true, true),
+                };
+                let result = float::fused_multiply_add(
+                    precision,
+                    [rs1, rs2, rs3].map(|reg| self.read_fp(reg, precision)),
+                    negate_product,
+                    negate_addend,
+                    &mut env,
+                );
+                self.write_fp(rd, precision, result);
+                self.csrs.accrue_fp_exceptions(env.flags);
+            }
+            Instruction::FCompare {
                 funct,
+                precision,
                 rd,
+                rs1,
+                rs2,
             } => {
-                let imm_i32 = sign_extend_12bit(imm);
-                let rs1 = self[rs1];
-                match funct {
-                    IFunct::Addi => {
-                        self[rd] = rs1.wrapping_add_signed(imm_i32.into());
-                    }
-                    IFunct::Slti => {
-                        self[rd] = u64::from((rs1 as i64) < i64::from(imm_i32));
-                    }
-                    IFunct::Sltiu => {
-                        self[rd] = u64::from(rs1 < imm_i32.sign_extend());
-                    }
-                    IFunct::Xori => self[rd] = rs1 ^ imm_i32.sign_extend(),
-                    IFunct::Ori => self[rd] = rs1 | imm_i32.sign_extend(),
-                    IFunct::Andi => self[rd] = rs1 & imm_i32.sign_extend(),
-                    IFunct::Slli => self[rd] = rs1.wrapping_shl(imm_i32 as u32),
-                    IFunct::Srli => self[rd] = rs1.wrapping_shr(imm_i32 as u32),
-                    IFunct::Srai => {
-                        self[rd] =
-                            (rs1 as i64).wrapping_shr(imm_i32 as u32) as u64;
-                    }
-                    IFunct::Lb => {
-                        self[rd] = i64::from(unsafe {
-                            *(rs1.wrapping_add_signed(i64::from(imm_i32))
-                                as *const i8)
-                        }) as u64;
-                    }
-                    IFunct::Lh => {
-                        self[rd] = i64::from(unsafe {
-                            *(rs1.wrapping_add_signed(i64::from(imm_i32))
-                                as *const i16)
-                        }) as u64;
-                    }
-                    IFunct::Lw => {
-                        self[rd] = i64::from(unsafe {
-                            *(rs1.wrapping_add_signed(i64::from(imm_i32))
-                                as *const i32)
-                        }) as u64;
-                    }
-                    IFunct::Ld => {
-                        self[rd] = unsafe {
-                            *(rs1.wrapping_add_signed(i64::from(imm_i32))
-                                as *const u64)
-                        };
-                    }
-                    IFunct::Lbu => {
-                        self[rd] = u64::from(unsafe {
-                            *(rs1.wrapping_add_signed(i64::from(imm_i32))
-                                as *const u8)
-                        });
-                    }
-                    IFunct::Lhu => {
-                        self[rd] = u64::from(unsafe {
-                            *(rs1.wrapping_add_signed(i64::from(imm_i32))
-                                as *const u16)
-                        });
-                    }
-                    IFunct::Jalr => {
-                        self[rd] = self.pc as u64;
-                        self.pc = rs1.wrapping_add_signed(i64::from(imm_i32))
-                            as *const u16;
+                self.require_fp()?;
+                let mut env = float::Env::default();
+                // Only equality comparisons are quiet
+                let ordering = float::compare(
+                    precision,
+                    self.read_fp(rs1, precision),
+                    self.read_fp(rs2, precision),
+                    matches!(funct, FCompareFunct::Eq),
+                    &mut env,
+                );
+                self[rd] = u64::from(match funct {
+                    FCompareFunct::Eq => ordering == Some(Ordering::Equal),
+                    FCompareFunct::Lt => ordering == Some(Ordering::Less),
+                    FCompareFunct::Le => {
+                        matches!(
+                            ordering,
+                            Some(Ordering::Less | Ordering::Equal)
+                        )
                     }
+                });
+                self.csrs.accrue_fp_exceptions(env.flags);
+            }
+            Instruction::FClass { precision, rd, rs1 } => {
+                self.require_fp()?;
+                self[rd] =
+                    float::classify(precision, self.read_fp(rs1, precision));
+            }
+            Instruction::FMvToInt { precision, rd, rs1 } => {
+                self.require_fp()?;
+                // The raw bits are moved, whether NaN-boxed or not
+                let value = self.fregisters[usize::from(rs1)];
+                self[rd] = match precision {
+                    Precision::Single => (value as u32).sign_extend(),
+                    Precision::Double => value,
+                };
+            }
+            Instruction::FMvFromInt { precision, rd, rs1 } => {
+                self.require_fp()?;
+                let value = match precision {
+                    Precision::Single => self[rs1] & 0xffff_ffff,
+                    Precision::Double => self[rs1],
+                };
+                self.write_fp(rd, precision, value);
+            }
+            Instruction::FCvtToInt {
+                precision,
+                int,
+                rd,
+                rs1,
+                rm,
+            } => {
+                let mut env = self.fp_env(rm)?;
+                self[rd] = float::to_int(
+                    precision,
+                    self.read_fp(rs1, precision),
+                    int,
+                    &mut env,
+                );
+                self.csrs.accrue_fp_exceptions(env.flags);
+            }
+            Instruction::FCvtFromInt {
+                precision,
+                int,
+                rd,
+                rs1,
+                rm,
+            } => {
+                let mut env = self.fp_env(rm)?;
+                let result =
+                    float::from_int(precision, self[rs1], int, &mut env);
+                self.write_fp(rd, precision, result);
+                self.csrs.accrue_fp_exceptions(env.flags);
+            }
+            Instruction::FCvtFloat {
+                precision,
+                rd,
+                rs1,
+                rm,
+            } => {
+                let mut env = self.fp_env(rm)?;
+                let from = match precision {
+                    Precision::Single => Precision::Double,
+                    Precision::Double => Precision::Single,
+                };
+                let result = float::convert(
+                    from,
+                    precision,
+                    self.read_fp(rs1, from),
+                    &mut env,
+                );
+                self.write_fp(rd, precision, result);
+                self.csrs.accrue_fp_exceptions(env.flags);
+            }
+            // Memory is accessed one instruction at a time in program order,
+            // which is as strong as any ordering that the `aq` and `rl` bits
+            // or fences can ask for
+            Instruction::LoadReserved { width, rd, rs1, .. } => {
+                let address = self[rs1];
+                if !address.is_multiple_of(width.size()) {
+                    return Err(Exception::LoadAddressMisaligned(address));
                 }
+                self[rd] = self.load_atomic(width, address)?;
+                let physical = self.translate(address, Access::Load)?;
+                self.shared.borrow_mut().reserve(self.hart_id, physical);
             }
-            Instruction::S {
-                imm,
-                rs2,
+            Instruction::StoreConditional {
+                width,
+                rd,
                 rs1,
-                funct,
+                rs2,
+                ..
             } => {
-                let dest = self[rs1].wrapping_add_signed(i64::from(
-                    sign_extend_12bit(u32::from(imm)),
-                ));
-                match funct {
-                    SFunct::Sb => unsafe {
-                        *(dest as *mut u8) = self[rs2] as _;
-                    },
-                    SFunct::Sh => unsafe {
-                        *(dest as *mut u16) = self[rs2] as _;
-                    },
-                    SFunct::Sw => unsafe {
-                        *(dest as *mut u32) = self[rs2] as _;
-                    },
-                    SFunct::Sd => unsafe {
-                        *(dest as *mut u64) = self[rs2];
-                    },
+                let address = self[rs1];
+                // Every `sc` gives up the reservation, even if it fails
+                let reservation =
+                    self.shared.borrow_mut().take_reservation(self.hart_id);
+                if !address.is_multiple_of(width.size()) {
+                    return Err(Exception::StoreAddressMisaligned(address));
                 }
+                let physical = self.translate(address, Access::Store)?;
+                let success = reservation == Some(physical);
+                if success {
+                    self.store_atomic(width, address, self[rs2])?;
+                }
+                self[rd] = u64::from(!success);
             }
-            Instruction::B {
-                imm,
+            Instruction::Amo {
+                funct,
+                width,
+                rd,
+                rs1,
                 rs2,
+                ..
+            } => {
+                let src = match width {
+                    AmoWidth::Word => (self[rs2] as u32).sign_extend(),
+                    AmoWidth::Double => self[rs2],
+                };
+                self[rd] = self.atomic_update(width, self[rs1], |old| {
+                    amo_result(&funct, old, src)
+                })?;
+            }
+            // Memory is always accessed in program order, so the ordering
+            // bits of fences and atomics can be ignored
+            Instruction::Fence { .. } => {}
+            // Stores already drop the decoded instructions and blocks that
+            // they overwrite, but everything is thrown away regardless
+            Instruction::FenceI => {
+                self.shared.borrow_mut().flush_code_caches();
+            }
+            Instruction::Csr {
+                funct,
+                rd,
                 rs1,
+                csr,
+            } => {
+                // Setting or clearing no bits doesn't count as a write
+                let writes =
+                    matches!(funct, CsrFunct::Csrrw) || rs1 != RegisterName::X0;
+                self.run_csr_instruction(&funct, rd, csr, self[rs1], writes)?;
+            }
+            Instruction::CsrImm {
                 funct,
+                rd,
+                uimm,
+                csr,
             } => {
-                let rs1 = self[rs1];
-                let rs2 = self[rs2];
-                let branch_condition = match funct {
-                    BFunct::Beq => rs1 == rs2,
-                    BFunct::Bne => rs1 != rs2,
-                    BFunct::Blt => (rs1 as i64) < rs2 as i64,
-                    BFunct::Bge => rs1 as i64 >= rs2 as i64,
-                    BFunct::Bltu => rs1 < rs2,
-                    BFunct::Bgeu => rs1 >= rs2,
-                };
-                if branch_condition {
-                    self.pc = self
-                        .old_pc
-                        .cast::<u8>() // For single byte offsets
-                        .wrapping_offset(isize::from(imm))
-                        .cast();
+                let writes = matches!(funct, CsrFunct::Csrrw) || uimm != 0;
+                self.run_csr_instruction(&funct, rd, csr, uimm.into(), writes)?;
+            }
+            Instruction::Ecall => {
+                if self.privilege == Privilege::Supervisor
+                    && self.opts.sbi.is_some()
+                {
+                    self.sbi_call();
+                    return Ok(());
                 }
+                return Err(match self.privilege {
+                    Privilege::User => Exception::EnvironmentCallFromUMode,
+                    Privilege::Supervisor => {
+                        Exception::EnvironmentCallFromSMode
+                    }
+                    Privilege::Machine => Exception::EnvironmentCallFromMMode,
+                });
+            }
+            Instruction::Ebreak => {
+                return Err(Exception::Breakpoint(self.old_pc));
             }
-            Instruction::U { imm, rd, opcode } => match opcode {
-                UOpcode::Lui => self[rd] = imm.sign_extend(),
-                UOpcode::Auipc => {
-                    self[rd] = (self.old_pc as u64)
-                        .wrapping_add_signed(i64::from(imm));
+            Instruction::Sret => {
+                if self.privilege < Privilege::Supervisor
+                    || self.privilege == Privilege::Supervisor
+                        && self.csrs.mstatus & MSTATUS_TSR != 0
+                {
+                    return Err(self.illegal_instruction());
                 }
-            },
-            Instruction::Jal { imm, rd } => {
-                self[rd] = self.pc as u64;
-                self.pc = self
-                    .old_pc
-                    .cast::<u8>() // For single byte offsets
-                    .wrapping_offset(imm as isize)
-                    .cast();
+                (self.pc, self.privilege) = self.csrs.sret();
             }
-            Instruction::Ecall => {
-                self.registers[9] = unsafe {
-                    libc::syscall(
-                        self.registers[16] as i64,
-                        self.registers[9] as i64,
-                        self.registers[10] as i64,
-                        self.registers[11] as i64,
-                        self.registers[12] as i64,
-                        self.registers[13] as i64,
-                        self.registers[14] as i64,
-                    )
-                } as u64;
+            Instruction::Mret => {
+                if self.privilege < Privilege::Machine {
+                    return Err(self.illegal_instruction());
+                }
+                (self.pc, self.privilege) = self.csrs.mret();
+            }
+            Instruction::SfenceVma { rs1, .. } => {
+                if self.privilege < Privilege::Supervisor
+                    || self.privilege == Privilege::Supervisor
+                        && self.csrs.mstatus & MSTATUS_TVM != 0
+                {
+                    return Err(self.illegal_instruction());
+                }
+                // Address space identifiers aren't tracked, so the ASID in
+                // `rs2` is ignored
+                if rs1 == RegisterName::X0 {
+                    self.mmu.flush();
+                } else {
+                    self.mmu.flush_page(self[rs1]);
+                }
+            }
+            Instruction::Wfi => {
+                if self.privilege < Privilege::Machine
+                    && self.csrs.mstatus & MSTATUS_TW != 0
+                {
+                    return Err(self.illegal_instruction());
+                }
+                // Stall instead of spinning in the guest's idle loop
+                self.wait_for_interrupt();
+            }
+        }
+        Ok(())
+    }
+
+    /// Atomically reads a CSR into `rd` and updates it based on `operand`.
+    fn run_csr_instruction(
+        &mut self,
+        funct: &CsrFunct,
+        rd: RegisterName,
+        csr: u16,
+        operand: u64,
+        writes: bool,
+    ) -> Result<(), Exception> {
+        let illegal = self.illegal_instruction();
+        let old = self.csrs.read(csr, self.privilege).ok_or(illegal)?;
+        if writes {
+            let new = match funct {
+                CsrFunct::Csrrw => operand,
+                CsrFunct::Csrrs => old | operand,
+                CsrFunct::Csrrc => old & !operand,
+            };
+            self.csrs.write(csr, new, self.privilege).ok_or(illegal)?;
+            if let Some(commit) = &mut self.commit {
+                let value = self.csrs.read(csr, self.privilege).unwrap_or(new);
+                commit.writes.push((Register::Csr(csr), value));
+            }
+            // Entries in the TLB aren't tagged with their address space
+            if csr == csr::SATP {
+                self.mmu.flush();
+            }
+        }
+        self[rd] = old;
+        Ok(())
+    }
+
+    const fn illegal_instruction(&self) -> Exception {
+        Exception::IllegalInstruction(self.instruction_bits)
+    }
+
+    /// Floating point instructions are illegal while `mstatus.FS` is off.
+    const fn require_fp(&self) -> Result<(), Exception> {
+        if self.csrs.fp_enabled() {
+            Ok(())
+        } else {
+            Err(self.illegal_instruction())
+        }
+    }
+
+    /// Sets up a floating point operation with the rounding mode from an
+    /// instruction's `rm` field, which can defer to the dynamic rounding
+    /// mode in `frm`.
+    fn fp_env(&self, rm: u8) -> Result<float::Env, Exception> {
+        self.require_fp()?;
+        let rm = match rm {
+            0b111 => self.csrs.frm(),
+            _ => rm.into(),
+        };
+        RoundingMode::from_bits(rm)
+            .map(float::Env::new)
+            .ok_or(self.illegal_instruction())
+    }
+
+    /// Reads a floating point register as a value of the given precision.
+    /// Single-precision values that aren't properly NaN-boxed read as the
+    /// canonical NaN.
+    fn read_fp(&self, reg: FRegisterName, precision: Precision) -> u64 {
+        let value = self.fregisters[usize::from(reg)];
+        match precision {
+            Precision::Single if value >> 32 != 0xffff_ffff => {
+                precision.canonical_nan()
             }
+            Precision::Single => value & 0xffff_ffff,
+            Precision::Double => value,
         }
     }
+
+    fn write_fp(
+        &mut self,
+        reg: FRegisterName,
+        precision: Precision,
+        value: u64,
+    ) {
+        self.fregisters[usize::from(reg)] = match precision {
+            Precision::Single => 0xffff_ffff_0000_0000 | value,
+            Precision::Double => value,
+        };
+        self.csrs.set_fp_dirty();
+    }
+
+    /// Handles a write to the HTIF `tohost` location, which is how
+    /// bare-metal test programs report their results.
+    fn handle_tohost(&mut self, tohost: u64) -> Result<()> {
+        let mut shared = self.shared.borrow_mut();
+        let value = shared.memory.read_u64(tohost)?;
+        let payload = value & 0xffff_ffff_ffff;
+        if value >> 48 == HTIF_CONSOLE_PUTCHAR {
+            print!("{}", char::from(payload as u8));
+            // Output is unbuffered from the guest's point of view
+            let _ = io::stdout().flush();
+            shared.memory.write_u64(tohost, 0)?;
+        } else if value >> 48 == 0 && payload & 1 == 1 {
+            self.exit_reason = Some(ExitReason::Tohost(payload >> 1));
+        }
+        Ok(())
+    }
 }
 
 impl Index<RegisterName> for Cpu {
@@ -261,8 +1336,19 @@ impl IndexMut<RegisterName> for Cpu {
     }
 }
 
-/// Interprets the low 12 bits of the operand as a signed integer and
-/// sign-extends it to fill 32 bits again.
-const fn sign_extend_12bit(imm: u32) -> i32 {
-    (imm << 20) as i32 >> 20
+/// Computes the value that an AMO stores from the old value in memory and the
+/// value of `rs2`. Word-sized values are sign-extended, which doesn't change
+/// how they compare as unsigned integers.
+fn amo_result(funct: &AmoFunct, old: u64, src: u64) -> u64 {
+    match funct {
+        AmoFunct::Swap => src,
+        AmoFunct::Add => old.wrapping_add(src),
+        AmoFunct::Xor => old ^ src,
+        AmoFunct::And => old & src,
+        AmoFunct::Or => old | src,
+        AmoFunct::Min => (old as i64).min(src as i64) as u64,
+        AmoFunct::Max => (old as i64).max(src as i64) as u64,
+        AmoFunct::Minu => old.min(src),
+        AmoFunct::Maxu => old.max(src),
+    }
 }
@@ -0,0 +1,742 @@
+//! Basic blocks of instructions that have been translated ahead of time.
+//!
+//! Each instruction becomes an [`Op`]: a pointer to a handler for that exact
+//! operation, along with its registers and an immediate that has already been
+//! sign-extended, so running it doesn't go through a `match` on the
+//! instruction. A block runs until the first instruction that jumps,
+//! branches, traps on purpose or changes how later instructions behave, like
+//! a CSR write, and never leaves the page it starts on.
+//!
+//! Blocks are cached by the physical address of their first instruction and
+//! dropped like decoded instructions when their page is written to. Each
+//! block also remembers the blocks that ran right after it, so that loops can
+//! go from one block to the next without translating `pc` or looking
+//! anything up.
+
+use super::Cpu;
+use crate::{
+    bits::{sign_extend_12bit, SignExtend},
+    csr::Privilege,
+    decode_cache::Decoded,
+    instruction::{BFunct, IFunct, Instruction, RFunct, SFunct, UOpcode},
+    register::RegisterName,
+    trap::Exception,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    rc::{Rc, Weak},
+};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// The most instructions that go into a single block.
+const MAX_BLOCK_LENGTH: usize = 64;
+
+type Handler = fn(&mut Cpu, &Op) -> Result<(), Exception>;
+
+/// A single instruction, ready to run.
+#[derive(Clone, Copy)]
+pub struct Op {
+    handler: Handler,
+    pub(super) rd: RegisterName,
+    pub(super) rs1: RegisterName,
+    pub(super) rs2: RegisterName,
+    /// Sign-extended immediate, which branches and jumps add to the address
+    /// of the instruction.
+    pub(super) imm: u64,
+    /// The instruction that the op was translated from, for handlers that
+    /// need more than the fields above.
+    pub(super) instruction: Instruction,
+    /// The raw encoding, which also tells how long the instruction is.
+    pub bits: u32,
+}
+
+impl Op {
+    pub fn new(decoded: Decoded) -> Self {
+        let Decoded { instruction, bits } = decoded;
+        let mut op = Self {
+            handler: run_instruction,
+            rd: RegisterName::X0,
+            rs1: RegisterName::X0,
+            rs2: RegisterName::X0,
+            imm: 0,
+            instruction,
+            bits,
+        };
+        match instruction {
+            Instruction::R {
+                funct,
+                rd,
+                rs2,
+                rs1,
+            } => {
+                (op.rd, op.rs1, op.rs2) = (rd, rs1, rs2);
+                op.handler = match funct {
+                    RFunct::Add => add,
+                    RFunct::Sub => sub,
+                    RFunct::Sll => sll,
+                    RFunct::Slt => slt,
+                    RFunct::Sltu => sltu,
+                    RFunct::Xor => xor,
+                    RFunct::Srl => srl,
+                    RFunct::Sra => sra,
+                    RFunct::Or => or,
+                    RFunct::And => and,
+                    RFunct::Mul => mul,
+                    RFunct::Mulh => mulh,
+                    RFunct::Mulhsu => mulhsu,
+                    RFunct::Mulhu => mulhu,
+                    RFunct::Div => div,
+                    RFunct::Divu => divu,
+                    RFunct::Rem => rem,
+                    RFunct::Remu => remu,
+                    RFunct::Mulw => mulw,
+                    RFunct::Divw => divw,
+                    RFunct::Divuw => divuw,
+                    RFunct::Remw => remw,
+                    RFunct::Remuw => remuw,
+                    RFunct::Addw => addw,
+                    RFunct::Subw => subw,
+                    RFunct::Sllw => sllw,
+                    RFunct::Srlw => srlw,
+                    RFunct::Sraw => sraw,
+                };
+            }
+            Instruction::I {
+                funct,
+                rd,
+                rs1,
+                imm,
+            } => {
+                (op.rd, op.rs1) = (rd, rs1);
+                op.imm = sign_extend_12bit(imm).sign_extend();
+                op.handler = match funct {
+                    IFunct::Addi => addi,
+                    IFunct::Slti => slti,
+                    IFunct::Sltiu => sltiu,
+                    IFunct::Xori => xori,
+                    IFunct::Ori => ori,
+                    IFunct::Andi => andi,
+                    IFunct::Slli => slli,
+                    IFunct::Srli => srli,
+                    IFunct::Srai => srai,
+                    IFunct::Addiw => addiw,
+                    IFunct::Slliw => slliw,
+                    IFunct::Srliw => srliw,
+                    IFunct::Sraiw => sraiw,
+                    IFunct::Lb => lb,
+                    IFunct::Lh => lh,
+                    IFunct::Lw => lw,
+                    IFunct::Ld => ld,
+                    IFunct::Lbu => lbu,
+                    IFunct::Lhu => lhu,
+                    IFunct::Lwu => lwu,
+                    IFunct::Jalr => jalr,
+                };
+            }
+            Instruction::S {
+                funct,
+                rs2,
+                rs1,
+                imm,
+            } => {
+                (op.rs1, op.rs2) = (rs1, rs2);
+                op.imm = sign_extend_12bit(imm.into()).sign_extend();
+                op.handler = match funct {
+                    SFunct::Sb => sb,
+                    SFunct::Sh => sh,
+                    SFunct::Sw => sw,
+                    SFunct::Sd => sd,
+                };
+            }
+            Instruction::B {
+                funct,
+                rs2,
+                rs1,
+                imm,
+            } => {
+                (op.rs1, op.rs2) = (rs1, rs2);
+                op.imm = i32::from(imm).sign_extend();
+                op.handler = match funct {
+                    BFunct::Beq => beq,
+                    BFunct::Bne => bne,
+                    BFunct::Blt => blt,
+                    BFunct::Bge => bge,
+                    BFunct::Bltu => bltu,
+                    BFunct::Bgeu => bgeu,
+                };
+            }
+            Instruction::U { opcode, rd, imm } => {
+                op.rd = rd;
+                op.imm = imm.sign_extend();
+                op.handler = match opcode {
+                    UOpcode::Lui => lui,
+                    UOpcode::Auipc => auipc,
+                };
+            }
+            Instruction::Jal { rd, imm } => {
+                op.rd = rd;
+                op.imm = imm.sign_extend();
+                op.handler = jal;
+            }
+            _ => {}
+        }
+        op
+    }
+
+    pub fn run(&self, cpu: &mut Cpu) -> Result<(), Exception> {
+        (self.handler)(cpu, self)
+    }
+
+    pub const fn len(&self) -> u64 {
+        if self.bits & 0b11 == 0b11 {
+            4
+        } else {
+            2
+        }
+    }
+}
+
+/// Runs instructions that don't have handlers of their own.
+fn run_instruction(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+    cpu.run_instruction(op.instruction)
+}
+
+/// Defines handlers that compute a value from `rs1` and either `rs2` or the
+/// immediate, and write it to `rd`. The computations themselves end up in
+/// [`alu`].
+macro_rules! alu_handlers {
+    ($($reg:ident $(, $imm:ident)? => |$a:ident, $b:ident| $result:expr;)*) => {
+        /// The operations of the register-register ALU instructions.
+        pub(super) mod alu {
+            use crate::bits::SignExtend;
+
+            $(
+                pub fn $reg($a: u64, $b: u64) -> u64 {
+                    $result
+                }
+            )*
+        }
+
+        $(
+            fn $reg(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+                cpu[op.rd] = alu::$reg(cpu[op.rs1], cpu[op.rs2]);
+                Ok(())
+            }
+            $(
+                fn $imm(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+                    cpu[op.rd] = alu::$reg(cpu[op.rs1], op.imm);
+                    Ok(())
+                }
+            )?
+        )*
+    };
+}
+
+alu_handlers! {
+    add, addi => |a, b| a.wrapping_add(b);
+    sub => |a, b| a.wrapping_sub(b);
+    sll, slli => |a, b| a.wrapping_shl(b as u32);
+    slt, slti => |a, b| u64::from((a as i64) < b as i64);
+    sltu, sltiu => |a, b| u64::from(a < b);
+    xor, xori => |a, b| a ^ b;
+    srl, srli => |a, b| a.wrapping_shr(b as u32);
+    sra, srai => |a, b| (a as i64).wrapping_shr(b as u32) as u64;
+    or, ori => |a, b| a | b;
+    and, andi => |a, b| a & b;
+    mul => |a, b| a.wrapping_mul(b);
+    mulh => |a, b| {
+        ((i128::from(a as i64) * i128::from(b as i64)) >> 64) as u64
+    };
+    mulhsu => |a, b| ((i128::from(a as i64) * i128::from(b)) >> 64) as u64;
+    mulhu => |a, b| ((u128::from(a) * u128::from(b)) >> 64) as u64;
+    // Division by zero and signed overflow don't trap; the results are fully
+    // specified by the M extension instead.
+    div => |a, b| {
+        if b == 0 {
+            u64::MAX
+        } else {
+            (a as i64).wrapping_div(b as i64) as u64
+        }
+    };
+    divu => |a, b| a.checked_div(b).unwrap_or(u64::MAX);
+    rem => |a, b| {
+        if b == 0 {
+            a
+        } else {
+            (a as i64).wrapping_rem(b as i64) as u64
+        }
+    };
+    remu => |a, b| a.checked_rem(b).unwrap_or(a);
+    mulw => |a, b| (a as u32).wrapping_mul(b as u32).sign_extend();
+    divw => |a, b| {
+        if b as u32 == 0 {
+            u64::MAX
+        } else {
+            (a as i32).wrapping_div(b as i32).sign_extend()
+        }
+    };
+    divuw => |a, b| {
+        (a as u32)
+            .checked_div(b as u32)
+            .map_or(u64::MAX, SignExtend::sign_extend)
+    };
+    remw => |a, b| {
+        if b as u32 == 0 {
+            (a as u32).sign_extend()
+        } else {
+            (a as i32).wrapping_rem(b as i32).sign_extend()
+        }
+    };
+    remuw => |a, b| {
+        (a as u32).checked_rem(b as u32).unwrap_or(a as u32).sign_extend()
+    };
+    addw, addiw => |a, b| (a as u32).wrapping_add(b as u32).sign_extend();
+    subw => |a, b| (a as u32).wrapping_sub(b as u32).sign_extend();
+    sllw, slliw => |a, b| (a as u32).wrapping_shl(b as u32).sign_extend();
+    srlw, srliw => |a, b| (a as u32).wrapping_shr(b as u32).sign_extend();
+    sraw, sraiw => |a, b| (a as i32).wrapping_shr(b as u32).sign_extend();
+}
+
+/// Defines handlers that load a value of the given type from `rs1` plus the
+/// immediate and write it to `rd`, extended according to its signedness.
+macro_rules! load_handlers {
+    ($($name:ident: $type:ty;)*) => {
+        $(
+            fn $name(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+                let address = cpu[op.rs1].wrapping_add(op.imm);
+                cpu[op.rd] = <$type>::from_le_bytes(cpu.load(address)?) as u64;
+                Ok(())
+            }
+        )*
+    };
+}
+
+load_handlers! {
+    lb: i8;
+    lh: i16;
+    lw: i32;
+    ld: u64;
+    lbu: u8;
+    lhu: u16;
+    lwu: u32;
+}
+
+/// Defines handlers that store the low bytes of `rs2` to `rs1` plus the
+/// immediate.
+macro_rules! store_handlers {
+    ($($name:ident: $type:ty;)*) => {
+        $(
+            fn $name(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+                let address = cpu[op.rs1].wrapping_add(op.imm);
+                cpu.store(address, (cpu[op.rs2] as $type).to_le_bytes())
+            }
+        )*
+    };
+}
+
+store_handlers! {
+    sb: u8;
+    sh: u16;
+    sw: u32;
+    sd: u64;
+}
+
+/// Defines handlers that branch by the immediate if a condition on `rs1` and
+/// `rs2` holds.
+macro_rules! branch_handlers {
+    ($($name:ident => |$a:ident, $b:ident| $condition:expr;)*) => {
+        $(
+            fn $name(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+                let ($a, $b) = (cpu[op.rs1], cpu[op.rs2]);
+                if $condition {
+                    cpu.pc = cpu.old_pc.wrapping_add(op.imm);
+                }
+                Ok(())
+            }
+        )*
+    };
+}
+
+branch_handlers! {
+    beq => |a, b| a == b;
+    bne => |a, b| a != b;
+    blt => |a, b| (a as i64) < b as i64;
+    bge => |a, b| a as i64 >= b as i64;
+    bltu => |a, b| a < b;
+    bgeu => |a, b| a >= b;
+}
+
+fn lui(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+    cpu[op.rd] = op.imm;
+    Ok(())
+}
+
+fn auipc(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+    cpu[op.rd] = cpu.old_pc.wrapping_add(op.imm);
+    Ok(())
+}
+
+fn jal(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+    cpu[op.rd] = cpu.pc;
+    cpu.pc = cpu.old_pc.wrapping_add(op.imm);
+    Ok(())
+}
+
+fn jalr(cpu: &mut Cpu, op: &Op) -> Result<(), Exception> {
+    // `rd` and `rs1` may be the same register
+    let target = cpu[op.rs1].wrapping_add(op.imm) & !1;
+    cpu[op.rd] = cpu.pc;
+    cpu.pc = target;
+    Ok(())
+}
+
+/// Whether a block has to end after the instruction, since what runs next
+/// can't be known until it has run.
+const fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::B { .. }
+            | Instruction::Jal { .. }
+            | Instruction::I {
+                funct: IFunct::Jalr,
+                ..
+            }
+            | Instruction::Ecall
+            | Instruction::Ebreak
+            | Instruction::Sret
+            | Instruction::Mret
+            | Instruction::Wfi
+            | Instruction::SfenceVma { .. }
+            | Instruction::FenceI
+            | Instruction::Csr { .. }
+            | Instruction::CsrImm { .. }
+    )
+}
+
+pub struct Block {
+    ops: Vec<Op>,
+    /// Cleared once the block has been dropped from the cache, so that blocks
+    /// linked to it stop going there.
+    valid: Cell<bool>,
+    /// The blocks that ran right after this one, which are likely to run
+    /// after it again.
+    successors: RefCell<[Option<Link>; 2]>,
+    #[cfg(feature = "jit")]
+    pub(super) native: Cell<super::jit::Native>,
+}
+
+impl Block {
+    /// Translates the instructions starting at a physical address, stopping
+    /// at the end of the page or at the first one that can't be decoded.
+    /// Returns `None` if not even the first instruction can be translated.
+    pub fn translate(
+        mut read: impl FnMut(u64) -> Option<u16>,
+        physical: u64,
+    ) -> Option<Self> {
+        let page_end = (physical / PAGE_SIZE + 1) * PAGE_SIZE;
+        let mut ops = Vec::new();
+        let mut address = physical;
+        while ops.len() < MAX_BLOCK_LENGTH && address < page_end {
+            let Some(decoded) = decode(&mut read, address, page_end) else {
+                break;
+            };
+            let op = Op::new(decoded);
+            ops.push(op);
+            if ends_block(&decoded.instruction) {
+                break;
+            }
+            address += op.len();
+        }
+        (!ops.is_empty()).then(|| Self {
+            ops,
+            valid: Cell::new(true),
+            successors: RefCell::default(),
+            #[cfg(feature = "jit")]
+            native: Cell::default(),
+        })
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Finds the block that was linked to run after this one with the given
+    /// `pc`, privilege mode and MMU generation.
+    pub fn successor(&self, context: Context) -> Option<Rc<Self>> {
+        self.successors.borrow().iter().flatten().find_map(|link| {
+            (link.context == context)
+                .then(|| link.block.upgrade())
+                .flatten()
+                .filter(|block| block.valid.get())
+        })
+    }
+
+    /// Remembers that `next` runs after this block in the given context,
+    /// replacing the older of the remembered successors if need be.
+    pub fn link(&self, context: Context, next: &Rc<Self>) {
+        let mut successors = self.successors.borrow_mut();
+        successors.swap(0, 1);
+        successors[0] = Some(Link {
+            context,
+            block: Rc::downgrade(next),
+        });
+    }
+}
+
+/// What a block's successor is linked under. The MMU generation changes
+/// whenever translations are flushed, which may map `pc` somewhere else.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub pc: u64,
+    pub privilege: Privilege,
+    pub mmu_generation: u64,
+}
+
+struct Link {
+    context: Context,
+    block: Weak<Block>,
+}
+
+/// Decodes the instruction at a physical address, unless it continues past
+/// `page_end`.
+fn decode(
+    read: &mut impl FnMut(u64) -> Option<u16>,
+    address: u64,
+    page_end: u64,
+) -> Option<Decoded> {
+    let low_half = read(address)?;
+    if low_half & 0b11 != 0b11 {
+        let instruction = Instruction::try_from(low_half).ok()?;
+        return Some(Decoded {
+            instruction,
+            bits: low_half.into(),
+        });
+    }
+    if address + 4 > page_end {
+        return None;
+    }
+    let bits = u32::from(read(address + 2)?) << 16 | u32::from(low_half);
+    let instruction = Instruction::try_from(bits).ok()?;
+    Some(Decoded { instruction, bits })
+}
+
+/// Translated blocks, by the physical address of their first instruction.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: BTreeMap<u64, Rc<Block>>,
+}
+
+impl BlockCache {
+    pub fn get(&self, physical: u64) -> Option<Rc<Block>> {
+        self.blocks.get(&physical).cloned()
+    }
+
+    pub fn insert(&mut self, physical: u64, block: Rc<Block>) {
+        self.blocks.insert(physical, block);
+    }
+
+    /// Drops the blocks in the page that contains `physical`.
+    pub fn invalidate_page(&mut self, physical: u64) {
+        let start = physical / PAGE_SIZE * PAGE_SIZE;
+        let end = start + PAGE_SIZE;
+        let addresses =
+            self.blocks.range(start..end).map(|(&address, _)| address);
+        for address in addresses.collect::<Vec<_>>() {
+            if let Some(block) = self.blocks.remove(&address) {
+                block.valid.set(false);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for block in std::mem::take(&mut self.blocks).into_values() {
+            block.valid.set(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Op;
+    use crate::{
+        cpu::{Cpu, ExitReason},
+        decode_cache::Decoded,
+        error::Error,
+        instruction::{Instruction, RFunct},
+        load::Program,
+        machine::{Machine, Shared},
+        memory::Memory,
+        register::RegisterName,
+        trap::Exception,
+        Opts,
+    };
+    use gumdrop::Options;
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    const BASE: u64 = 0x8000_0000;
+
+    /// Jumps to an odd address a hundred times, exiting with 1 if `jalr`
+    /// doesn't land on the instruction just below it.
+    const JALR_LOOP: [u32; 12] = [
+        0x0640_0413, // li s0, 100
+        0x0000_0297, // loop: auipc t0, 0
+        0x0112_8293, // addi t0, t0, 17
+        0x0002_80e7, // jalr ra, 0(t0)
+        0x0140_006f, // j fail
+        0xfff4_0413, // addi s0, s0, -1
+        0xfe04_16e3, // bnez s0, loop
+        0x0000_0513, // li a0, 0
+        0x0080_006f, // j exit
+        0x0010_0513, // fail: li a0, 1
+        0x05d0_0893, // exit: li a7, 93
+        0x0000_0073, // ecall
+    ];
+
+    /// Sets up a Linux process that runs the given instructions.
+    fn process(words: &[u32], args: &[&str]) -> Machine {
+        let bytes = words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut memory = Memory::default();
+        memory.map(BASE, bytes.len());
+        memory.write_bytes(BASE, &bytes).unwrap();
+        let program = Program {
+            memory,
+            entry: BASE,
+            end: BASE + bytes.len() as u64,
+            phdr: 0,
+            phent: 0,
+            phnum: 0,
+            tohost: None,
+            symbols: HashMap::new(),
+        };
+        let opts =
+            Opts::parse_args_default(&[args, &["test"]].concat()).unwrap();
+        Machine::new(opts, program).unwrap()
+    }
+
+    /// Runs instructions as a Linux process and returns its exit status.
+    fn run(words: &[u32], args: &[&str]) -> i32 {
+        match process(words, args).run().unwrap() {
+            ExitReason::Exit(status) => status,
+            reason => panic!("unexpected exit: {reason:?}"),
+        }
+    }
+
+    /// Runs a register-register instruction on two operands.
+    fn run_r(funct: RFunct, a: u64, b: u64) -> u64 {
+        let (rd, rs1, rs2) = (
+            RegisterName::rd(10 << 7),
+            RegisterName::rs1(11 << 15),
+            RegisterName::rs2(12 << 20),
+        );
+        let opts = Opts::parse_args_default(&["test"]).unwrap();
+        let shared = Shared::new(Memory::default(), None, None, None, 1, None);
+        let mut cpu = Cpu::with_process(
+            Rc::new(opts),
+            Rc::new(RefCell::new(shared)),
+            0,
+            0,
+        );
+        cpu[rs1] = a;
+        cpu[rs2] = b;
+        let instruction = Instruction::R {
+            funct,
+            rd,
+            rs1,
+            rs2,
+        };
+        Op::new(Decoded {
+            instruction,
+            bits: 0,
+        })
+        .run(&mut cpu)
+        .unwrap();
+        cpu[rd]
+    }
+
+    #[test]
+    fn m_extension_edge_cases() {
+        const MIN: u64 = i64::MIN as u64;
+        const MINUS_ONE: u64 = u64::MAX;
+        const WORD_MIN: u64 = i32::MIN as i64 as u64;
+        let cases = [
+            // Division by zero gives all ones, and the remainder is the
+            // dividend
+            (RFunct::Div, 7, 0, MINUS_ONE),
+            (RFunct::Divu, 7, 0, u64::MAX),
+            (RFunct::Rem, MIN, 0, MIN),
+            (RFunct::Remu, 7, 0, 7),
+            (RFunct::Divw, 7, 0, MINUS_ONE),
+            (RFunct::Divuw, 7, 0, u64::MAX),
+            (RFunct::Remw, 0x1_8000_0000, 0, WORD_MIN),
+            (RFunct::Remuw, 0x1_8000_0000, 0, WORD_MIN),
+            // Signed overflow gives the dividend and no remainder
+            (RFunct::Div, MIN, MINUS_ONE, MIN),
+            (RFunct::Rem, MIN, MINUS_ONE, 0),
+            (RFunct::Divw, WORD_MIN, MINUS_ONE, WORD_MIN),
+            (RFunct::Remw, WORD_MIN, MINUS_ONE, 0),
+            // Word operations ignore the upper halves of their operands and
+            // sign-extend their results
+            (RFunct::Divw, 0x1_0000_0006, 0x1_0000_0003, 2),
+            (RFunct::Divuw, 0xffff_fffe, 1, 0xffff_ffff_ffff_fffe),
+            (RFunct::Mulw, 0x7fff_ffff, 2, 0xffff_ffff_ffff_fffe),
+            // The upper halves of 128-bit products
+            (RFunct::Mulh, MINUS_ONE, MINUS_ONE, 0),
+            (RFunct::Mulh, MIN, MIN, 1 << 62),
+            (RFunct::Mulhsu, MINUS_ONE, u64::MAX, MINUS_ONE),
+            (RFunct::Mulhu, u64::MAX, u64::MAX, u64::MAX - 1),
+            (RFunct::Mul, MIN, MINUS_ONE, MIN),
+        ];
+        for (i, (funct, a, b, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(run_r(funct, a, b), expected, "case {i}");
+        }
+    }
+
+    #[test]
+    fn jalr_clears_bit_zero() {
+        assert_eq!(run(&JALR_LOOP, &[]), 0);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn compiled_jalr_clears_bit_zero() {
+        assert_eq!(run(&JALR_LOOP, &["--jit"]), 0);
+    }
+
+    #[test]
+    fn misaligned_accesses_are_split_in_processes() {
+        let words = [
+            0x1234_52b7, // lui t0, 0x12345
+            0x6782_829b, // addiw t0, t0, 0x678
+            0xfe51_2ca3, // sw t0, -7(sp)
+            0xff91_2303, // lw t1, -7(sp)
+            0x4062_8533, // sub a0, t0, t1
+            0x05d0_0893, // li a7, 93
+            0x0000_0073, // ecall
+        ];
+        assert_eq!(run(&words, &[]), 0);
+    }
+
+    #[test]
+    fn processes_run_in_user_mode() {
+        let words = [
+            0xc010_2573, // rdtime a0
+            0x3000_2573, // csrr a0, mstatus
+        ];
+        let result = process(&words, &[]).run();
+        assert!(
+            matches!(
+                result,
+                Err(Error::UnhandledTrap {
+                    exception: Exception::IllegalInstruction(_),
+                    pc: 0x8000_0004,
+                })
+            ),
+            "{result:?}"
+        );
+    }
+}
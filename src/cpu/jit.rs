@@ -0,0 +1,691 @@
+//! Compiling hot blocks to native code with Cranelift.
+//!
+//! Once a block has run [`HOT_THRESHOLD`] times, its ops are compiled into a
+//! native function that works on the hart's registers in place. Integer
+//! arithmetic, branches and jumps are compiled inline, with guest registers
+//! kept in host registers until the block exits. Loads and stores call into
+//! the same memory layer as the interpreter, and every other instruction is
+//! run by its interpreter handler, with the registers written back first.
+//!
+//! Whenever an instruction can't let the block go on, like when it raises
+//! an exception or overwrites code, the compiled code writes the registers
+//! back and returns, and the interpreter takes over from there. Compiled code
+//! is never freed, even once its block has been dropped, since Cranelift's
+//! JIT can only free all of its memory at once.
+
+use super::{
+    block::{alu, Op},
+    Cpu,
+};
+use crate::{
+    error::{Error, Result},
+    instruction::{BFunct, IFunct, Instruction, RFunct, SFunct, UOpcode},
+    register::RegisterName,
+};
+use cranelift_codegen::{
+    ir::{
+        condcodes::IntCC, types, AbiParam, InstBuilder, MemFlags, SigRef,
+        Signature, StackSlot, StackSlotData, StackSlotKind, Type, Value,
+    },
+    settings::{self, Configurable},
+    Context,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+use std::mem::{self, offset_of};
+
+/// How many times a block runs in the interpreter before it gets compiled.
+const HOT_THRESHOLD: u32 = 50;
+
+// What compiled code returns
+/// Every instruction in the block retired.
+const FINISHED: u32 = 0;
+/// An instruction raised the exception in [`Cpu::jit_exception`], and `pc`
+/// points at it.
+const EXCEPTION: u32 = 1;
+/// An instruction retired, but the rest of the block can't run since it
+/// overwrote code, stopped the hart or made the guest exit.
+const STOPPED: u32 = 2;
+
+/// A compiled block, which returns one of the statuses above.
+type Function = unsafe extern "C" fn(*mut Cpu) -> u32;
+
+/// Where a block is in its way to being compiled.
+#[derive(Clone, Copy)]
+pub enum Native {
+    /// Not compiled yet, after running this many times.
+    Cold(u32),
+    Compiled(Function),
+    /// Compiling the block failed, so it stays in the interpreter.
+    Failed,
+}
+
+impl Default for Native {
+    fn default() -> Self {
+        Self::Cold(0)
+    }
+}
+
+pub struct Compiler {
+    module: JITModule,
+    context: Context,
+    builder_context: FunctionBuilderContext,
+}
+
+impl Compiler {
+    pub fn new() -> Result<Self> {
+        let mut flags = settings::builder();
+        flags
+            .set("opt_level", "speed")
+            .expect("Cranelift has an `opt_level` setting");
+        let isa = cranelift_native::builder()
+            .map_err(|err| Error::Jit(err.to_owned()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|err| Error::Jit(err.to_string()))?;
+        let module =
+            JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        Ok(Self {
+            context: module.make_context(),
+            module,
+            builder_context: FunctionBuilderContext::new(),
+        })
+    }
+
+    /// Compiles the ops of a block, which have to stay where they are for as
+    /// long as the compiled code may run.
+    fn compile(&mut self, ops: &[Op]) -> Option<Function> {
+        let pointer = self.module.target_config().pointer_type();
+        let signature = &mut self.context.func.signature;
+        signature.params.push(AbiParam::new(pointer));
+        signature.returns.push(AbiParam::new(types::I32));
+        let builder = FunctionBuilder::new(
+            &mut self.context.func,
+            &mut self.builder_context,
+        );
+        Translator::new(builder, pointer).translate(ops);
+        let id = self
+            .module
+            .declare_anonymous_function(&self.context.func.signature)
+            .ok();
+        let defined = id.is_some_and(|id| {
+            self.module.define_function(id, &mut self.context).is_ok()
+        });
+        self.module.clear_context(&mut self.context);
+        let id = id.filter(|_| defined)?;
+        self.module.finalize_definitions().ok()?;
+        let code = self.module.get_finalized_function(id);
+        // SAFETY: The function was declared with this signature
+        Some(unsafe { mem::transmute::<*const u8, Function>(code) })
+    }
+}
+
+impl Cpu {
+    /// Returns the compiled code for a block, compiling it if it has become
+    /// hot.
+    pub(super) fn native_code(&self, block: &super::Block) -> Option<Function> {
+        let ops = block.ops();
+        let native = &block.native;
+        match native.get() {
+            Native::Compiled(function) => Some(function),
+            Native::Failed => None,
+            Native::Cold(runs) if runs < HOT_THRESHOLD => {
+                native.set(Native::Cold(runs + 1));
+                None
+            }
+            Native::Cold(_) => {
+                let mut shared = self.shared.borrow_mut();
+                let compiler =
+                    shared.jit.as_mut().expect("--jit sets up a compiler");
+                let function = compiler.compile(ops);
+                native.set(function.map_or(Native::Failed, Native::Compiled));
+                function
+            }
+        }
+    }
+
+    /// Runs a compiled block, returning whether the hart can go on to the
+    /// next block like [`Cpu::run_block`] does.
+    pub(super) fn run_native(&mut self, function: Function) -> Result<bool> {
+        self.code_written = false;
+        // SAFETY: Compiled code only touches the fields of the hart that it
+        // was compiled to, and calls back into the helpers below
+        match unsafe { function(self) } {
+            FINISHED => Ok(self.is_running()),
+            EXCEPTION => {
+                let exception = self
+                    .jit_exception
+                    .take()
+                    .expect("compiled code leaves the exception behind");
+                self.take_trap(exception)?;
+                Ok(false)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Builds the native function for a block out of its ops.
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    pointer: Type,
+    cpu: Value,
+    /// Address of the first instruction.
+    entry_pc: Value,
+    /// The values of the guest registers that have been used so far, along
+    /// with whether they have yet to be written back to the hart.
+    registers: [Option<(Value, bool)>; 32],
+    /// How many instructions have retired without `instret` counting them.
+    retired: i64,
+    /// Where loads put the value that they read.
+    load_slot: StackSlot,
+    alu_signature: SigRef,
+    load_signature: SigRef,
+    store_signature: SigRef,
+    op_signature: SigRef,
+}
+
+impl<'a> Translator<'a> {
+    fn new(mut builder: FunctionBuilder<'a>, pointer: Type) -> Self {
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let cpu = builder.block_params(entry)[0];
+        let entry_pc = builder.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            cpu,
+            field(offset_of!(Cpu, pc)),
+        );
+        let load_slot = builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            8,
+            3,
+        ));
+        let mut import = |params: &[Type], returns: Type| {
+            let mut signature =
+                Signature::new(builder.func.signature.call_conv);
+            signature
+                .params
+                .extend(params.iter().map(|&param| AbiParam::new(param)));
+            signature.returns.push(AbiParam::new(returns));
+            builder.import_signature(signature)
+        };
+        let alu_signature = import(&[types::I64, types::I64], types::I64);
+        let load_signature =
+            import(&[pointer, types::I64, pointer], types::I32);
+        let store_signature =
+            import(&[pointer, types::I64, types::I64], types::I32);
+        let op_signature = import(&[pointer, pointer], types::I32);
+        Self {
+            builder,
+            pointer,
+            cpu,
+            entry_pc,
+            registers: [None; 32],
+            retired: 0,
+            load_slot,
+            alu_signature,
+            load_signature,
+            store_signature,
+            op_signature,
+        }
+    }
+
+    fn translate(mut self, ops: &[Op]) {
+        let mut offset = 0;
+        for (i, op) in ops.iter().enumerate() {
+            let pc = self.builder.ins().iadd_imm(self.entry_pc, offset);
+            let next_pc = self.builder.ins().iadd_imm(pc, op.len() as i64);
+            offset += op.len() as i64;
+            match op.instruction {
+                Instruction::R { funct, .. } => {
+                    let (a, b) = (self.read(op.rs1), self.read(op.rs2));
+                    let result = self.alu(funct, a, b);
+                    self.write(op.rd, result);
+                }
+                Instruction::I {
+                    funct: IFunct::Jalr,
+                    ..
+                } => {
+                    // `rd` may be the same register as `rs1`
+                    let base = self.read(op.rs1);
+                    let target =
+                        self.builder.ins().iadd_imm(base, op.imm as i64);
+                    let target = self.builder.ins().band_imm(target, !1);
+                    self.write(op.rd, next_pc);
+                    self.retired += 1;
+                    return self.finish(Some(target));
+                }
+                Instruction::I { funct, .. } => {
+                    let a = self.read(op.rs1);
+                    if let Some(helper) = load_function(funct) {
+                        let address =
+                            self.builder.ins().iadd_imm(a, op.imm as i64);
+                        self.load(helper, address, op.rd, pc, next_pc);
+                    } else {
+                        let b = self
+                            .builder
+                            .ins()
+                            .iconst(types::I64, op.imm as i64);
+                        let result = self.alu(alu_funct(funct), a, b);
+                        self.write(op.rd, result);
+                    }
+                }
+                Instruction::S { funct, .. } => {
+                    let base = self.read(op.rs1);
+                    let address =
+                        self.builder.ins().iadd_imm(base, op.imm as i64);
+                    let value = self.read(op.rs2);
+                    let helper = match funct {
+                        SFunct::Sb => store::<1> as *const u8,
+                        SFunct::Sh => store::<2> as *const u8,
+                        SFunct::Sw => store::<4> as *const u8,
+                        SFunct::Sd => store::<8> as *const u8,
+                    };
+                    let status = self.call(
+                        self.store_signature,
+                        helper,
+                        &[self.cpu, address, value],
+                    );
+                    self.exit_unless_finished(status, pc, next_pc);
+                }
+                Instruction::B { funct, .. } => {
+                    let (a, b) = (self.read(op.rs1), self.read(op.rs2));
+                    let condition = match funct {
+                        BFunct::Beq => IntCC::Equal,
+                        BFunct::Bne => IntCC::NotEqual,
+                        BFunct::Blt => IntCC::SignedLessThan,
+                        BFunct::Bge => IntCC::SignedGreaterThanOrEqual,
+                        BFunct::Bltu => IntCC::UnsignedLessThan,
+                        BFunct::Bgeu => IntCC::UnsignedGreaterThanOrEqual,
+                    };
+                    let taken = self.builder.ins().icmp(condition, a, b);
+                    let target = self.builder.ins().iadd_imm(pc, op.imm as i64);
+                    let next =
+                        self.builder.ins().select(taken, target, next_pc);
+                    self.retired += 1;
+                    return self.finish(Some(next));
+                }
+                Instruction::U { opcode, .. } => {
+                    let value = match opcode {
+                        UOpcode::Lui => {
+                            self.builder.ins().iconst(types::I64, op.imm as i64)
+                        }
+                        UOpcode::Auipc => {
+                            self.builder.ins().iadd_imm(pc, op.imm as i64)
+                        }
+                    };
+                    self.write(op.rd, value);
+                }
+                Instruction::Jal { .. } => {
+                    let target = self.builder.ins().iadd_imm(pc, op.imm as i64);
+                    self.write(op.rd, next_pc);
+                    self.retired += 1;
+                    return self.finish(Some(target));
+                }
+                _ => {
+                    self.run_op(op, pc);
+                    if i + 1 == ops.len() {
+                        // The handler has moved `pc` along already
+                        return self.finish(None);
+                    }
+                    // The handler counts the instruction as retired itself
+                    continue;
+                }
+            }
+            self.retired += 1;
+        }
+        let pc = self.builder.ins().iadd_imm(self.entry_pc, offset);
+        self.finish(Some(pc));
+    }
+
+    fn read(&mut self, register: RegisterName) -> Value {
+        let i = usize::from(register);
+        if i == 0 {
+            return self.builder.ins().iconst(types::I64, 0);
+        }
+        if let Some((value, _)) = self.registers[i] {
+            return value;
+        }
+        let value = self.builder.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            self.cpu,
+            register_offset(i),
+        );
+        self.registers[i] = Some((value, false));
+        value
+    }
+
+    fn write(&mut self, register: RegisterName, value: Value) {
+        let i = usize::from(register);
+        if i != 0 {
+            self.registers[i] = Some((value, true));
+        }
+    }
+
+    fn alu(&mut self, funct: RFunct, a: Value, b: Value) -> Value {
+        let ins = self.builder.ins();
+        match funct {
+            RFunct::Add => ins.iadd(a, b),
+            RFunct::Sub => ins.isub(a, b),
+            RFunct::Sll => ins.ishl(a, b),
+            RFunct::Slt => {
+                let less = ins.icmp(IntCC::SignedLessThan, a, b);
+                self.builder.ins().uextend(types::I64, less)
+            }
+            RFunct::Sltu => {
+                let less = ins.icmp(IntCC::UnsignedLessThan, a, b);
+                self.builder.ins().uextend(types::I64, less)
+            }
+            RFunct::Xor => ins.bxor(a, b),
+            RFunct::Srl => ins.ushr(a, b),
+            RFunct::Sra => ins.sshr(a, b),
+            RFunct::Or => ins.bor(a, b),
+            RFunct::And => ins.band(a, b),
+            RFunct::Mul => ins.imul(a, b),
+            RFunct::Mulh => ins.smulhi(a, b),
+            RFunct::Mulhu => ins.umulhi(a, b),
+            RFunct::Mulhsu => self.call_alu(mulhsu, a, b),
+            RFunct::Div => self.call_alu(div, a, b),
+            RFunct::Divu => self.call_alu(divu, a, b),
+            RFunct::Rem => self.call_alu(rem, a, b),
+            RFunct::Remu => self.call_alu(remu, a, b),
+            RFunct::Divw => self.call_alu(divw, a, b),
+            RFunct::Divuw => self.call_alu(divuw, a, b),
+            RFunct::Remw => self.call_alu(remw, a, b),
+            RFunct::Remuw => self.call_alu(remuw, a, b),
+            // The low half of a product or sum only depends on the low
+            // halves of the operands
+            RFunct::Mulw => {
+                let product = ins.imul(a, b);
+                self.sign_extend_word(product)
+            }
+            RFunct::Addw => {
+                let sum = ins.iadd(a, b);
+                self.sign_extend_word(sum)
+            }
+            RFunct::Subw => {
+                let difference = ins.isub(a, b);
+                self.sign_extend_word(difference)
+            }
+            RFunct::Sllw | RFunct::Srlw | RFunct::Sraw => {
+                let word = ins.ireduce(types::I32, a);
+                let ins = self.builder.ins();
+                let shifted = match funct {
+                    RFunct::Sllw => ins.ishl(word, b),
+                    RFunct::Srlw => ins.ushr(word, b),
+                    _ => ins.sshr(word, b),
+                };
+                self.builder.ins().sextend(types::I64, shifted)
+            }
+        }
+    }
+
+    fn sign_extend_word(&mut self, value: Value) -> Value {
+        let word = self.builder.ins().ireduce(types::I32, value);
+        self.builder.ins().sextend(types::I64, word)
+    }
+
+    fn call_alu(
+        &mut self,
+        helper: extern "C" fn(u64, u64) -> u64,
+        a: Value,
+        b: Value,
+    ) -> Value {
+        self.call(self.alu_signature, helper as *const u8, &[a, b])
+    }
+
+    fn call(
+        &mut self,
+        signature: SigRef,
+        helper: *const u8,
+        args: &[Value],
+    ) -> Value {
+        let callee = self.builder.ins().iconst(self.pointer, helper as i64);
+        let call = self.builder.ins().call_indirect(signature, callee, args);
+        self.builder.inst_results(call)[0]
+    }
+
+    fn load(
+        &mut self,
+        helper: *const u8,
+        address: Value,
+        rd: RegisterName,
+        pc: Value,
+        next_pc: Value,
+    ) {
+        let slot =
+            self.builder
+                .ins()
+                .stack_addr(self.pointer, self.load_slot, 0);
+        let status =
+            self.call(self.load_signature, helper, &[self.cpu, address, slot]);
+        self.exit_unless_finished(status, pc, next_pc);
+        let value =
+            self.builder.ins().stack_load(types::I64, self.load_slot, 0);
+        self.write(rd, value);
+    }
+
+    /// Returns from the compiled code if a helper that ran the instruction at
+    /// `pc` didn't finish, leaving the hart as the interpreter would.
+    fn exit_unless_finished(
+        &mut self,
+        status: Value,
+        pc: Value,
+        next_pc: Value,
+    ) {
+        let exit = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(status, exit, &[], next, &[]);
+        self.builder.switch_to_block(exit);
+        self.emit_write_back();
+        // Instructions that stop the block have still retired
+        let stopped = self.builder.ins().icmp_imm(
+            IntCC::Equal,
+            status,
+            i64::from(STOPPED),
+        );
+        let resume_pc = self.builder.ins().select(stopped, next_pc, pc);
+        self.store_field(resume_pc, offset_of!(Cpu, pc));
+        self.store_field(pc, offset_of!(Cpu, old_pc));
+        let stopped = self.builder.ins().uextend(types::I64, stopped);
+        let retired = self.builder.ins().iadd_imm(stopped, self.retired);
+        self.emit_retire(retired);
+        self.builder.ins().return_(&[status]);
+        self.builder.switch_to_block(next);
+    }
+
+    /// Runs the instruction at `pc` with its interpreter handler.
+    fn run_op(&mut self, op: &Op, pc: Value) {
+        // The handler may use any register
+        self.emit_write_back();
+        self.registers = [None; 32];
+        if self.retired != 0 {
+            let retired = self.builder.ins().iconst(types::I64, self.retired);
+            self.emit_retire(retired);
+            self.retired = 0;
+        }
+        self.store_field(pc, offset_of!(Cpu, pc));
+        let op = self
+            .builder
+            .ins()
+            .iconst(self.pointer, op as *const Op as i64);
+        let status =
+            self.call(self.op_signature, run_op as *const u8, &[self.cpu, op]);
+        let exit = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(status, exit, &[], next, &[]);
+        self.builder.switch_to_block(exit);
+        self.builder.ins().return_(&[status]);
+        self.builder.switch_to_block(next);
+    }
+
+    /// Ends the function, with the hart going on at `next_pc` unless a
+    /// handler has already set `pc`.
+    fn finish(mut self, next_pc: Option<Value>) {
+        self.emit_write_back();
+        if let Some(next_pc) = next_pc {
+            self.store_field(next_pc, offset_of!(Cpu, pc));
+        }
+        if self.retired != 0 {
+            let retired = self.builder.ins().iconst(types::I64, self.retired);
+            self.emit_retire(retired);
+        }
+        let finished =
+            self.builder.ins().iconst(types::I32, i64::from(FINISHED));
+        self.builder.ins().return_(&[finished]);
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    /// Writes the registers that have changed back to the hart, without
+    /// forgetting that they have changed, since the code that doesn't exit
+    /// still has to write them back later.
+    fn emit_write_back(&mut self) {
+        for (i, register) in self.registers.iter().enumerate() {
+            if let Some((value, true)) = *register {
+                self.builder.ins().store(
+                    MemFlags::trusted(),
+                    value,
+                    self.cpu,
+                    register_offset(i),
+                );
+            }
+        }
+    }
+
+    /// Adds a number of retired instructions to `instret`.
+    fn emit_retire(&mut self, count: Value) {
+        let offset = field(offset_of!(Cpu, csrs.instret));
+        let flags = MemFlags::trusted();
+        let instret =
+            self.builder.ins().load(types::I64, flags, self.cpu, offset);
+        let instret = self.builder.ins().iadd(instret, count);
+        self.builder.ins().store(flags, instret, self.cpu, offset);
+    }
+
+    fn store_field(&mut self, value: Value, offset: usize) {
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            value,
+            self.cpu,
+            field(offset),
+        );
+    }
+}
+
+fn field(offset: usize) -> i32 {
+    offset.try_into().expect("harts are small")
+}
+
+/// Offset of an integer register other than `x0` within the hart.
+fn register_offset(register: usize) -> i32 {
+    field(offset_of!(Cpu, registers) + (register - 1) * mem::size_of::<u64>())
+}
+
+/// The helper that a load instruction calls, if it is one.
+fn load_function(funct: IFunct) -> Option<*const u8> {
+    Some(match funct {
+        IFunct::Lb => load::<1, true> as *const u8,
+        IFunct::Lh => load::<2, true> as *const u8,
+        IFunct::Lw => load::<4, true> as *const u8,
+        IFunct::Ld => load::<8, false> as *const u8,
+        IFunct::Lbu => load::<1, false> as *const u8,
+        IFunct::Lhu => load::<2, false> as *const u8,
+        IFunct::Lwu => load::<4, false> as *const u8,
+        _ => return None,
+    })
+}
+
+/// The register-register counterpart of an ALU instruction with an
+/// immediate, which computes the same thing.
+const fn alu_funct(funct: IFunct) -> RFunct {
+    match funct {
+        IFunct::Addi => RFunct::Add,
+        IFunct::Slti => RFunct::Slt,
+        IFunct::Sltiu => RFunct::Sltu,
+        IFunct::Xori => RFunct::Xor,
+        IFunct::Ori => RFunct::Or,
+        IFunct::Andi => RFunct::And,
+        IFunct::Slli => RFunct::Sll,
+        IFunct::Srli => RFunct::Srl,
+        IFunct::Srai => RFunct::Sra,
+        IFunct::Addiw => RFunct::Addw,
+        IFunct::Slliw => RFunct::Sllw,
+        IFunct::Srliw => RFunct::Srlw,
+        IFunct::Sraiw => RFunct::Sraw,
+        _ => panic!("not an ALU instruction"),
+    }
+}
+
+/// Makes ALU operations that aren't worth compiling inline callable from
+/// compiled code.
+macro_rules! alu_helpers {
+    ($($name:ident),*) => {
+        $(
+            extern "C" fn $name(a: u64, b: u64) -> u64 {
+                alu::$name(a, b)
+            }
+        )*
+    };
+}
+
+alu_helpers!(mulhsu, div, divu, rem, remu, divw, divuw, remw, remuw);
+
+extern "C" fn load<const N: usize, const SIGNED: bool>(
+    cpu: &mut Cpu,
+    address: u64,
+    value: &mut u64,
+) -> u32 {
+    match cpu.load::<N>(address) {
+        Ok(bytes) => {
+            let mut buffer = [0; 8];
+            buffer[..N].copy_from_slice(&bytes);
+            let unused_bits = 64 - 8 * N as u32;
+            *value = u64::from_le_bytes(buffer);
+            if SIGNED {
+                *value = ((*value << unused_bits) as i64 >> unused_bits) as u64;
+            }
+            FINISHED
+        }
+        Err(exception) => {
+            cpu.jit_exception = Some(exception);
+            EXCEPTION
+        }
+    }
+}
+
+extern "C" fn store<const N: usize>(
+    cpu: &mut Cpu,
+    address: u64,
+    value: u64,
+) -> u32 {
+    let bytes = value.to_le_bytes()[..N].try_into().unwrap();
+    match cpu.store::<N>(address, bytes) {
+        Ok(()) if cpu.code_written || cpu.exit_reason.is_some() => STOPPED,
+        Ok(()) => FINISHED,
+        Err(exception) => {
+            cpu.jit_exception = Some(exception);
+            EXCEPTION
+        }
+    }
+}
+
+extern "C" fn run_op(cpu: &mut Cpu, op: &Op) -> u32 {
+    match cpu.run_op(op) {
+        Ok(())
+            if cpu.code_written
+                || cpu.exit_reason.is_some()
+                || !cpu.is_running() =>
+        {
+            STOPPED
+        }
+        Ok(()) => FINISHED,
+        Err(exception) => {
+            cpu.jit_exception = Some(exception);
+            EXCEPTION
+        }
+    }
+}
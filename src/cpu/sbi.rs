@@ -0,0 +1,423 @@
+//! Built-in implementation of the RISC-V Supervisor Binary Interface, which
+//! lets S-mode payloads like kernels run without any M-mode firmware.
+//!
+//! The extension ID is taken from `a7`, the function ID from `a6` and the
+//! arguments from `a0` through `a5`. An error code is returned in `a0` and a
+//! value in `a1`, except by the legacy extensions, which only return a value
+//! in `a0`.
+//!
+//! Requests aimed at other harts, like IPIs and remote fences, are left in
+//! their [`HartState`] for them to act on before running their next
+//! instruction.
+
+use super::{Cpu, ExitReason, State};
+use crate::{
+    csr::{self, Privilege},
+    register::RegisterName,
+    trap::Interrupt,
+};
+use std::{cell::RefMut, mem};
+
+// Extension IDs
+const EXT_LEGACY_CONSOLE_PUTCHAR: u64 = 0x01;
+const EXT_LEGACY_CONSOLE_GETCHAR: u64 = 0x02;
+const EXT_BASE: u64 = 0x10;
+const EXT_TIME: u64 = 0x5449_4d45;
+const EXT_IPI: u64 = 0x73_5049;
+const EXT_RFENCE: u64 = 0x5246_4e43;
+const EXT_HSM: u64 = 0x48_534d;
+const EXT_SRST: u64 = 0x5352_5354;
+
+const SUPPORTED_EXTENSIONS: [u64; 8] = [
+    EXT_LEGACY_CONSOLE_PUTCHAR,
+    EXT_LEGACY_CONSOLE_GETCHAR,
+    EXT_BASE,
+    EXT_TIME,
+    EXT_IPI,
+    EXT_RFENCE,
+    EXT_HSM,
+    EXT_SRST,
+];
+
+/// Version 2.0 of the SBI specification.
+const SPEC_VERSION: u64 = 2 << 24;
+/// "rv" in ASCII, which isn't taken by any of the implementations listed in
+/// the SBI specification.
+const IMPL_ID: u64 = 0x7276;
+
+// Base extension functions
+const BASE_GET_SPEC_VERSION: u64 = 0;
+const BASE_GET_IMPL_ID: u64 = 1;
+const BASE_GET_IMPL_VERSION: u64 = 2;
+const BASE_PROBE_EXTENSION: u64 = 3;
+const BASE_GET_MVENDORID: u64 = 4;
+const BASE_GET_MARCHID: u64 = 5;
+const BASE_GET_MIMPID: u64 = 6;
+
+const TIME_SET_TIMER: u64 = 0;
+
+const IPI_SEND_IPI: u64 = 0;
+
+// RFENCE extension functions. The rest are for hypervisors.
+const RFENCE_REMOTE_FENCE_I: u64 = 0;
+const RFENCE_REMOTE_SFENCE_VMA: u64 = 1;
+const RFENCE_REMOTE_SFENCE_VMA_ASID: u64 = 2;
+
+// HSM extension functions
+const HSM_HART_START: u64 = 0;
+const HSM_HART_STOP: u64 = 1;
+const HSM_HART_GET_STATUS: u64 = 2;
+const HSM_HART_SUSPEND: u64 = 3;
+
+// Values returned by `sbi_hart_get_status`
+const HART_STATE_STARTED: u64 = 0;
+const HART_STATE_STOPPED: u64 = 1;
+const HART_STATE_START_PENDING: u64 = 2;
+const HART_STATE_SUSPENDED: u64 = 4;
+const SUSPEND_DEFAULT_RETENTIVE: u64 = 0;
+const SUSPEND_DEFAULT_NON_RETENTIVE: u64 = 0x8000_0000;
+
+const SRST_SYSTEM_RESET: u64 = 0;
+
+const RESET_TYPE_SHUTDOWN: u64 = 0;
+const RESET_TYPE_COLD_REBOOT: u64 = 1;
+const RESET_TYPE_WARM_REBOOT: u64 = 2;
+const RESET_REASON_SYSTEM_FAILURE: u64 = 1;
+
+/// The error codes that SBI calls return in `a0`.
+#[derive(Debug, Clone, Copy)]
+enum SbiError {
+    Failed = -1,
+    NotSupported = -2,
+    InvalidParam = -3,
+    AlreadyAvailable = -6,
+}
+
+type SbiResult = Result<u64, SbiError>;
+
+/// What the SBI keeps track of for a hart, including requests from other
+/// harts.
+#[derive(Clone, Default)]
+pub struct HartState {
+    status: HartStatus,
+    /// An IPI has been sent to the hart.
+    ipi: bool,
+    /// The hart has been asked to flush its TLB.
+    flush_tlb: bool,
+}
+
+/// The states of the hart state management extension.
+#[derive(Clone, Copy, Default)]
+enum HartStatus {
+    #[default]
+    Started,
+    Stopped,
+    StartPending {
+        start_addr: u64,
+        opaque: u64,
+    },
+    /// Waiting for an interrupt, after which a non-retentive suspend resumes
+    /// at the given address with the given opaque value.
+    Suspended {
+        resume: Option<(u64, u64)>,
+    },
+}
+
+impl Cpu {
+    /// Sets up the hart like SBI firmware does before jumping to an S-mode
+    /// payload. Only hart 0 runs the payload, while the others wait to be
+    /// started.
+    pub(super) fn enter_sbi_payload(&mut self) {
+        if self.hart_id != 0 {
+            self.state = State::Stopped;
+            self.sbi_hart_mut().status = HartStatus::Stopped;
+        }
+        self.privilege = Privilege::Supervisor;
+        // Without any M-mode software to look at them, every trap that S-mode
+        // can handle goes straight there
+        for csr in [csr::MEDELEG, csr::MIDELEG] {
+            self.csrs.write(csr, u64::MAX, Privilege::Machine);
+        }
+        // Let S-mode read the time and the other counters
+        self.csrs
+            .write(csr::MCOUNTEREN, u64::MAX, Privilege::Machine);
+    }
+
+    /// Acts on the requests that other harts have made through the SBI.
+    pub(super) fn handle_sbi_requests(&mut self) {
+        let mut hart = self.sbi_hart_mut();
+        let ipi = mem::take(&mut hart.ipi);
+        let flush_tlb = mem::take(&mut hart.flush_tlb);
+        let start = match hart.status {
+            HartStatus::StartPending { start_addr, opaque } => {
+                hart.status = HartStatus::Started;
+                Some((start_addr, opaque))
+            }
+            _ => None,
+        };
+        drop(hart);
+        if ipi {
+            self.csrs.mip |= Interrupt::SupervisorSoftware.bit();
+        }
+        if flush_tlb {
+            self.mmu.flush();
+        }
+        if let Some((start_addr, opaque)) = start {
+            self.start_in_supervisor(start_addr, opaque);
+        }
+    }
+
+    /// Finishes a suspend once the hart has been woken up by an interrupt.
+    pub(super) fn resume_from_suspend(&mut self) {
+        let mut hart = self.sbi_hart_mut();
+        let HartStatus::Suspended { resume } = hart.status else {
+            return;
+        };
+        hart.status = HartStatus::Started;
+        drop(hart);
+        if let Some((resume_addr, opaque)) = resume {
+            self.start_in_supervisor(resume_addr, opaque);
+        }
+    }
+
+    /// Puts the hart in the state that a hart is in right after being
+    /// started through the SBI.
+    fn start_in_supervisor(&mut self, start_addr: u64, opaque: u64) {
+        self.state = State::Running;
+        self.privilege = Privilege::Supervisor;
+        self.pc = start_addr;
+        self[RegisterName::A0] = self.hart_id as u64;
+        self[RegisterName::A1] = opaque;
+        self.csrs.mstatus &= !csr::MSTATUS_SIE;
+        self.csrs.satp = 0;
+        self.mmu.flush();
+    }
+
+    fn sbi_hart_mut(&self) -> RefMut<'_, HartState> {
+        RefMut::map(self.shared.borrow_mut(), |shared| {
+            &mut shared.sbi_harts[self.hart_id]
+        })
+    }
+
+    /// Handles an `ecall` from S-mode.
+    pub(super) fn sbi_call(&mut self) {
+        let extension = self[RegisterName::A7];
+        let function = self[RegisterName::A6];
+        let args = [
+            RegisterName::A0,
+            RegisterName::A1,
+            RegisterName::A2,
+            RegisterName::A3,
+            RegisterName::A4,
+            RegisterName::A5,
+        ]
+        .map(|reg| self[reg]);
+        let result = match extension {
+            EXT_LEGACY_CONSOLE_PUTCHAR => {
+                self.virt_mut().uart.send(args[0] as u8);
+                self[RegisterName::A0] = 0;
+                return;
+            }
+            EXT_LEGACY_CONSOLE_GETCHAR => {
+                let byte = self.virt_mut().uart.receive();
+                self[RegisterName::A0] = byte.map_or(u64::MAX, u64::from);
+                return;
+            }
+            EXT_BASE => self.sbi_base(function, args),
+            EXT_TIME => self.sbi_time(function, args),
+            EXT_IPI => self.sbi_ipi(function, args),
+            EXT_RFENCE => self.sbi_rfence(function, args),
+            EXT_HSM => self.sbi_hsm(function, args),
+            EXT_SRST => self.sbi_srst(function, args),
+            _ => Err(SbiError::NotSupported),
+        };
+        if self.opts.verbose {
+            eprintln!(
+                "SBI call 0x{extension:x}/{function} returned {result:?}"
+            );
+        }
+        let (error, value) = match result {
+            Ok(value) => (0, value),
+            Err(error) => (error as i64 as u64, 0),
+        };
+        self[RegisterName::A0] = error;
+        self[RegisterName::A1] = value;
+    }
+
+    fn sbi_base(&self, function: u64, args: [u64; 6]) -> SbiResult {
+        let read_csr = |csr| {
+            Ok(self
+                .csrs
+                .read(csr, Privilege::Machine)
+                .expect("M-mode can read every CSR"))
+        };
+        match function {
+            BASE_GET_SPEC_VERSION => Ok(SPEC_VERSION),
+            BASE_GET_IMPL_ID => Ok(IMPL_ID),
+            BASE_GET_IMPL_VERSION => Ok(impl_version()),
+            BASE_PROBE_EXTENSION => {
+                Ok(SUPPORTED_EXTENSIONS.contains(&args[0]).into())
+            }
+            BASE_GET_MVENDORID => read_csr(csr::MVENDORID),
+            BASE_GET_MARCHID => read_csr(csr::MARCHID),
+            BASE_GET_MIMPID => read_csr(csr::MIMPID),
+            _ => Err(SbiError::NotSupported),
+        }
+    }
+
+    fn sbi_time(&mut self, function: u64, args: [u64; 6]) -> SbiResult {
+        if function != TIME_SET_TIMER {
+            return Err(SbiError::NotSupported);
+        }
+        self.virt_mut().clint.set_mtimecmp(self.hart_id, args[0]);
+        // The timer interrupt stays pending until the next time the timer is
+        // set, since only M-mode can clear it
+        self.csrs.mip &= !Interrupt::SupervisorTimer.bit();
+        self.update_interrupts();
+        Ok(0)
+    }
+
+    fn sbi_ipi(&mut self, function: u64, args: [u64; 6]) -> SbiResult {
+        if function != IPI_SEND_IPI {
+            return Err(SbiError::NotSupported);
+        }
+        let harts = self.selected_harts(args[0], args[1])?;
+        let mut shared = self.shared.borrow_mut();
+        for hart in harts {
+            shared.sbi_harts[hart].ipi = true;
+        }
+        Ok(0)
+    }
+
+    fn sbi_rfence(&mut self, function: u64, args: [u64; 6]) -> SbiResult {
+        let harts = self.selected_harts(args[0], args[1])?;
+        match function {
+            // Every hart uses the same decoded instructions
+            RFENCE_REMOTE_FENCE_I => {
+                self.shared.borrow_mut().flush_code_caches();
+            }
+            // Address space identifiers aren't tracked, so everything is
+            // flushed
+            RFENCE_REMOTE_SFENCE_VMA | RFENCE_REMOTE_SFENCE_VMA_ASID => {
+                let mut shared = self.shared.borrow_mut();
+                for hart in harts {
+                    shared.sbi_harts[hart].flush_tlb = true;
+                }
+            }
+            _ => return Err(SbiError::NotSupported),
+        }
+        Ok(0)
+    }
+
+    fn sbi_hsm(&mut self, function: u64, args: [u64; 6]) -> SbiResult {
+        match function {
+            HSM_HART_START | HSM_HART_GET_STATUS
+                if args[0] >= self.opts.harts as u64 =>
+            {
+                Err(SbiError::InvalidParam)
+            }
+            HSM_HART_START => {
+                let mut shared = self.shared.borrow_mut();
+                let hart = &mut shared.sbi_harts[args[0] as usize];
+                match hart.status {
+                    HartStatus::Stopped => {
+                        hart.status = HartStatus::StartPending {
+                            start_addr: args[1],
+                            opaque: args[2],
+                        };
+                        Ok(0)
+                    }
+                    _ => Err(SbiError::AlreadyAvailable),
+                }
+            }
+            HSM_HART_GET_STATUS => {
+                let shared = self.shared.borrow();
+                Ok(match shared.sbi_harts[args[0] as usize].status {
+                    HartStatus::Started => HART_STATE_STARTED,
+                    HartStatus::Stopped => HART_STATE_STOPPED,
+                    HartStatus::StartPending { .. } => HART_STATE_START_PENDING,
+                    HartStatus::Suspended { .. } => HART_STATE_SUSPENDED,
+                })
+            }
+            HSM_HART_STOP => {
+                let others_started =
+                    self.shared.borrow().sbi_harts.iter().enumerate().any(
+                        |(hart, state)| {
+                            hart != self.hart_id
+                                && !matches!(state.status, HartStatus::Stopped)
+                        },
+                    );
+                // Nothing would be left to start the hart again
+                if !others_started {
+                    return Err(SbiError::Failed);
+                }
+                self.sbi_hart_mut().status = HartStatus::Stopped;
+                self.state = State::Stopped;
+                Ok(0)
+            }
+            HSM_HART_SUSPEND => {
+                let resume = match args[0] {
+                    SUSPEND_DEFAULT_RETENTIVE => None,
+                    // Resume like a hart that has just been started
+                    SUSPEND_DEFAULT_NON_RETENTIVE => Some((args[1], args[2])),
+                    _ => return Err(SbiError::InvalidParam),
+                };
+                self.sbi_hart_mut().status = HartStatus::Suspended { resume };
+                self.wait_for_interrupt();
+                Ok(0)
+            }
+            _ => Err(SbiError::NotSupported),
+        }
+    }
+
+    /// Lists the harts that a hart mask refers to. A base of `-1` means all
+    /// harts.
+    fn selected_harts(
+        &self,
+        mask: u64,
+        base: u64,
+    ) -> Result<Vec<usize>, SbiError> {
+        let num_harts = self.opts.harts;
+        if base == u64::MAX {
+            return Ok((0..num_harts).collect());
+        }
+        (0..64)
+            .filter(|bit| mask >> bit & 1 == 1)
+            .map(|bit| {
+                base.checked_add(bit)
+                    .and_then(|hart| usize::try_from(hart).ok())
+                    .filter(|&hart| hart < num_harts)
+                    .ok_or(SbiError::InvalidParam)
+            })
+            .collect()
+    }
+
+    fn sbi_srst(&mut self, function: u64, args: [u64; 6]) -> SbiResult {
+        if function != SRST_SYSTEM_RESET {
+            return Err(SbiError::NotSupported);
+        }
+        let status = i32::from(args[1] == RESET_REASON_SYSTEM_FAILURE);
+        match args[0] {
+            // Resetting isn't supported, so the machine is stopped instead
+            RESET_TYPE_SHUTDOWN
+            | RESET_TYPE_COLD_REBOOT
+            | RESET_TYPE_WARM_REBOOT => {
+                self.exit_reason = Some(ExitReason::Shutdown(status));
+                Ok(0)
+            }
+            _ => Err(SbiError::InvalidParam),
+        }
+    }
+}
+
+/// The version of the emulator, as `major << 16 | minor << 8 | patch`.
+fn impl_version() -> u64 {
+    [
+        env!("CARGO_PKG_VERSION_MAJOR"),
+        env!("CARGO_PKG_VERSION_MINOR"),
+        env!("CARGO_PKG_VERSION_PATCH"),
+    ]
+    .map(|part| part.parse::<u64>().unwrap_or(0))
+    .into_iter()
+    .fold(0, |version, part| version << 8 | part)
+}
@@ -0,0 +1,472 @@
+//! Control and status registers.
+
+use crate::mmu::{SATP_MODE_BARE, SATP_MODE_SV39, SATP_MODE_SV48};
+use std::time::Instant;
+
+pub const FFLAGS: u16 = 0x001;
+pub const FRM: u16 = 0x002;
+pub const FCSR: u16 = 0x003;
+pub const CYCLE: u16 = 0xc00;
+pub const TIME: u16 = 0xc01;
+pub const INSTRET: u16 = 0xc02;
+pub const SSTATUS: u16 = 0x100;
+pub const SIE: u16 = 0x104;
+pub const STVEC: u16 = 0x105;
+pub const SCOUNTEREN: u16 = 0x106;
+pub const SSCRATCH: u16 = 0x140;
+pub const SEPC: u16 = 0x141;
+pub const SCAUSE: u16 = 0x142;
+pub const STVAL: u16 = 0x143;
+pub const SIP: u16 = 0x144;
+pub const SATP: u16 = 0x180;
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MEDELEG: u16 = 0x302;
+pub const MIDELEG: u16 = 0x303;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MCOUNTEREN: u16 = 0x306;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const MCYCLE: u16 = 0xb00;
+pub const MINSTRET: u16 = 0xb02;
+pub const MVENDORID: u16 = 0xf11;
+pub const MARCHID: u16 = 0xf12;
+pub const MIMPID: u16 = 0xf13;
+pub const MHARTID: u16 = 0xf14;
+
+// Fields of `mstatus`
+pub const MSTATUS_SIE: u64 = 1 << 1;
+pub const MSTATUS_MIE: u64 = 1 << 3;
+pub const MSTATUS_SPIE: u64 = 1 << 5;
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+pub const MSTATUS_SPP: u64 = 1 << 8;
+pub const MSTATUS_MPP: u64 = 0b11 << 11;
+pub const MSTATUS_FS: u64 = 0b11 << 13;
+pub const MSTATUS_FS_INITIAL: u64 = 0b01 << 13;
+pub const MSTATUS_MPRV: u64 = 1 << 17;
+pub const MSTATUS_SUM: u64 = 1 << 18;
+pub const MSTATUS_MXR: u64 = 1 << 19;
+pub const MSTATUS_TVM: u64 = 1 << 20;
+pub const MSTATUS_TW: u64 = 1 << 21;
+pub const MSTATUS_TSR: u64 = 1 << 22;
+pub const MSTATUS_UXL: u64 = 0b11 << 32;
+pub const MSTATUS_SD: u64 = 1 << 63;
+
+/// The parts of `mstatus` that are visible through `sstatus`.
+const SSTATUS_MASK: u64 = MSTATUS_SIE
+    | MSTATUS_SPIE
+    | MSTATUS_SPP
+    | MSTATUS_FS
+    | MSTATUS_SUM
+    | MSTATUS_MXR
+    | MSTATUS_UXL
+    | MSTATUS_SD;
+
+/// The parts of `mstatus` that can be written to.
+const MSTATUS_WRITABLE: u64 = MSTATUS_SIE
+    | MSTATUS_MIE
+    | MSTATUS_SPIE
+    | MSTATUS_MPIE
+    | MSTATUS_SPP
+    | MSTATUS_MPP
+    | MSTATUS_FS
+    | MSTATUS_MPRV
+    | MSTATUS_SUM
+    | MSTATUS_MXR
+    | MSTATUS_TVM
+    | MSTATUS_TW
+    | MSTATUS_TSR;
+
+/// Interrupts that M-mode can set or clear in `mip`. The machine-level ones
+/// are driven by devices instead.
+const MIP_WRITABLE: u64 = 1 << 1 | 1 << 5 | 1 << 9;
+/// Only supervisor software interrupts can be raised through `sip`.
+const SIP_WRITABLE: u64 = 1 << 1;
+/// Interrupts that exist at all, and can thus be enabled or delegated.
+const SUPERVISOR_INTERRUPTS: u64 = 1 << 1 | 1 << 5 | 1 << 9;
+const ALL_INTERRUPTS: u64 = SUPERVISOR_INTERRUPTS | 1 << 3 | 1 << 7 | 1 << 11;
+/// Every exception except environment calls from M-mode can be delegated.
+const MEDELEG_WRITABLE: u64 = 0xb3ff;
+
+/// Frequency at which the `time` CSR counts up.
+pub const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+/// `MXL` = 64 bits, along with one bit per supported extension.
+pub const MISA_VALUE: u64 = 2 << 62
+    | isa_bit(b'I')
+    | isa_bit(b'M')
+    | isa_bit(b'A')
+    | isa_bit(b'F')
+    | isa_bit(b'D')
+    | isa_bit(b'C')
+    | isa_bit(b'S')
+    | isa_bit(b'U');
+
+pub const fn isa_bit(extension: u8) -> u64 {
+    1 << (extension - b'A')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl Privilege {
+    /// Decodes a privilege level from a field such as `mstatus.MPP`.
+    /// The reserved value 2 has no meaning.
+    const fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(Self::User),
+            1 => Some(Self::Supervisor),
+            3 => Some(Self::Machine),
+            _ => None,
+        }
+    }
+}
+
+pub struct CsrFile {
+    pub mstatus: u64,
+    pub medeleg: u64,
+    pub mideleg: u64,
+    pub mie: u64,
+    pub mip: u64,
+    pub mtvec: u64,
+    pub mepc: u64,
+    pub mcause: u64,
+    pub mtval: u64,
+    mscratch: u64,
+    mcounteren: u64,
+    pub stvec: u64,
+    pub sepc: u64,
+    pub scause: u64,
+    pub stval: u64,
+    sscratch: u64,
+    scounteren: u64,
+    pub satp: u64,
+    /// Accrued floating point exceptions.
+    fflags: u64,
+    /// Dynamic rounding mode.
+    frm: u64,
+    /// Number of instructions retired so far. Every instruction takes a single
+    /// cycle, so this doubles as the cycle counter.
+    pub instret: u64,
+    epoch: Instant,
+    mhartid: u64,
+}
+
+impl CsrFile {
+    /// Creates the CSRs of a hart whose real-time counter started counting
+    /// at `epoch`.
+    pub fn new(epoch: Instant, hart_id: u64) -> Self {
+        Self {
+            // XLEN is 64 bits in every mode
+            mstatus: 2 << 32 | 2 << 34,
+            medeleg: 0,
+            mideleg: 0,
+            mie: 0,
+            mip: 0,
+            mtvec: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mscratch: 0,
+            mcounteren: 0,
+            stvec: 0,
+            sepc: 0,
+            scause: 0,
+            stval: 0,
+            sscratch: 0,
+            scounteren: 0,
+            satp: 0,
+            fflags: 0,
+            frm: 0,
+            instret: 0,
+            epoch,
+            mhartid: hart_id,
+        }
+    }
+
+    /// Reads a CSR, returning `None` if it doesn't exist or isn't accessible
+    /// from the given privilege level.
+    pub fn read(&self, csr: u16, privilege: Privilege) -> Option<u64> {
+        if !self.is_accessible(csr, privilege) {
+            return None;
+        }
+        Some(match csr {
+            FFLAGS => self.fflags,
+            FRM => self.frm,
+            FCSR => self.frm << 5 | self.fflags,
+            CYCLE | INSTRET | MCYCLE | MINSTRET => self.instret,
+            TIME => self.time(),
+            SSTATUS => self.mstatus() & SSTATUS_MASK,
+            SIE => self.mie & self.mideleg,
+            STVEC => self.stvec,
+            SCOUNTEREN => self.scounteren,
+            SSCRATCH => self.sscratch,
+            SEPC => self.sepc,
+            SCAUSE => self.scause,
+            STVAL => self.stval,
+            SIP => self.mip & self.mideleg,
+            SATP => self.satp,
+            MSTATUS => self.mstatus(),
+            MISA => MISA_VALUE,
+            MEDELEG => self.medeleg,
+            MIDELEG => self.mideleg,
+            MIE => self.mie,
+            MTVEC => self.mtvec,
+            MCOUNTEREN => self.mcounteren,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MIP => self.mip,
+            MHARTID => self.mhartid,
+            MVENDORID | MARCHID | MIMPID => 0,
+            _ => return None,
+        })
+    }
+
+    /// Writes a CSR, returning `None` if it doesn't exist, is read-only or
+    /// isn't accessible from the given privilege level.
+    pub fn write(
+        &mut self,
+        csr: u16,
+        value: u64,
+        privilege: Privilege,
+    ) -> Option<()> {
+        // The top two bits of the address being set means read-only
+        if csr >> 10 == 0b11 || !self.is_accessible(csr, privilege) {
+            return None;
+        }
+        match csr {
+            FFLAGS => {
+                self.fflags = value & 0x1f;
+                self.set_fp_dirty();
+            }
+            FRM => {
+                self.frm = value & 0b111;
+                self.set_fp_dirty();
+            }
+            FCSR => {
+                self.fflags = value & 0x1f;
+                self.frm = value >> 5 & 0b111;
+                self.set_fp_dirty();
+            }
+            MCYCLE | MINSTRET => self.instret = value,
+            SSTATUS => self.set_mstatus(
+                self.mstatus & !SSTATUS_MASK | value & SSTATUS_MASK,
+            ),
+            SIE => {
+                self.mie = self.mie & !self.mideleg | value & self.mideleg;
+            }
+            STVEC => self.stvec = legalize_tvec(value),
+            SCOUNTEREN => self.scounteren = value & 0b111,
+            SSCRATCH => self.sscratch = value,
+            SEPC => self.sepc = value & !1,
+            SCAUSE => self.scause = value,
+            STVAL => self.stval = value,
+            SIP => {
+                let mask = SIP_WRITABLE & self.mideleg;
+                self.mip = self.mip & !mask | value & mask;
+            }
+            // Writes with an unsupported mode have no effect at all
+            SATP => {
+                if let SATP_MODE_BARE | SATP_MODE_SV39 | SATP_MODE_SV48 =
+                    value >> 60
+                {
+                    self.satp = value;
+                }
+            }
+            MSTATUS => self.set_mstatus(value),
+            // The set of extensions is fixed, so `misa` can't be changed
+            MISA => {}
+            MEDELEG => self.medeleg = value & MEDELEG_WRITABLE,
+            MIDELEG => self.mideleg = value & SUPERVISOR_INTERRUPTS,
+            MIE => self.mie = value & ALL_INTERRUPTS,
+            MTVEC => self.mtvec = legalize_tvec(value),
+            MCOUNTEREN => self.mcounteren = value & 0b111,
+            MSCRATCH => self.mscratch = value,
+            // IALIGN is 16 bits, so only the lowest bit is cleared
+            MEPC => self.mepc = value & !1,
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            MIP => self.mip = self.mip & !MIP_WRITABLE | value & MIP_WRITABLE,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Updates the trap CSRs of the `target` privilege level when taking a
+    /// trap from `privilege`, returning the address of the trap handler.
+    pub fn enter_trap(
+        &mut self,
+        target: Privilege,
+        privilege: Privilege,
+        cause: u64,
+        tval: u64,
+        epc: u64,
+    ) -> u64 {
+        let tvec = if target == Privilege::Supervisor {
+            self.sepc = epc;
+            self.scause = cause;
+            self.stval = tval;
+            let sie = self.mstatus & MSTATUS_SIE != 0;
+            self.mstatus &= !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP);
+            if sie {
+                self.mstatus |= MSTATUS_SPIE;
+            }
+            if privilege != Privilege::User {
+                self.mstatus |= MSTATUS_SPP;
+            }
+            self.stvec
+        } else {
+            self.mepc = epc;
+            self.mcause = cause;
+            self.mtval = tval;
+            let mie = self.mstatus & MSTATUS_MIE != 0;
+            self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+            if mie {
+                self.mstatus |= MSTATUS_MPIE;
+            }
+            self.mstatus |= (privilege as u64) << 11;
+            self.mtvec
+        };
+        let base = tvec & !0b11;
+        // Vectored mode only applies to interrupts
+        if tvec & 1 == 1 && cause >> 63 == 1 {
+            base.wrapping_add((cause & 0x3f) * 4)
+        } else {
+            base
+        }
+    }
+
+    /// Restores the state saved by a trap into M-mode, returning the address
+    /// and privilege level to return to.
+    pub fn mret(&mut self) -> (u64, Privilege) {
+        let mpp = self.mpp();
+        let mpie = self.mstatus & MSTATUS_MPIE != 0;
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPP);
+        self.mstatus |= MSTATUS_MPIE;
+        if mpie {
+            self.mstatus |= MSTATUS_MIE;
+        }
+        if mpp != Privilege::Machine {
+            self.mstatus &= !MSTATUS_MPRV;
+        }
+        (self.mepc, mpp)
+    }
+
+    /// Restores the state saved by a trap into S-mode, returning the address
+    /// and privilege level to return to.
+    pub fn sret(&mut self) -> (u64, Privilege) {
+        let spp = if self.mstatus & MSTATUS_SPP == 0 {
+            Privilege::User
+        } else {
+            Privilege::Supervisor
+        };
+        let spie = self.mstatus & MSTATUS_SPIE != 0;
+        self.mstatus &= !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV);
+        self.mstatus |= MSTATUS_SPIE;
+        if spie {
+            self.mstatus |= MSTATUS_SIE;
+        }
+        (self.sepc, spp)
+    }
+
+    /// Whether floating point instructions are enabled by `mstatus.FS`.
+    pub const fn fp_enabled(&self) -> bool {
+        self.mstatus & MSTATUS_FS != 0
+    }
+
+    /// Records in `mstatus.FS` that the floating point state has changed.
+    pub fn set_fp_dirty(&mut self) {
+        self.mstatus |= MSTATUS_FS;
+    }
+
+    /// Adds to the exception flags that have accrued in `fflags`.
+    pub fn accrue_fp_exceptions(&mut self, flags: u8) {
+        if flags != 0 {
+            self.fflags |= u64::from(flags);
+            self.set_fp_dirty();
+        }
+    }
+
+    /// The dynamic rounding mode, which may hold an invalid value.
+    pub const fn frm(&self) -> u64 {
+        self.frm
+    }
+
+    /// The previous privilege level saved in `mstatus.MPP`.
+    pub fn mpp(&self) -> Privilege {
+        Privilege::from_bits((self.mstatus & MSTATUS_MPP) >> 11)
+            .unwrap_or(Privilege::User)
+    }
+
+    /// `mstatus` with the read-only summary bit filled in.
+    const fn mstatus(&self) -> u64 {
+        if self.mstatus & MSTATUS_FS == MSTATUS_FS {
+            self.mstatus | MSTATUS_SD
+        } else {
+            self.mstatus
+        }
+    }
+
+    fn set_mstatus(&mut self, value: u64) {
+        let old_mpp = self.mstatus & MSTATUS_MPP;
+        self.mstatus =
+            self.mstatus & !MSTATUS_WRITABLE | value & MSTATUS_WRITABLE;
+        // `MPP` is WARL, so the reserved privilege level is ignored
+        if Privilege::from_bits((value & MSTATUS_MPP) >> 11).is_none() {
+            self.mstatus = self.mstatus & !MSTATUS_MPP | old_mpp;
+        }
+    }
+
+    /// Checks the privilege level encoded in the address of a CSR, and for
+    /// the user-level counters, whether higher levels have enabled access.
+    fn is_accessible(&self, csr: u16, privilege: Privilege) -> bool {
+        if (csr >> 8 & 0b11) as u8 > privilege as u8 {
+            return false;
+        }
+        // `mstatus.TVM` traps S-mode attempts at managing virtual memory
+        if csr == SATP
+            && privilege == Privilege::Supervisor
+            && self.mstatus & MSTATUS_TVM != 0
+        {
+            return false;
+        }
+        if let FFLAGS | FRM | FCSR = csr {
+            return self.fp_enabled();
+        }
+        if let CYCLE | TIME | INSTRET = csr {
+            let bit = 1 << (csr - CYCLE);
+            return match privilege {
+                Privilege::Machine => true,
+                Privilege::Supervisor => self.mcounteren & bit != 0,
+                Privilege::User => self.mcounteren & self.scounteren & bit != 0,
+            };
+        }
+        true
+    }
+
+    /// The current value of the real-time counter.
+    fn time(&self) -> u64 {
+        (self.epoch.elapsed().as_nanos() * u128::from(TIMEBASE_FREQUENCY)
+            / 1_000_000_000) as u64
+    }
+}
+
+/// Only direct and vectored mode exist, so the reserved modes are turned into
+/// direct mode.
+const fn legalize_tvec(value: u64) -> u64 {
+    if value & 0b11 > 1 {
+        value & !0b11
+    } else {
+        value
+    }
+}
@@ -0,0 +1,76 @@
+//! Instructions that have already been decoded, so that running them again
+//! doesn't go through the decoder.
+//!
+//! Instructions are looked up by physical address, after `pc` has been
+//! translated as usual. Memory watches the pages that they were decoded
+//! from, and those pages are dropped from the cache as soon as anything
+//! writes to them. Instructions that cross a page boundary aren't cached,
+//! since their second half depends on how the next page is mapped.
+
+use crate::instruction::Instruction;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Number of instructions that can be cached at once. Each page maps to a
+/// contiguous run of slots, so this needs to be a multiple of how many
+/// instructions fit in a page.
+const SLOTS: usize = 1 << 16;
+const SLOTS_PER_PAGE: usize = PAGE_SIZE as usize / 2;
+
+#[derive(Clone, Copy)]
+pub struct Decoded {
+    pub instruction: Instruction,
+    /// The raw encoding, which also tells how long the instruction is.
+    pub bits: u32,
+}
+
+/// A direct-mapped table of instructions, indexed by the low bits of their
+/// address.
+pub struct DecodeCache {
+    /// Each instruction along with its full physical address.
+    slots: Box<[Option<(u64, Decoded)>]>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self {
+            slots: vec![None; SLOTS].into_boxed_slice(),
+        }
+    }
+}
+
+impl DecodeCache {
+    pub fn get(&self, physical: u64) -> Option<Decoded> {
+        match self.slots[slot(physical)] {
+            Some((address, decoded)) if address == physical => Some(decoded),
+            _ => None,
+        }
+    }
+
+    /// Caches an instruction, unless it crosses into the next page.
+    pub fn insert(&mut self, physical: u64, decoded: Decoded) {
+        let length = if decoded.bits & 0b11 == 0b11 { 4 } else { 2 };
+        if physical % PAGE_SIZE + length <= PAGE_SIZE {
+            self.slots[slot(physical)] = Some((physical, decoded));
+        }
+    }
+
+    /// Forgets the instructions in the page that contains `physical`.
+    pub fn invalidate_page(&mut self, physical: u64) {
+        let page = physical / PAGE_SIZE;
+        let first = slot(page * PAGE_SIZE);
+        for entry in &mut self.slots[first..first + SLOTS_PER_PAGE] {
+            if entry.is_some_and(|(address, _)| address / PAGE_SIZE == page) {
+                *entry = None;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.fill(None);
+    }
+}
+
+const fn slot(physical: u64) -> usize {
+    (physical / 2) as usize % SLOTS
+}
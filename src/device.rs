@@ -0,0 +1,16 @@
+//! Memory-mapped devices found on emulated machines.
+
+pub mod clint;
+pub mod finisher;
+pub mod plic;
+pub mod uart;
+
+/// A device whose registers are accessed through loads and stores.
+///
+/// Offsets are relative to the base address of the device, and accesses are
+/// 1, 2, 4 or 8 bytes wide. Returning `None` results in an access fault.
+pub trait Device {
+    fn read(&mut self, offset: u64, size: usize) -> Option<u64>;
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Option<()>;
+}
@@ -0,0 +1,116 @@
+//! Core-local interruptor, which provides the machine timer and software
+//! interrupts.
+//!
+//! Every hart has its own `msip` and `mtimecmp` registers, while `mtime` is
+//! shared between them.
+
+use super::Device;
+use crate::csr::TIMEBASE_FREQUENCY;
+use std::time::{Duration, Instant};
+
+const MSIP: u64 = 0x0000;
+const MTIMECMP: u64 = 0x4000;
+const MTIME: u64 = 0xbff8;
+const MTIME_END: u64 = MTIME + 8;
+
+pub struct Clint {
+    /// When `mtime` was zero.
+    epoch: Instant,
+    /// Per hart.
+    msip: Vec<bool>,
+    /// Per hart.
+    mtimecmp: Vec<u64>,
+}
+
+impl Clint {
+    pub fn new(epoch: Instant, harts: usize) -> Self {
+        Self {
+            epoch,
+            msip: vec![false; harts],
+            mtimecmp: vec![u64::MAX; harts],
+        }
+    }
+
+    pub const fn epoch(&self) -> Instant {
+        self.epoch
+    }
+
+    pub fn mtime(&self) -> u64 {
+        (self.epoch.elapsed().as_nanos() * u128::from(TIMEBASE_FREQUENCY)
+            / 1_000_000_000) as u64
+    }
+
+    pub fn set_mtimecmp(&mut self, hart: usize, value: u64) {
+        self.mtimecmp[hart] = value;
+    }
+
+    pub fn software_interrupt(&self, hart: usize) -> bool {
+        self.msip[hart]
+    }
+
+    pub fn timer_interrupt(&self, hart: usize) -> bool {
+        self.mtime() >= self.mtimecmp[hart]
+    }
+
+    /// How long it takes until the timer interrupt of a hart fires.
+    pub fn time_until_interrupt(&self, hart: usize) -> Duration {
+        let ticks = self.mtimecmp[hart].saturating_sub(self.mtime());
+        Duration::from_nanos(
+            (u128::from(ticks) * 1_000_000_000
+                / u128::from(TIMEBASE_FREQUENCY))
+            .try_into()
+            .unwrap_or(u64::MAX),
+        )
+    }
+}
+
+impl Device for Clint {
+    fn read(&mut self, offset: u64, size: usize) -> Option<u64> {
+        let (register, value) = match offset & !0b111 {
+            MTIME => (MTIME, self.mtime()),
+            MSIP..MTIMECMP => {
+                let hart = ((offset - MSIP) / 4) as usize;
+                (offset & !0b11, u64::from(*self.msip.get(hart)?))
+            }
+            MTIMECMP..MTIME => {
+                let hart = ((offset - MTIMECMP) / 8) as usize;
+                (offset & !0b111, *self.mtimecmp.get(hart)?)
+            }
+            _ => return None,
+        };
+        // The 64-bit registers can also be accessed one half at a time
+        match (offset - register, size) {
+            (0, 8) if register >= MTIMECMP => Some(value),
+            (0, 4) => Some(value & 0xffff_ffff),
+            (4, 4) => Some(value >> 32),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Option<()> {
+        match offset {
+            MSIP..MTIMECMP => {
+                let hart = ((offset - MSIP) / 4) as usize;
+                if size != 4 || !offset.is_multiple_of(4) {
+                    return None;
+                }
+                *self.msip.get_mut(hart)? = value & 1 == 1;
+            }
+            MTIMECMP..MTIME => {
+                let hart = ((offset - MTIMECMP) / 8) as usize;
+                let mtimecmp = self.mtimecmp.get_mut(hart)?;
+                *mtimecmp = match (offset % 8, size) {
+                    (0, 8) => value,
+                    (0, 4) => *mtimecmp & !0xffff_ffff | value,
+                    (4, 4) => *mtimecmp & 0xffff_ffff | value << 32,
+                    _ => return None,
+                };
+            }
+            // The timer is driven by the host's clock, so writes to `mtime`
+            // are ignored
+            MTIME..MTIME_END => {}
+            _ => return None,
+        }
+        Some(())
+    }
+}
@@ -0,0 +1,40 @@
+//! SiFive test device, which lets the guest power off the machine.
+
+use super::Device;
+
+const FINISHER_FAIL: u64 = 0x3333;
+const FINISHER_PASS: u64 = 0x5555;
+const FINISHER_RESET: u64 = 0x7777;
+
+#[derive(Default)]
+pub struct Finisher {
+    /// The exit status requested by the guest.
+    status: Option<i32>,
+}
+
+impl Finisher {
+    pub fn take_status(&mut self) -> Option<i32> {
+        self.status.take()
+    }
+}
+
+impl Device for Finisher {
+    fn read(&mut self, offset: u64, size: usize) -> Option<u64> {
+        (offset == 0 && size == 4).then_some(0)
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Option<()> {
+        if offset != 0 || size != 4 {
+            return None;
+        }
+        // The upper half holds the exit code on failure
+        match value & 0xffff {
+            FINISHER_PASS => self.status = Some(0),
+            FINISHER_FAIL => self.status = Some((value >> 16) as i32),
+            // Resetting isn't supported, so the machine is stopped instead
+            FINISHER_RESET => self.status = Some(0),
+            _ => {}
+        }
+        Some(())
+    }
+}
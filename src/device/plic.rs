@@ -0,0 +1,148 @@
+//! Platform-level interrupt controller, which routes interrupts from devices
+//! to the external interrupt inputs of each hart.
+//!
+//! Every hart has two contexts, one for M-mode and one for S-mode.
+
+use super::Device;
+
+/// Number of interrupt sources, including the nonexistent source 0.
+pub const NUM_SOURCES: usize = 32;
+
+const PRIORITY: u64 = 0x00_0000;
+const PENDING: u64 = 0x00_1000;
+const ENABLE: u64 = 0x00_2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT: u64 = 0x20_0000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+pub struct Plic {
+    priorities: [u32; NUM_SOURCES],
+    /// One bit per source.
+    pending: u32,
+    /// Sources that have been claimed but not completed yet.
+    in_service: u32,
+    /// Per context, one bit per source.
+    enabled: Vec<u32>,
+    thresholds: Vec<u32>,
+}
+
+impl Plic {
+    pub fn new(contexts: usize) -> Self {
+        Self {
+            priorities: [0; NUM_SOURCES],
+            pending: 0,
+            in_service: 0,
+            enabled: vec![0; contexts],
+            thresholds: vec![0; contexts],
+        }
+    }
+
+    /// Updates the level of a device's interrupt line.
+    pub fn set_level(&mut self, source: u32, level: bool) {
+        let bit = 1 << source;
+        if level && self.in_service & bit == 0 {
+            self.pending |= bit;
+        } else if !level {
+            self.pending &= !bit;
+        }
+    }
+
+    /// Checks whether a context has an interrupt waiting to be claimed.
+    pub fn is_pending(&self, context: usize) -> bool {
+        self.best_source(context).is_some()
+    }
+
+    /// Finds the enabled pending source with the highest priority above the
+    /// threshold. Ties go to the lowest numbered source.
+    fn best_source(&self, context: usize) -> Option<u32> {
+        let candidates = self.pending & self.enabled[context];
+        (1..NUM_SOURCES as u32)
+            .filter(|&source| candidates >> source & 1 == 1)
+            .filter(|&source| {
+                self.priorities[source as usize] > self.thresholds[context]
+            })
+            .max_by_key(|&source| {
+                (self.priorities[source as usize], std::cmp::Reverse(source))
+            })
+    }
+
+    fn claim(&mut self, context: usize) -> u32 {
+        let Some(source) = self.best_source(context) else {
+            return 0;
+        };
+        self.pending &= !(1 << source);
+        self.in_service |= 1 << source;
+        source
+    }
+}
+
+impl Device for Plic {
+    fn read(&mut self, offset: u64, size: usize) -> Option<u64> {
+        if size != 4 {
+            return None;
+        }
+        let value = match offset {
+            PRIORITY..PENDING => *self.priorities.get((offset / 4) as usize)?,
+            PENDING => self.pending,
+            ENABLE..CONTEXT => {
+                let context = ((offset - ENABLE) / ENABLE_STRIDE) as usize;
+                match (offset - ENABLE) % ENABLE_STRIDE {
+                    0 => *self.enabled.get(context)?,
+                    _ => 0,
+                }
+            }
+            CONTEXT.. => {
+                let context = ((offset - CONTEXT) / CONTEXT_STRIDE) as usize;
+                if context >= self.thresholds.len() {
+                    return None;
+                }
+                match (offset - CONTEXT) % CONTEXT_STRIDE {
+                    0 => self.thresholds[context],
+                    4 => self.claim(context),
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        };
+        Some(value.into())
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Option<()> {
+        if size != 4 {
+            return None;
+        }
+        let value = value as u32;
+        match offset {
+            PRIORITY..PENDING => {
+                let source = (offset / 4) as usize;
+                // Source 0 doesn't exist, so its priority is hardwired
+                if (1..NUM_SOURCES).contains(&source) {
+                    self.priorities[source] = value & 0b111;
+                }
+            }
+            ENABLE..CONTEXT => {
+                let context = ((offset - ENABLE) / ENABLE_STRIDE) as usize;
+                if (offset - ENABLE).is_multiple_of(ENABLE_STRIDE) {
+                    *self.enabled.get_mut(context)? = value & !1;
+                }
+            }
+            CONTEXT.. => {
+                let context = ((offset - CONTEXT) / CONTEXT_STRIDE) as usize;
+                if context >= self.thresholds.len() {
+                    return None;
+                }
+                match (offset - CONTEXT) % CONTEXT_STRIDE {
+                    0 => self.thresholds[context] = value & 0b111,
+                    // Completing an interrupt allows the source to become
+                    // pending again
+                    4 if (value as usize) < NUM_SOURCES => {
+                        self.in_service &= !(1 << value);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Some(())
+    }
+}
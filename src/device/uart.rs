@@ -0,0 +1,250 @@
+//! NS16550A-compatible UART connected to the host's standard input and
+//! output.
+//!
+//! When standard input is a terminal, it is switched to non-canonical mode
+//! without echo so that the guest sees each key press as it happens. Signals
+//! like `SIGINT` still reach the emulator.
+
+use super::Device;
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    sync::{
+        mpsc::{self, Receiver},
+        OnceLock,
+    },
+};
+
+// Register offsets
+const RBR_THR: u64 = 0;
+const IER: u64 = 1;
+const IIR_FCR: u64 = 2;
+const LCR: u64 = 3;
+const MCR: u64 = 4;
+const LSR: u64 = 5;
+const MSR: u64 = 6;
+const SCR: u64 = 7;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_THR_EMPTY: u8 = 1 << 1;
+
+const IIR_NONE: u8 = 0x01;
+const IIR_THR_EMPTY: u8 = 0x02;
+const IIR_RX_AVAILABLE: u8 = 0x04;
+const IIR_FIFO_ENABLED: u8 = 0xc0;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+const LSR_TRANSMITTER_EMPTY: u8 = 1 << 6;
+
+/// Terminal settings to restore when the emulator exits.
+static ORIGINAL_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+
+pub struct Uart {
+    input: Receiver<u8>,
+    rx_fifo: VecDeque<u8>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    fifo_enabled: bool,
+    /// Set when the transmitter becomes empty, and cleared by reading `IIR`
+    /// or writing to `THR`.
+    thr_empty_interrupt: bool,
+}
+
+impl Uart {
+    pub fn new() -> Self {
+        enter_raw_mode();
+        let (sender, input) = mpsc::channel();
+        // Reading from standard input blocks, so it has its own thread
+        std::thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                let Ok(byte) = byte else { break };
+                if sender.send(byte).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            input,
+            rx_fifo: VecDeque::new(),
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            divisor: 0,
+            fifo_enabled: false,
+            thr_empty_interrupt: false,
+        }
+    }
+
+    /// Moves newly typed characters into the receive FIFO.
+    pub fn poll(&mut self) {
+        self.rx_fifo.extend(self.input.try_iter());
+    }
+
+    /// Writes a character to the console right away.
+    pub fn send(&mut self, byte: u8) {
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(&[byte]);
+        let _ = stdout.flush();
+        self.thr_empty_interrupt = true;
+    }
+
+    /// Takes the next character that was typed, if any.
+    pub fn receive(&mut self) -> Option<u8> {
+        self.poll();
+        self.rx_fifo.pop_front()
+    }
+
+    /// The level of the interrupt line.
+    pub fn interrupt(&self) -> bool {
+        self.interrupt_id() != IIR_NONE
+    }
+
+    fn interrupt_id(&self) -> u8 {
+        if self.ier & IER_RX_AVAILABLE != 0 && !self.rx_fifo.is_empty() {
+            IIR_RX_AVAILABLE
+        } else if self.ier & IER_THR_EMPTY != 0 && self.thr_empty_interrupt {
+            IIR_THR_EMPTY
+        } else {
+            IIR_NONE
+        }
+    }
+}
+
+impl Drop for Uart {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: u64, size: usize) -> Option<u64> {
+        if size != 1 {
+            return None;
+        }
+        let dlab = self.lcr & LCR_DLAB != 0;
+        let value = match offset {
+            RBR_THR if dlab => self.divisor as u8,
+            IER if dlab => (self.divisor >> 8) as u8,
+            RBR_THR => self.rx_fifo.pop_front().unwrap_or(0),
+            IER => self.ier,
+            IIR_FCR => {
+                let id = self.interrupt_id();
+                if id == IIR_THR_EMPTY {
+                    self.thr_empty_interrupt = false;
+                }
+                if self.fifo_enabled {
+                    id | IIR_FIFO_ENABLED
+                } else {
+                    id
+                }
+            }
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                // Output is written out right away, so the transmitter is
+                // always empty
+                let data_ready = if self.rx_fifo.is_empty() {
+                    0
+                } else {
+                    LSR_DATA_READY
+                };
+                LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY | data_ready
+            }
+            // Report the carrier as detected and the other end as ready
+            MSR => 0xb0,
+            SCR => self.scr,
+            _ => return None,
+        };
+        Some(value.into())
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Option<()> {
+        if size != 1 {
+            return None;
+        }
+        let value = value as u8;
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            RBR_THR if dlab => {
+                self.divisor = self.divisor & 0xff00 | u16::from(value);
+            }
+            IER if dlab => {
+                self.divisor = self.divisor & 0xff | u16::from(value) << 8;
+            }
+            RBR_THR => self.send(value),
+            IER => {
+                // Enabling the interrupt while the transmitter is empty
+                // raises it right away
+                if value & !self.ier & IER_THR_EMPTY != 0 {
+                    self.thr_empty_interrupt = true;
+                }
+                self.ier = value & 0x0f;
+            }
+            IIR_FCR => {
+                self.fifo_enabled = value & 1 != 0;
+                // Clear the receive FIFO
+                if value & 0b10 != 0 {
+                    self.rx_fifo.clear();
+                }
+            }
+            LCR => self.lcr = value,
+            MCR => self.mcr = value & 0x1f,
+            LSR | MSR => {}
+            SCR => self.scr = value,
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+/// Puts the terminal into non-canonical mode without echo, and makes sure
+/// that it gets restored even if the emulator is killed by a signal.
+fn enter_raw_mode() {
+    // SAFETY: `termios` is a plain C struct, so it's valid when zeroed, and
+    // `tcgetattr` only writes to it
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    // SAFETY: see above
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+        // Not a terminal
+        return;
+    }
+    if ORIGINAL_TERMIOS.set(termios).is_err() {
+        return;
+    }
+    termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+    termios.c_cc[libc::VMIN] = 1;
+    termios.c_cc[libc::VTIME] = 0;
+    // SAFETY: the handler only calls async-signal-safe functions, and
+    // `termios` was filled in by `tcgetattr`
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            libc::signal(
+                signal,
+                handle_signal as *const () as libc::sighandler_t,
+            );
+        }
+    }
+}
+
+fn restore_terminal() {
+    if let Some(termios) = ORIGINAL_TERMIOS.get() {
+        // SAFETY: the settings came from `tcgetattr`
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+        }
+    }
+}
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    restore_terminal();
+    // SAFETY: exiting without running any destructors is always safe
+    unsafe { libc::_exit(128 + signal) };
+}
@@ -0,0 +1,925 @@
+//! Renders instructions in the assembly syntax that objdump uses.
+//!
+//! Instructions that match a common pseudo-instruction, such as `li` or
+//! `ret`, are shown as that pseudo-instruction. Compressed instructions keep
+//! their `c.` mnemonics and their shorter operand lists, so that it's clear
+//! which encoding was used.
+
+use crate::{
+    bits::sign_extend_12bit,
+    csr,
+    float::{IntType, Precision},
+    instruction::{
+        AmoFunct, AmoWidth, BFunct, CsrFunct, FArithFunct, FCompareFunct,
+        FFmaFunct, FMinMaxFunct, FSignFunct, IFunct, Instruction, RFunct,
+        SFunct, UOpcode, FENCE_I, FENCE_O, FENCE_R, FENCE_W,
+    },
+    load::CodeSection,
+    register::RegisterName,
+};
+use std::fmt;
+
+/// Rounding mode that defers to `frm`, which is left out of the assembly.
+const DYNAMIC_ROUNDING: u8 = 0b111;
+
+/// An instruction along with where it came from, which together can be
+/// displayed as assembly.
+pub struct Disassembly<'a> {
+    instruction: &'a Instruction,
+    /// Used to show jump and branch targets as absolute addresses.
+    address: u64,
+    /// The raw encoding, which tells compressed instructions apart.
+    bits: u32,
+}
+
+impl<'a> Disassembly<'a> {
+    pub const fn new(
+        instruction: &'a Instruction,
+        address: u64,
+        bits: u32,
+    ) -> Self {
+        Self {
+            instruction,
+            address,
+            bits,
+        }
+    }
+
+    const fn target(&self, offset: i64) -> u64 {
+        self.address.wrapping_add_signed(offset)
+    }
+
+    fn fmt_compressed(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        word: u16,
+    ) -> fmt::Result {
+        // Quadrant 2 holds the stack-pointer-relative loads and stores
+        let sp_relative = word & 0b11 == 0b10;
+        let sp = if sp_relative { "sp" } else { "" };
+        match self.instruction {
+            Instruction::I {
+                funct: IFunct::Addi,
+                rd,
+                rs1,
+                imm,
+            } => {
+                let imm = sign_extend_12bit(*imm);
+                match (word & 0b11, word >> 13) {
+                    (0b00, _) => write!(f, "c.addi4spn {rd:?}, sp, {imm}"),
+                    (_, 0b010) => write!(f, "c.li {rd:?}, {imm}"),
+                    (_, 0b011) => write!(f, "c.addi16sp sp, {imm}"),
+                    _ if *rd == RegisterName::X0 => f.write_str("c.nop"),
+                    _ => write!(f, "c.addi {rs1:?}, {imm}"),
+                }
+            }
+            Instruction::I {
+                funct: IFunct::Jalr,
+                rd,
+                rs1,
+                ..
+            } => {
+                if *rd == RegisterName::X0 {
+                    write!(f, "c.jr {rs1:?}")
+                } else {
+                    write!(f, "c.jalr {rs1:?}")
+                }
+            }
+            Instruction::I {
+                funct: funct @ (IFunct::Lw | IFunct::Ld),
+                rd,
+                rs1,
+                imm,
+            } => write!(f, "c.{}{sp} {rd:?}, {imm}({rs1:?})", funct.mnemonic()),
+            Instruction::I {
+                funct: funct @ (IFunct::Slli | IFunct::Srli | IFunct::Srai),
+                rd,
+                imm,
+                ..
+            } => write!(f, "c.{} {rd:?}, {}", funct.mnemonic(), imm & 0x3f),
+            Instruction::I { funct, rd, imm, .. } => write!(
+                f,
+                "c.{} {rd:?}, {}",
+                funct.mnemonic(),
+                sign_extend_12bit(*imm)
+            ),
+            Instruction::R {
+                funct: RFunct::Add,
+                rd,
+                rs1,
+                rs2,
+            } if *rs1 == RegisterName::X0 => write!(f, "c.mv {rd:?}, {rs2:?}"),
+            Instruction::R { funct, rd, rs2, .. } => {
+                write!(f, "c.{} {rd:?}, {rs2:?}", funct.mnemonic())
+            }
+            Instruction::S {
+                funct,
+                rs2,
+                rs1,
+                imm,
+            } => {
+                write!(f, "c.{}{sp} {rs2:?}, {imm}({rs1:?})", funct.mnemonic())
+            }
+            Instruction::FLoad {
+                precision,
+                rd,
+                rs1,
+                imm,
+            } => write!(
+                f,
+                "c.fl{}{sp} {rd:?}, {imm}({rs1:?})",
+                precision.int_suffix()
+            ),
+            Instruction::FStore {
+                precision,
+                rs2,
+                rs1,
+                imm,
+            } => write!(
+                f,
+                "c.fs{}{sp} {rs2:?}, {imm}({rs1:?})",
+                precision.int_suffix()
+            ),
+            Instruction::U { rd, imm, .. } => {
+                write!(f, "c.lui {rd:?}, {:#x}", (*imm as u32) >> 12)
+            }
+            Instruction::Jal { imm, .. } => {
+                write!(f, "c.j {:#x}", self.target(i64::from(*imm)))
+            }
+            Instruction::B {
+                funct, rs1, imm, ..
+            } => {
+                let mnemonic = match funct {
+                    BFunct::Beq => "c.beqz",
+                    _ => "c.bnez",
+                };
+                write!(
+                    f,
+                    "{mnemonic} {rs1:?}, {:#x}",
+                    self.target((*imm).into())
+                )
+            }
+            Instruction::Ebreak => f.write_str("c.ebreak"),
+            // Nothing else has a compressed encoding
+            _ => self.fmt_full(f),
+        }
+    }
+
+    fn fmt_full(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RegisterName as R;
+
+        match self.instruction {
+            Instruction::R {
+                funct,
+                rd,
+                rs2,
+                rs1,
+            } => match (funct, *rs1, *rs2) {
+                (RFunct::Sub, R::X0, _) => write!(f, "neg {rd:?}, {rs2:?}"),
+                (RFunct::Subw, R::X0, _) => write!(f, "negw {rd:?}, {rs2:?}"),
+                (RFunct::Sltu, R::X0, _) => write!(f, "snez {rd:?}, {rs2:?}"),
+                (RFunct::Slt, _, R::X0) => write!(f, "sltz {rd:?}, {rs1:?}"),
+                (RFunct::Slt, R::X0, _) => write!(f, "sgtz {rd:?}, {rs2:?}"),
+                _ => write!(f, "{} {rd:?}, {rs1:?}, {rs2:?}", funct.mnemonic()),
+            },
+            Instruction::I {
+                funct,
+                rd,
+                rs1,
+                imm,
+            } => {
+                let simm = sign_extend_12bit(*imm);
+                match (funct, *rd, *rs1, simm) {
+                    (IFunct::Addi, R::X0, R::X0, 0) => f.write_str("nop"),
+                    (IFunct::Addi, _, R::X0, _) => {
+                        write!(f, "li {rd:?}, {simm}")
+                    }
+                    (IFunct::Addi, _, _, 0) => write!(f, "mv {rd:?}, {rs1:?}"),
+                    (IFunct::Addiw, _, _, 0) => {
+                        write!(f, "sext.w {rd:?}, {rs1:?}")
+                    }
+                    (IFunct::Xori, _, _, -1) => {
+                        write!(f, "not {rd:?}, {rs1:?}")
+                    }
+                    (IFunct::Sltiu, _, _, 1) => {
+                        write!(f, "seqz {rd:?}, {rs1:?}")
+                    }
+                    (IFunct::Jalr, R::X0, R::X1, 0) => f.write_str("ret"),
+                    (IFunct::Jalr, R::X0, _, 0) => write!(f, "jr {rs1:?}"),
+                    (IFunct::Jalr, R::X1, _, 0) => write!(f, "jalr {rs1:?}"),
+                    (
+                        IFunct::Lb
+                        | IFunct::Lh
+                        | IFunct::Lw
+                        | IFunct::Ld
+                        | IFunct::Lbu
+                        | IFunct::Lhu
+                        | IFunct::Lwu
+                        | IFunct::Jalr,
+                        ..,
+                    ) => write!(
+                        f,
+                        "{} {rd:?}, {simm}({rs1:?})",
+                        funct.mnemonic()
+                    ),
+                    (IFunct::Slli | IFunct::Srli | IFunct::Srai, ..) => {
+                        write!(
+                            f,
+                            "{} {rd:?}, {rs1:?}, {}",
+                            funct.mnemonic(),
+                            imm & 0x3f
+                        )
+                    }
+                    (IFunct::Slliw | IFunct::Srliw | IFunct::Sraiw, ..) => {
+                        write!(
+                            f,
+                            "{} {rd:?}, {rs1:?}, {}",
+                            funct.mnemonic(),
+                            imm & 0x1f
+                        )
+                    }
+                    _ => write!(
+                        f,
+                        "{} {rd:?}, {rs1:?}, {simm}",
+                        funct.mnemonic()
+                    ),
+                }
+            }
+            Instruction::S {
+                funct,
+                rs2,
+                rs1,
+                imm,
+            } => write!(
+                f,
+                "{} {rs2:?}, {}({rs1:?})",
+                funct.mnemonic(),
+                sign_extend_12bit((*imm).into())
+            ),
+            Instruction::B {
+                funct,
+                rs2,
+                rs1,
+                imm,
+            } => {
+                let target = self.target((*imm).into());
+                match (funct, *rs1, *rs2) {
+                    (BFunct::Beq, _, R::X0) => {
+                        write!(f, "beqz {rs1:?}, {target:#x}")
+                    }
+                    (BFunct::Bne, _, R::X0) => {
+                        write!(f, "bnez {rs1:?}, {target:#x}")
+                    }
+                    (BFunct::Blt, _, R::X0) => {
+                        write!(f, "bltz {rs1:?}, {target:#x}")
+                    }
+                    (BFunct::Bge, _, R::X0) => {
+                        write!(f, "bgez {rs1:?}, {target:#x}")
+                    }
+                    (BFunct::Blt, R::X0, _) => {
+                        write!(f, "bgtz {rs2:?}, {target:#x}")
+                    }
+                    (BFunct::Bge, R::X0, _) => {
+                        write!(f, "blez {rs2:?}, {target:#x}")
+                    }
+                    _ => write!(
+                        f,
+                        "{} {rs1:?}, {rs2:?}, {target:#x}",
+                        funct.mnemonic()
+                    ),
+                }
+            }
+            Instruction::U { opcode, rd, imm } => {
+                let mnemonic = match opcode {
+                    UOpcode::Lui => "lui",
+                    UOpcode::Auipc => "auipc",
+                };
+                write!(f, "{mnemonic} {rd:?}, {:#x}", (*imm as u32) >> 12)
+            }
+            Instruction::Jal { rd, imm } => {
+                let target = self.target((*imm).into());
+                match *rd {
+                    R::X0 => write!(f, "j {target:#x}"),
+                    R::X1 => write!(f, "jal {target:#x}"),
+                    _ => write!(f, "jal {rd:?}, {target:#x}"),
+                }
+            }
+            Instruction::FLoad {
+                precision,
+                rd,
+                rs1,
+                imm,
+            } => write!(
+                f,
+                "fl{} {rd:?}, {}({rs1:?})",
+                precision.int_suffix(),
+                sign_extend_12bit(*imm)
+            ),
+            Instruction::FStore {
+                precision,
+                rs2,
+                rs1,
+                imm,
+            } => write!(
+                f,
+                "fs{} {rs2:?}, {}({rs1:?})",
+                precision.int_suffix(),
+                sign_extend_12bit((*imm).into())
+            ),
+            Instruction::FArith {
+                funct,
+                precision,
+                rd,
+                rs1,
+                rs2,
+                rm,
+            } => {
+                let mnemonic = match funct {
+                    FArithFunct::Add => "fadd",
+                    FArithFunct::Sub => "fsub",
+                    FArithFunct::Mul => "fmul",
+                    FArithFunct::Div => "fdiv",
+                };
+                write!(
+                    f,
+                    "{mnemonic}.{} {rd:?}, {rs1:?}, {rs2:?}{}",
+                    precision.suffix(),
+                    RoundingMode(*rm)
+                )
+            }
+            Instruction::FSqrt {
+                precision,
+                rd,
+                rs1,
+                rm,
+            } => write!(
+                f,
+                "fsqrt.{} {rd:?}, {rs1:?}{}",
+                precision.suffix(),
+                RoundingMode(*rm)
+            ),
+            Instruction::FSign {
+                funct,
+                precision,
+                rd,
+                rs1,
+                rs2,
+            } => {
+                let p = precision.suffix();
+                if rs1 == rs2 {
+                    let mnemonic = match funct {
+                        FSignFunct::Sgnj => "fmv",
+                        FSignFunct::Sgnjn => "fneg",
+                        FSignFunct::Sgnjx => "fabs",
+                    };
+                    write!(f, "{mnemonic}.{p} {rd:?}, {rs1:?}")
+                } else {
+                    let mnemonic = match funct {
+                        FSignFunct::Sgnj => "fsgnj",
+                        FSignFunct::Sgnjn => "fsgnjn",
+                        FSignFunct::Sgnjx => "fsgnjx",
+                    };
+                    write!(f, "{mnemonic}.{p} {rd:?}, {rs1:?}, {rs2:?}")
+                }
+            }
+            Instruction::FMinMax {
+                funct,
+                precision,
+                rd,
+                rs1,
+                rs2,
+            } => {
+                let mnemonic = match funct {
+                    FMinMaxFunct::Min => "fmin",
+                    FMinMaxFunct::Max => "fmax",
+                };
+                write!(
+                    f,
+                    "{mnemonic}.{} {rd:?}, {rs1:?}, {rs2:?}",
+                    precision.suffix()
+                )
+            }
+            Instruction::FFma {
+                funct,
+                precision,
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => {
+                let mnemonic = match funct {
+                    FFmaFunct::Madd => "fmadd",
+                    FFmaFunct::Msub => "fmsub",
+                    FFmaFunct::Nmsub => "fnmsub",
+                    FFmaFunct::Nmadd => "fnmadd",
+                };
+                write!(
+                    f,
+                    "{mnemonic}.{} {rd:?}, {rs1:?}, {rs2:?}, {rs3:?}{}",
+                    precision.suffix(),
+                    RoundingMode(*rm)
+                )
+            }
+            Instruction::FCompare {
+                funct,
+                precision,
+                rd,
+                rs1,
+                rs2,
+            } => {
+                let mnemonic = match funct {
+                    FCompareFunct::Eq => "feq",
+                    FCompareFunct::Lt => "flt",
+                    FCompareFunct::Le => "fle",
+                };
+                write!(
+                    f,
+                    "{mnemonic}.{} {rd:?}, {rs1:?}, {rs2:?}",
+                    precision.suffix()
+                )
+            }
+            Instruction::FClass { precision, rd, rs1 } => {
+                write!(f, "fclass.{} {rd:?}, {rs1:?}", precision.suffix())
+            }
+            Instruction::FMvToInt { precision, rd, rs1 } => {
+                write!(f, "fmv.x.{} {rd:?}, {rs1:?}", precision.int_suffix())
+            }
+            Instruction::FMvFromInt { precision, rd, rs1 } => {
+                write!(f, "fmv.{}.x {rd:?}, {rs1:?}", precision.int_suffix())
+            }
+            Instruction::FCvtToInt {
+                precision,
+                int,
+                rd,
+                rs1,
+                rm,
+            } => write!(
+                f,
+                "fcvt.{}.{} {rd:?}, {rs1:?}{}",
+                int.suffix(),
+                precision.suffix(),
+                RoundingMode(*rm)
+            ),
+            Instruction::FCvtFromInt {
+                precision,
+                int,
+                rd,
+                rs1,
+                rm,
+            } => write!(
+                f,
+                "fcvt.{}.{} {rd:?}, {rs1:?}{}",
+                precision.suffix(),
+                int.suffix(),
+                RoundingMode(*rm)
+            ),
+            Instruction::FCvtFloat {
+                precision,
+                rd,
+                rs1,
+                rm,
+            } => {
+                let source = match precision {
+                    Precision::Single => Precision::Double,
+                    Precision::Double => Precision::Single,
+                };
+                write!(
+                    f,
+                    "fcvt.{}.{} {rd:?}, {rs1:?}{}",
+                    precision.suffix(),
+                    source.suffix(),
+                    RoundingMode(*rm)
+                )
+            }
+            Instruction::LoadReserved {
+                width,
+                rd,
+                rs1,
+                aq,
+                rl,
+            } => write!(
+                f,
+                "lr.{}{} {rd:?}, ({rs1:?})",
+                width.suffix(),
+                Ordering(*aq, *rl)
+            ),
+            Instruction::StoreConditional {
+                width,
+                rd,
+                rs1,
+                rs2,
+                aq,
+                rl,
+            } => write!(
+                f,
+                "sc.{}{} {rd:?}, {rs2:?}, ({rs1:?})",
+                width.suffix(),
+                Ordering(*aq, *rl)
+            ),
+            Instruction::Amo {
+                funct,
+                width,
+                rd,
+                rs1,
+                rs2,
+                aq,
+                rl,
+            } => write!(
+                f,
+                "{}.{}{} {rd:?}, {rs2:?}, ({rs1:?})",
+                funct.mnemonic(),
+                width.suffix(),
+                Ordering(*aq, *rl)
+            ),
+            Instruction::Fence { fm, pred, succ } => {
+                let rw = FENCE_R | FENCE_W;
+                let iorw = FENCE_I | FENCE_O | rw;
+                match (fm, *pred, *succ) {
+                    (0b1000, p, s) if p == rw && s == rw => {
+                        f.write_str("fence.tso")
+                    }
+                    (0, FENCE_W, 0) => f.write_str("pause"),
+                    (0, p, s) if p == iorw && s == iorw => f.write_str("fence"),
+                    _ => write!(
+                        f,
+                        "fence {}, {}",
+                        FenceSet(*pred),
+                        FenceSet(*succ)
+                    ),
+                }
+            }
+            Instruction::FenceI => f.write_str("fence.i"),
+            Instruction::Csr {
+                funct,
+                rd,
+                rs1,
+                csr,
+            } => {
+                let csr = CsrName(*csr);
+                match (funct, *rd, *rs1) {
+                    (CsrFunct::Csrrs, _, R::X0) => {
+                        write!(f, "csrr {rd:?}, {csr}")
+                    }
+                    (_, R::X0, _) => write!(
+                        f,
+                        "{} {csr}, {rs1:?}",
+                        match funct {
+                            CsrFunct::Csrrw => "csrw",
+                            CsrFunct::Csrrs => "csrs",
+                            CsrFunct::Csrrc => "csrc",
+                        }
+                    ),
+                    _ => {
+                        write!(f, "{} {rd:?}, {csr}, {rs1:?}", funct.mnemonic())
+                    }
+                }
+            }
+            Instruction::CsrImm {
+                funct,
+                rd,
+                uimm,
+                csr,
+            } => {
+                let csr = CsrName(*csr);
+                if *rd == R::X0 {
+                    let mnemonic = match funct {
+                        CsrFunct::Csrrw => "csrwi",
+                        CsrFunct::Csrrs => "csrsi",
+                        CsrFunct::Csrrc => "csrci",
+                    };
+                    write!(f, "{mnemonic} {csr}, {uimm}")
+                } else {
+                    write!(f, "{}i {rd:?}, {csr}, {uimm}", funct.mnemonic())
+                }
+            }
+            Instruction::Ecall => f.write_str("ecall"),
+            Instruction::Ebreak => f.write_str("ebreak"),
+            Instruction::Sret => f.write_str("sret"),
+            Instruction::Mret => f.write_str("mret"),
+            Instruction::Wfi => f.write_str("wfi"),
+            Instruction::SfenceVma { rs1, rs2 } => match (*rs1, *rs2) {
+                (R::X0, R::X0) => f.write_str("sfence.vma"),
+                (_, R::X0) => write!(f, "sfence.vma {rs1:?}"),
+                _ => write!(f, "sfence.vma {rs1:?}, {rs2:?}"),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bits & 0b11 == 0b11 {
+            self.fmt_full(f)
+        } else {
+            self.fmt_compressed(f, self.bits as u16)
+        }
+    }
+}
+
+/// Prints every instruction in some sections of code, one per line, along
+/// with its address and raw encoding.
+pub fn disassemble(sections: &[CodeSection]) {
+    for (i, section) in sections.iter().enumerate() {
+        if i != 0 {
+            println!();
+        }
+        println!("Disassembly of section {}:", section.name);
+        println!();
+        let mut offset = 0;
+        while offset < section.bytes.len() {
+            let address = section.address + offset as u64;
+            let rest = &section.bytes[offset..];
+            let Some(low_half) = rest.get(..2) else {
+                println!("{address:8x}: {:02x}", rest[0]);
+                break;
+            };
+            let low_half = u16::from_le_bytes(low_half.try_into().unwrap());
+            let (bits, instruction) = match Instruction::try_from(low_half) {
+                Ok(instruction) => (low_half.into(), Ok(instruction)),
+                Err(Ok(_)) => match rest.get(..4) {
+                    Some(word) => {
+                        let word = u32::from_le_bytes(word.try_into().unwrap());
+                        (word, Instruction::try_from(word).map_err(drop))
+                    }
+                    None => (low_half.into(), Err(())),
+                },
+                Err(Err(_)) => (low_half.into(), Err(())),
+            };
+            let raw = Encoding(bits);
+            match instruction {
+                Ok(instruction) => println!(
+                    "{address:8x}: {raw}  {}",
+                    Disassembly::new(&instruction, address, bits)
+                ),
+                Err(()) => println!("{address:8x}: {raw}  <unknown>"),
+            }
+            offset += if bits & 0b11 == 0b11 { 4 } else { 2 };
+        }
+    }
+}
+
+/// The raw bits of an instruction, in hex. Compressed instructions are
+/// padded to the width of the others so that columns line up.
+pub struct Encoding(pub u32);
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 & 0b11 == 0b11 {
+            write!(f, "{:08x}", self.0)
+        } else {
+            write!(f, "{:04x}    ", self.0)
+        }
+    }
+}
+
+/// Rounding mode operand, which is only shown when it's not dynamic.
+struct RoundingMode(u8);
+
+impl fmt::Display for RoundingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            DYNAMIC_ROUNDING => return Ok(()),
+            0b000 => "rne",
+            0b001 => "rtz",
+            0b010 => "rdn",
+            0b011 => "rup",
+            0b100 => "rmm",
+            reserved => return write!(f, ", {reserved}"),
+        };
+        write!(f, ", {name}")
+    }
+}
+
+/// The `aq` and `rl` bits of an atomic instruction, as a mnemonic suffix.
+struct Ordering(bool, bool);
+
+impl fmt::Display for Ordering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match (self.0, self.1) {
+            (false, false) => "",
+            (true, false) => ".aq",
+            (false, true) => ".rl",
+            (true, true) => ".aqrl",
+        })
+    }
+}
+
+/// The accesses that a fence orders on one side.
+struct FenceSet(u8);
+
+impl fmt::Display for FenceSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return f.write_str("0");
+        }
+        for (bit, name) in [
+            (FENCE_I, 'i'),
+            (FENCE_O, 'o'),
+            (FENCE_R, 'r'),
+            (FENCE_W, 'w'),
+        ] {
+            if self.0 & bit != 0 {
+                write!(f, "{name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A CSR, shown by name if it's one that the emulator knows about.
+pub struct CsrName(pub u16);
+
+impl fmt::Display for CsrName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            csr::FFLAGS => "fflags",
+            csr::FRM => "frm",
+            csr::FCSR => "fcsr",
+            csr::CYCLE => "cycle",
+            csr::TIME => "time",
+            csr::INSTRET => "instret",
+            csr::SSTATUS => "sstatus",
+            csr::SIE => "sie",
+            csr::STVEC => "stvec",
+            csr::SCOUNTEREN => "scounteren",
+            csr::SSCRATCH => "sscratch",
+            csr::SEPC => "sepc",
+            csr::SCAUSE => "scause",
+            csr::STVAL => "stval",
+            csr::SIP => "sip",
+            csr::SATP => "satp",
+            csr::MSTATUS => "mstatus",
+            csr::MISA => "misa",
+            csr::MEDELEG => "medeleg",
+            csr::MIDELEG => "mideleg",
+            csr::MIE => "mie",
+            csr::MTVEC => "mtvec",
+            csr::MCOUNTEREN => "mcounteren",
+            csr::MSCRATCH => "mscratch",
+            csr::MEPC => "mepc",
+            csr::MCAUSE => "mcause",
+            csr::MTVAL => "mtval",
+            csr::MIP => "mip",
+            csr::MCYCLE => "mcycle",
+            csr::MINSTRET => "minstret",
+            csr::MVENDORID => "mvendorid",
+            csr::MARCHID => "marchid",
+            csr::MIMPID => "mimpid",
+            csr::MHARTID => "mhartid",
+            other => return write!(f, "{other:#x}"),
+        };
+        f.write_str(name)
+    }
+}
+
+impl RFunct {
+    const fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Sll => "sll",
+            Self::Slt => "slt",
+            Self::Sltu => "sltu",
+            Self::Xor => "xor",
+            Self::Srl => "srl",
+            Self::Sra => "sra",
+            Self::Or => "or",
+            Self::And => "and",
+            Self::Mul => "mul",
+            Self::Mulh => "mulh",
+            Self::Mulhsu => "mulhsu",
+            Self::Mulhu => "mulhu",
+            Self::Div => "div",
+            Self::Divu => "divu",
+            Self::Rem => "rem",
+            Self::Remu => "remu",
+            Self::Mulw => "mulw",
+            Self::Divw => "divw",
+            Self::Divuw => "divuw",
+            Self::Remw => "remw",
+            Self::Remuw => "remuw",
+            Self::Addw => "addw",
+            Self::Subw => "subw",
+            Self::Sllw => "sllw",
+            Self::Srlw => "srlw",
+            Self::Sraw => "sraw",
+        }
+    }
+}
+
+impl IFunct {
+    const fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Addi => "addi",
+            Self::Slti => "slti",
+            Self::Sltiu => "sltiu",
+            Self::Xori => "xori",
+            Self::Ori => "ori",
+            Self::Andi => "andi",
+            Self::Slli => "slli",
+            Self::Srli => "srli",
+            Self::Srai => "srai",
+            Self::Addiw => "addiw",
+            Self::Slliw => "slliw",
+            Self::Srliw => "srliw",
+            Self::Sraiw => "sraiw",
+            Self::Lb => "lb",
+            Self::Lh => "lh",
+            Self::Lw => "lw",
+            Self::Ld => "ld",
+            Self::Lbu => "lbu",
+            Self::Lhu => "lhu",
+            Self::Lwu => "lwu",
+            Self::Jalr => "jalr",
+        }
+    }
+}
+
+impl SFunct {
+    const fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Sb => "sb",
+            Self::Sh => "sh",
+            Self::Sw => "sw",
+            Self::Sd => "sd",
+        }
+    }
+}
+
+impl BFunct {
+    const fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Beq => "beq",
+            Self::Bne => "bne",
+            Self::Blt => "blt",
+            Self::Bge => "bge",
+            Self::Bltu => "bltu",
+            Self::Bgeu => "bgeu",
+        }
+    }
+}
+
+impl CsrFunct {
+    const fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Csrrw => "csrrw",
+            Self::Csrrs => "csrrs",
+            Self::Csrrc => "csrrc",
+        }
+    }
+}
+
+impl AmoFunct {
+    const fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Swap => "amoswap",
+            Self::Add => "amoadd",
+            Self::Xor => "amoxor",
+            Self::And => "amoand",
+            Self::Or => "amoor",
+            Self::Min => "amomin",
+            Self::Max => "amomax",
+            Self::Minu => "amominu",
+            Self::Maxu => "amomaxu",
+        }
+    }
+}
+
+impl AmoWidth {
+    const fn suffix(self) -> &'static str {
+        match self {
+            Self::Word => "w",
+            Self::Double => "d",
+        }
+    }
+}
+
+impl Precision {
+    const fn suffix(self) -> &'static str {
+        match self {
+            Self::Single => "s",
+            Self::Double => "d",
+        }
+    }
+
+    /// The suffix of loads, stores and moves to or from integer registers,
+    /// which is named after the integer of the same width.
+    const fn int_suffix(self) -> &'static str {
+        match self {
+            Self::Single => "w",
+            Self::Double => "d",
+        }
+    }
+}
+
+impl IntType {
+    const fn suffix(self) -> &'static str {
+        match self {
+            Self::Word => "w",
+            Self::UnsignedWord => "wu",
+            Self::Long => "l",
+            Self::UnsignedLong => "lu",
+        }
+    }
+}
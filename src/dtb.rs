@@ -0,0 +1,141 @@
+//! Writer for flattened device trees, the format in which firmware and
+//! kernels receive a description of the hardware.
+
+use std::collections::HashMap;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+/// The oldest version that this output is backwards compatible with.
+const FDT_LAST_COMPATIBLE_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: usize = 40;
+/// The memory reservation block only holds its terminating entry.
+const FDT_RESERVATION_BLOCK_SIZE: usize = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+
+/// Builds a device tree one node at a time. Properties belong to the most
+/// recently begun node that hasn't been ended yet.
+#[derive(Default)]
+pub struct DtbWriter {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    /// Offsets of property names that are already in the strings block.
+    string_offsets: HashMap<String, u32>,
+}
+
+impl DtbWriter {
+    pub fn begin_node(&mut self, name: &str) {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.align();
+    }
+
+    pub fn end_node(&mut self) {
+        self.push_u32(FDT_END_NODE);
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) {
+        let name_offset = match self.string_offsets.get(name) {
+            Some(&offset) => offset,
+            None => {
+                let offset = self.strings.len() as u32;
+                self.strings.extend_from_slice(name.as_bytes());
+                self.strings.push(0);
+                self.string_offsets.insert(name.to_owned(), offset);
+                offset
+            }
+        };
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(name_offset);
+        self.structure.extend_from_slice(value);
+        self.align();
+    }
+
+    /// Adds a property without a value, which is true by being present.
+    pub fn property_empty(&mut self, name: &str) {
+        self.property(name, &[]);
+    }
+
+    pub fn property_u32(&mut self, name: &str, value: u32) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    pub fn property_u64(&mut self, name: &str, value: u64) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) {
+        let value = cells
+            .iter()
+            .flat_map(|cell| cell.to_be_bytes())
+            .collect::<Vec<_>>();
+        self.property(name, &value);
+    }
+
+    /// Adds a property made up of 64-bit address and size pairs, assuming
+    /// that `#address-cells` and `#size-cells` are both 2.
+    pub fn property_reg(&mut self, name: &str, ranges: &[(u64, u64)]) {
+        let cells = ranges
+            .iter()
+            .flat_map(|&(address, size)| [address, size])
+            .flat_map(|value| [(value >> 32) as u32, value as u32])
+            .collect::<Vec<_>>();
+        self.property_cells(name, &cells);
+    }
+
+    pub fn property_string(&mut self, name: &str, value: &str) {
+        self.property_strings(name, &[value]);
+    }
+
+    pub fn property_strings(&mut self, name: &str, values: &[&str]) {
+        let value = values
+            .iter()
+            .flat_map(|value| value.bytes().chain([0]))
+            .collect::<Vec<_>>();
+        self.property(name, &value);
+    }
+
+    /// Assembles the complete device tree blob.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.push_u32(FDT_END);
+
+        let structure_offset = FDT_HEADER_SIZE + FDT_RESERVATION_BLOCK_SIZE;
+        let strings_offset = structure_offset + self.structure.len();
+        let total_size = strings_offset + self.strings.len();
+        let header = [
+            FDT_MAGIC,
+            total_size as u32,
+            structure_offset as u32,
+            strings_offset as u32,
+            FDT_HEADER_SIZE as u32,
+            FDT_VERSION,
+            FDT_LAST_COMPATIBLE_VERSION,
+            // Physical ID of the boot hart
+            0,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ];
+
+        let mut blob = Vec::with_capacity(total_size);
+        blob.extend(header.iter().flat_map(|word| word.to_be_bytes()));
+        blob.extend_from_slice(&[0; FDT_RESERVATION_BLOCK_SIZE]);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+
+    fn push_u32(&mut self, value: u32) {
+        self.structure.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Pads the structure block to the next 4-byte boundary.
+    fn align(&mut self) {
+        let padded_len = self.structure.len().next_multiple_of(4);
+        self.structure.resize(padded_len, 0);
+    }
+}
@@ -1,9 +1,22 @@
+use crate::trap::Exception;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("unknown instruction: 0x{0:08x}")]
     UnknownInstruction(u32),
     #[error("unknown compressed instruction: 0x{0:04x}")]
     UnknownCompressedInstruction(u16),
+    #[error("memory access fault at 0x{0:016x}")]
+    AccessFault(u64),
+    #[error("a stack of {0} bytes doesn't fit above the program")]
+    StackSize(usize),
+    #[error("unhandled {exception} (pc = 0x{pc:016x})")]
+    UnhandledTrap { exception: Exception, pc: u64 },
+    #[error("failed to write trace: {0}")]
+    Trace(std::io::Error),
+    #[cfg(feature = "jit")]
+    #[error("failed to set up the JIT: {0}")]
+    Jit(String),
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T, E = Error> = std::result::Result<T, E>;
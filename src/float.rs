@@ -0,0 +1,849 @@
+//! Software implementation of IEEE 754 single- and double-precision
+//! arithmetic, as required by the F and D extensions.
+//!
+//! The host's floating point unit can't be relied upon to honor the guest's
+//! rounding mode or to report which exceptions an operation raised, so every
+//! operation is done on integers instead. Values are passed around as raw
+//! bits, with single-precision ones in the low 32 bits. Any NaN that an
+//! operation produces is the canonical NaN, since RISC-V doesn't propagate
+//! NaN payloads.
+
+use std::cmp::Ordering;
+
+// Exception flags, in the same bit positions as in `fflags`
+pub const INVALID: u8 = 1 << 4;
+pub const DIVIDE_BY_ZERO: u8 = 1 << 3;
+pub const OVERFLOW: u8 = 1 << 2;
+pub const UNDERFLOW: u8 = 1 << 1;
+pub const INEXACT: u8 = 1 << 0;
+
+/// Where the most significant bit of a significand is placed before adding
+/// it to another, which leaves room for a carry.
+const ALIGNED_MSB: u32 = 125;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Single,
+    Double,
+}
+
+impl Precision {
+    const fn exponent_bits(self) -> u32 {
+        match self {
+            Self::Single => 8,
+            Self::Double => 11,
+        }
+    }
+
+    const fn fraction_bits(self) -> u32 {
+        match self {
+            Self::Single => 23,
+            Self::Double => 52,
+        }
+    }
+
+    const fn bias(self) -> i32 {
+        (1 << (self.exponent_bits() - 1)) - 1
+    }
+
+    /// The biased exponent of infinities and NaNs.
+    const fn max_exponent(self) -> u64 {
+        (1 << self.exponent_bits()) - 1
+    }
+
+    pub const fn sign_bit(self) -> u64 {
+        1 << (self.exponent_bits() + self.fraction_bits())
+    }
+
+    const fn quiet_bit(self) -> u64 {
+        1 << (self.fraction_bits() - 1)
+    }
+
+    pub const fn canonical_nan(self) -> u64 {
+        self.max_exponent() << self.fraction_bits() | self.quiet_bit()
+    }
+
+    const fn pack(self, negative: bool, exponent: u64, fraction: u64) -> u64 {
+        let sign = if negative { self.sign_bit() } else { 0 };
+        sign | exponent << self.fraction_bits() | fraction
+    }
+
+    const fn zero(self, negative: bool) -> u64 {
+        self.pack(negative, 0, 0)
+    }
+
+    const fn infinity(self, negative: bool) -> u64 {
+        self.pack(negative, self.max_exponent(), 0)
+    }
+
+    const fn max_finite(self, negative: bool) -> u64 {
+        self.pack(
+            negative,
+            self.max_exponent() - 1,
+            (1 << self.fraction_bits()) - 1,
+        )
+    }
+
+    fn unpack(self, bits: u64) -> Value {
+        let negative = bits & self.sign_bit() != 0;
+        let exponent = bits >> self.fraction_bits() & self.max_exponent();
+        let fraction = bits & ((1 << self.fraction_bits()) - 1);
+        let kind = match (exponent, fraction) {
+            (0, 0) => Kind::Zero,
+            // Subnormals have the same exponent as the smallest normals, but
+            // no implicit leading one
+            (0, _) => Kind::Finite(Exact {
+                negative,
+                exponent: 1 - self.bias() - self.fraction_bits() as i32,
+                significand: fraction.into(),
+            }),
+            (max, 0) if max == self.max_exponent() => Kind::Infinity,
+            (max, _) if max == self.max_exponent() => Kind::Nan {
+                signaling: fraction & self.quiet_bit() == 0,
+            },
+            _ => Kind::Finite(Exact {
+                negative,
+                exponent: exponent as i32
+                    - self.bias()
+                    - self.fraction_bits() as i32,
+                significand: (fraction | 1 << self.fraction_bits()).into(),
+            }),
+        };
+        Value { negative, kind }
+    }
+}
+
+/// How results that can't be represented exactly are rounded, numbered like
+/// in the `rm` field of instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    #[default]
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+impl RoundingMode {
+    /// Decodes a rounding mode, returning `None` for the reserved values and
+    /// the dynamic rounding mode.
+    pub const fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(Self::NearestEven),
+            1 => Some(Self::TowardZero),
+            2 => Some(Self::Down),
+            3 => Some(Self::Up),
+            4 => Some(Self::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+/// The environment that operations run in: the rounding mode to use, and
+/// the exception flags raised so far.
+#[derive(Default)]
+pub struct Env {
+    pub rounding: RoundingMode,
+    pub flags: u8,
+}
+
+impl Env {
+    pub fn new(rounding: RoundingMode) -> Self {
+        Self { rounding, flags: 0 }
+    }
+}
+
+/// The integer types that can be converted to and from.
+#[derive(Debug, Clone, Copy)]
+pub enum IntType {
+    Word,
+    UnsignedWord,
+    Long,
+    UnsignedLong,
+}
+
+impl IntType {
+    const fn range(self) -> (i128, i128) {
+        match self {
+            Self::Word => (i32::MIN as i128, i32::MAX as i128),
+            Self::UnsignedWord => (0, u32::MAX as i128),
+            Self::Long => (i64::MIN as i128, i64::MAX as i128),
+            Self::UnsignedLong => (0, u64::MAX as i128),
+        }
+    }
+}
+
+struct Value {
+    negative: bool,
+    kind: Kind,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Nan { signaling: bool },
+    Infinity,
+    Zero,
+    Finite(Exact),
+}
+
+/// A nonzero number with unlimited precision, equal to
+/// `significand * 2^exponent`.
+#[derive(Clone, Copy)]
+struct Exact {
+    negative: bool,
+    exponent: i32,
+    significand: u128,
+}
+
+impl Exact {
+    /// The exponent of the most significant bit.
+    const fn msb(&self) -> i32 {
+        self.exponent + msb_index(self.significand) as i32
+    }
+
+    /// Moves the most significant bit of the significand to `ALIGNED_MSB`
+    /// without changing the value.
+    const fn aligned(self) -> Self {
+        let shift = ALIGNED_MSB - msb_index(self.significand);
+        Self {
+            significand: self.significand << shift,
+            exponent: self.exponent - shift as i32,
+            ..self
+        }
+    }
+}
+
+pub fn add(p: Precision, a: u64, b: u64, env: &mut Env) -> u64 {
+    let (x, y) = (p.unpack(a), p.unpack(b));
+    match (x.kind, y.kind) {
+        (Kind::Nan { .. }, _) | (_, Kind::Nan { .. }) => nan(p, &[x, y], env),
+        (Kind::Infinity, Kind::Infinity) if x.negative != y.negative => {
+            invalid(p, env)
+        }
+        (Kind::Infinity, _) => p.infinity(x.negative),
+        (_, Kind::Infinity) => p.infinity(y.negative),
+        (Kind::Zero, Kind::Zero) => add_zeros(p, x.negative, y.negative, env),
+        (Kind::Zero, _) => b,
+        (_, Kind::Zero) => a,
+        (Kind::Finite(x), Kind::Finite(y)) => add_exact(p, x, y, env),
+    }
+}
+
+pub fn sub(p: Precision, a: u64, b: u64, env: &mut Env) -> u64 {
+    add(p, a, b ^ p.sign_bit(), env)
+}
+
+pub fn mul(p: Precision, a: u64, b: u64, env: &mut Env) -> u64 {
+    let (x, y) = (p.unpack(a), p.unpack(b));
+    let negative = x.negative != y.negative;
+    match (x.kind, y.kind) {
+        (Kind::Nan { .. }, _) | (_, Kind::Nan { .. }) => nan(p, &[x, y], env),
+        (Kind::Infinity, Kind::Zero) | (Kind::Zero, Kind::Infinity) => {
+            invalid(p, env)
+        }
+        (Kind::Infinity, _) | (_, Kind::Infinity) => p.infinity(negative),
+        (Kind::Zero, _) | (_, Kind::Zero) => p.zero(negative),
+        (Kind::Finite(x), Kind::Finite(y)) => {
+            round(p, multiply_exact(x, y), env)
+        }
+    }
+}
+
+pub fn div(p: Precision, a: u64, b: u64, env: &mut Env) -> u64 {
+    let (x, y) = (p.unpack(a), p.unpack(b));
+    let negative = x.negative != y.negative;
+    match (x.kind, y.kind) {
+        (Kind::Nan { .. }, _) | (_, Kind::Nan { .. }) => nan(p, &[x, y], env),
+        (Kind::Infinity, Kind::Infinity) | (Kind::Zero, Kind::Zero) => {
+            invalid(p, env)
+        }
+        (Kind::Infinity, _) => p.infinity(negative),
+        (_, Kind::Infinity) | (Kind::Zero, _) => p.zero(negative),
+        (_, Kind::Zero) => {
+            env.flags |= DIVIDE_BY_ZERO;
+            p.infinity(negative)
+        }
+        (Kind::Finite(x), Kind::Finite(y)) => {
+            // The quotient has far more bits than needed, and any remainder
+            // only matters as a sticky bit
+            let x = x.aligned();
+            let quotient = x.significand / y.significand;
+            let remainder = x.significand % y.significand;
+            let exact = Exact {
+                negative,
+                exponent: x.exponent - y.exponent,
+                significand: quotient | u128::from(remainder != 0),
+            };
+            round(p, exact, env)
+        }
+    }
+}
+
+pub fn sqrt(p: Precision, a: u64, env: &mut Env) -> u64 {
+    let x = p.unpack(a);
+    match x.kind {
+        Kind::Nan { .. } => nan(p, &[x], env),
+        Kind::Zero => a,
+        _ if x.negative => invalid(p, env),
+        Kind::Infinity => a,
+        Kind::Finite(x) => {
+            // Halving the exponent requires it to be even
+            let mut x = x.aligned();
+            if x.exponent % 2 != 0 {
+                x.significand >>= 1;
+                x.exponent += 1;
+            }
+            let (root, remainder) = integer_sqrt(x.significand);
+            let exact = Exact {
+                negative: false,
+                exponent: x.exponent / 2,
+                significand: root | u128::from(remainder != 0),
+            };
+            round(p, exact, env)
+        }
+    }
+}
+
+/// Computes `a * b + c` with a single rounding, negating the product and the
+/// addend as requested.
+pub fn fused_multiply_add(
+    p: Precision,
+    [a, b, c]: [u64; 3],
+    negate_product: bool,
+    negate_addend: bool,
+    env: &mut Env,
+) -> u64 {
+    let (x, y, z) = (p.unpack(a), p.unpack(b), p.unpack(c));
+    let product_negative = (x.negative != y.negative) != negate_product;
+    let addend_negative = z.negative != negate_addend;
+    let product = match (x.kind, y.kind) {
+        (Kind::Nan { .. }, _) | (_, Kind::Nan { .. }) => {
+            return nan(p, &[x, y, z], env);
+        }
+        // Even a NaN addend doesn't stop this from being invalid
+        (Kind::Infinity, Kind::Zero) | (Kind::Zero, Kind::Infinity) => {
+            return invalid(p, env);
+        }
+        (Kind::Infinity, _) | (_, Kind::Infinity) => Kind::Infinity,
+        (Kind::Zero, _) | (_, Kind::Zero) => Kind::Zero,
+        (Kind::Finite(x), Kind::Finite(y)) => Kind::Finite(Exact {
+            negative: product_negative,
+            ..multiply_exact(x, y)
+        }),
+    };
+    match (product, z.kind) {
+        (_, Kind::Nan { .. }) => nan(p, &[z], env),
+        (Kind::Infinity, Kind::Infinity)
+            if product_negative != addend_negative =>
+        {
+            invalid(p, env)
+        }
+        (Kind::Infinity, _) => p.infinity(product_negative),
+        (_, Kind::Infinity) => p.infinity(addend_negative),
+        (Kind::Zero, Kind::Zero) => {
+            add_zeros(p, product_negative, addend_negative, env)
+        }
+        (Kind::Zero, _) => {
+            if negate_addend {
+                c ^ p.sign_bit()
+            } else {
+                c
+            }
+        }
+        (Kind::Finite(product), Kind::Zero) => round(p, product, env),
+        (Kind::Finite(product), Kind::Finite(z)) => add_exact(
+            p,
+            product,
+            Exact {
+                negative: addend_negative,
+                ..z
+            },
+            env,
+        ),
+        (Kind::Nan { .. }, _) => unreachable!(),
+    }
+}
+
+/// Picks the smaller or larger of two values, treating -0 as less than +0.
+/// If only one of them is a NaN, the other one is picked.
+pub fn min_max(p: Precision, a: u64, b: u64, max: bool, env: &mut Env) -> u64 {
+    let (x, y) = (p.unpack(a), p.unpack(b));
+    match (x.kind, y.kind) {
+        (Kind::Nan { .. }, Kind::Nan { .. }) => nan(p, &[x, y], env),
+        (Kind::Nan { .. }, _) => {
+            nan(p, &[x], env);
+            b
+        }
+        (_, Kind::Nan { .. }) => {
+            nan(p, &[y], env);
+            a
+        }
+        _ => {
+            let ordering = compare_numbers(p, a, b)
+                .then_with(|| y.negative.cmp(&x.negative));
+            if (ordering == Ordering::Greater) == max {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+/// Compares two values, returning `None` if they are unordered because
+/// either one is a NaN. Signaling NaNs are always invalid, and quiet NaNs are
+/// only invalid if `quiet` is false.
+pub fn compare(
+    p: Precision,
+    a: u64,
+    b: u64,
+    quiet: bool,
+    env: &mut Env,
+) -> Option<Ordering> {
+    let (x, y) = (p.unpack(a), p.unpack(b));
+    match (x.kind, y.kind) {
+        (Kind::Nan { .. }, _) | (_, Kind::Nan { .. }) => {
+            if quiet {
+                nan(p, &[x, y], env);
+            } else {
+                env.flags |= INVALID;
+            }
+            None
+        }
+        _ => Some(compare_numbers(p, a, b)),
+    }
+}
+
+/// Classifies a value into one of the ten categories of the `fclass`
+/// instructions, returned as a mask with a single bit set.
+pub fn classify(p: Precision, a: u64) -> u64 {
+    let x = p.unpack(a);
+    let subnormal = a & p.max_exponent() << p.fraction_bits() == 0;
+    let bit = match (x.kind, x.negative) {
+        (Kind::Infinity, true) => 0,
+        (Kind::Finite(_), true) if !subnormal => 1,
+        (Kind::Finite(_), true) => 2,
+        (Kind::Zero, true) => 3,
+        (Kind::Zero, false) => 4,
+        (Kind::Finite(_), false) if subnormal => 5,
+        (Kind::Finite(_), false) => 6,
+        (Kind::Infinity, false) => 7,
+        (Kind::Nan { signaling: true }, _) => 8,
+        (Kind::Nan { signaling: false }, _) => 9,
+    };
+    1 << bit
+}
+
+/// Converts a value from one precision to another.
+pub fn convert(from: Precision, to: Precision, a: u64, env: &mut Env) -> u64 {
+    let x = from.unpack(a);
+    match x.kind {
+        Kind::Nan { .. } => nan(to, &[x], env),
+        Kind::Infinity => to.infinity(x.negative),
+        Kind::Zero => to.zero(x.negative),
+        Kind::Finite(exact) => round(to, exact, env),
+    }
+}
+
+/// Converts a value to an integer, returned sign-extended to 64 bits. Values
+/// that are out of range saturate, and NaNs turn into the largest integer.
+pub fn to_int(p: Precision, a: u64, int: IntType, env: &mut Env) -> u64 {
+    let (min, max) = int.range();
+    let x = p.unpack(a);
+    let result = match x.kind {
+        Kind::Nan { .. } => None,
+        Kind::Infinity => None,
+        Kind::Zero => Some(0),
+        Kind::Finite(x) => {
+            let magnitude = if x.exponent >= 0 {
+                // Far too large for any of the integer types
+                (x.msb() < 64).then(|| (x.significand << x.exponent) as i128)
+            } else {
+                let (rounded, inexact) = shift_right_rounding(
+                    x.significand,
+                    -x.exponent as u32,
+                    x.negative,
+                    env.rounding,
+                );
+                if inexact {
+                    env.flags |= INEXACT;
+                }
+                Some(rounded as i128)
+            };
+            magnitude
+                .map(
+                    |magnitude| if x.negative { -magnitude } else { magnitude },
+                )
+                .filter(|value| (min..=max).contains(value))
+        }
+    };
+    let result = result.unwrap_or_else(|| {
+        // Inexact isn't raised along with invalid
+        env.flags = env.flags & !INEXACT | INVALID;
+        if x.negative && !matches!(x.kind, Kind::Nan { .. }) {
+            min
+        } else {
+            max
+        }
+    });
+    match int {
+        IntType::Word | IntType::UnsignedWord => result as i32 as u64,
+        IntType::Long | IntType::UnsignedLong => result as u64,
+    }
+}
+
+/// Converts an integer, given in the low bits of `a`, to a floating point
+/// value.
+pub fn from_int(p: Precision, a: u64, int: IntType, env: &mut Env) -> u64 {
+    let value = match int {
+        IntType::Word => i128::from(a as i32),
+        IntType::UnsignedWord => i128::from(a as u32),
+        IntType::Long => i128::from(a as i64),
+        IntType::UnsignedLong => i128::from(a),
+    };
+    if value == 0 {
+        return p.zero(false);
+    }
+    let exact = Exact {
+        negative: value < 0,
+        exponent: 0,
+        significand: value.unsigned_abs(),
+    };
+    round(p, exact, env)
+}
+
+/// Handles operations on NaNs, which raise the invalid operation exception
+/// if any of them is signaling.
+fn nan(p: Precision, operands: &[Value], env: &mut Env) -> u64 {
+    if operands
+        .iter()
+        .any(|x| matches!(x.kind, Kind::Nan { signaling: true }))
+    {
+        env.flags |= INVALID;
+    }
+    p.canonical_nan()
+}
+
+fn invalid(p: Precision, env: &mut Env) -> u64 {
+    env.flags |= INVALID;
+    p.canonical_nan()
+}
+
+/// The sum of two zeros is only negative if both of them are, except when
+/// rounding down, where it is only positive if both of them are.
+fn add_zeros(p: Precision, x: bool, y: bool, env: &Env) -> u64 {
+    if x == y {
+        p.zero(x)
+    } else {
+        p.zero(env.rounding == RoundingMode::Down)
+    }
+}
+
+fn multiply_exact(x: Exact, y: Exact) -> Exact {
+    Exact {
+        negative: x.negative != y.negative,
+        exponent: x.exponent + y.exponent,
+        significand: x.significand * y.significand,
+    }
+}
+
+/// Adds two nonzero numbers and rounds the result.
+fn add_exact(p: Precision, x: Exact, y: Exact, env: &mut Env) -> u64 {
+    let (x, y) = if x.msb() >= y.msb() { (x, y) } else { (y, x) };
+    // Line the smaller number up with the larger one. Any of its bits that
+    // end up below the larger one's significand are far below where the
+    // result gets rounded, so they are only kept as a sticky bit.
+    let x = x.aligned();
+    let shift = y.exponent - x.exponent;
+    let y_significand = if shift >= 0 {
+        y.significand << shift
+    } else {
+        shift_right_jamming(y.significand, shift.unsigned_abs())
+    };
+    let (negative, significand) = if x.negative == y.negative {
+        (x.negative, x.significand + y_significand)
+    } else if x.significand >= y_significand {
+        (x.negative, x.significand - y_significand)
+    } else {
+        (y.negative, y_significand - x.significand)
+    };
+    if significand == 0 {
+        // Exact cancellation
+        return p.zero(env.rounding == RoundingMode::Down);
+    }
+    let exact = Exact {
+        negative,
+        exponent: x.exponent,
+        significand,
+    };
+    round(p, exact, env)
+}
+
+/// Rounds a number to the given precision and packs it into bits, raising
+/// exceptions as needed.
+fn round(p: Precision, x: Exact, env: &mut Env) -> u64 {
+    let fraction_bits = p.fraction_bits() as i32;
+    let min_exponent = 1 - p.bias();
+    let msb = x.msb();
+    // The exponent of the least significant bit that fits, which is limited
+    // by the exponent range for subnormals
+    let mut lsb = (msb - fraction_bits).max(min_exponent - fraction_bits);
+    let (mut significand, inexact) =
+        shift_to(x.significand, lsb - x.exponent, x.negative, env.rounding);
+    if significand >> (fraction_bits + 1) != 0 {
+        // Rounding carried into a new bit
+        significand >>= 1;
+        lsb += 1;
+    }
+    if inexact {
+        env.flags |= INEXACT;
+        // Tininess is detected after rounding, as if the exponent range
+        // were unbounded
+        if msb < min_exponent {
+            let (unbounded, _) = shift_to(
+                x.significand,
+                msb - fraction_bits - x.exponent,
+                x.negative,
+                env.rounding,
+            );
+            let carried = unbounded >> (fraction_bits + 1) != 0;
+            if !carried || msb + 1 < min_exponent {
+                env.flags |= UNDERFLOW;
+            }
+        }
+    }
+    let exponent = if significand >> fraction_bits == 0 {
+        0
+    } else {
+        (lsb + fraction_bits + p.bias()) as u64
+    };
+    if exponent >= p.max_exponent() {
+        env.flags |= OVERFLOW | INEXACT;
+        let to_infinity = match env.rounding {
+            RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => {
+                true
+            }
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => x.negative,
+            RoundingMode::Up => !x.negative,
+        };
+        return if to_infinity {
+            p.infinity(x.negative)
+        } else {
+            p.max_finite(x.negative)
+        };
+    }
+    let fraction = significand as u64 & ((1 << fraction_bits) - 1);
+    p.pack(x.negative, exponent, fraction)
+}
+
+/// Shifts a significand right, or left if `shift` is negative, rounding off
+/// the bits that get shifted out. Returns whether the result is inexact.
+fn shift_to(
+    significand: u128,
+    shift: i32,
+    negative: bool,
+    rounding: RoundingMode,
+) -> (u128, bool) {
+    if shift <= 0 {
+        (significand << shift.unsigned_abs(), false)
+    } else {
+        shift_right_rounding(significand, shift as u32, negative, rounding)
+    }
+}
+
+fn shift_right_rounding(
+    significand: u128,
+    shift: u32,
+    negative: bool,
+    rounding: RoundingMode,
+) -> (u128, bool) {
+    let (kept, remainder) = if shift >= 128 {
+        (0, significand)
+    } else {
+        (significand >> shift, significand & ((1 << shift) - 1))
+    };
+    // How the remainder compares to half of the least significant bit kept
+    let half = match shift {
+        0 => Ordering::Less,
+        1..=128 => remainder.cmp(&(1 << (shift - 1))),
+        _ => Ordering::Less,
+    };
+    let round_up = match rounding {
+        RoundingMode::NearestEven => {
+            half == Ordering::Greater
+                || half == Ordering::Equal && kept & 1 == 1
+        }
+        RoundingMode::NearestMaxMagnitude => half != Ordering::Less,
+        RoundingMode::TowardZero => false,
+        RoundingMode::Down => negative && remainder != 0,
+        RoundingMode::Up => !negative && remainder != 0,
+    };
+    (kept + u128::from(round_up), remainder != 0)
+}
+
+/// Shifts right, setting the lowest bit if any of the bits shifted out were
+/// set.
+fn shift_right_jamming(significand: u128, shift: u32) -> u128 {
+    if shift >= 128 {
+        u128::from(significand != 0)
+    } else {
+        significand >> shift | u128::from(significand & ((1 << shift) - 1) != 0)
+    }
+}
+
+/// Compares two values that aren't NaNs, treating zeros as equal.
+fn compare_numbers(p: Precision, a: u64, b: u64) -> Ordering {
+    // Sign-magnitude turned into something that compares like integers
+    let key = |bits: u64| {
+        let magnitude = (bits & !p.sign_bit()) as i64;
+        if bits & p.sign_bit() != 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    };
+    key(a).cmp(&key(b))
+}
+
+/// The index of the most significant set bit.
+const fn msb_index(value: u128) -> u32 {
+    127 - value.leading_zeros()
+}
+
+/// Computes the integer square root along with the remainder.
+fn integer_sqrt(value: u128) -> (u128, u128) {
+    let mut root = 0;
+    let mut remainder = value;
+    let mut bit = 1 << (msb_index(value) & !1);
+    while bit != 0 {
+        if remainder >= root + bit {
+            remainder -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    (root, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add, div, from_int, mul, sqrt, to_int, Env, IntType,
+        Precision::{Double, Single},
+        RoundingMode::{self, *},
+        DIVIDE_BY_ZERO, INEXACT, INVALID, OVERFLOW, UNDERFLOW,
+    };
+
+    const ONE: u64 = 0x3f80_0000;
+    const HALF: u64 = 0x3f00_0000;
+    const TWO: u64 = 0x4000_0000;
+    /// Half of the gap between 1 and the next single-precision value.
+    const HALF_ULP: u64 = 0x3380_0000;
+    const MAX: u64 = 0x7f7f_ffff;
+    const MIN_NORMAL: u64 = 0x0080_0000;
+    const INFINITY: u64 = 0x7f80_0000;
+    const NEGATIVE_ONE: u64 = 0xbf80_0000;
+
+    /// Runs an operation, returning its result and the flags it raised.
+    fn run(
+        rounding: RoundingMode,
+        op: impl FnOnce(&mut Env) -> u64,
+    ) -> (u64, u8) {
+        let mut env = Env::new(rounding);
+        let result = op(&mut env);
+        (result, env.flags)
+    }
+
+    #[test]
+    fn rounding_modes() {
+        // 1 + half an ulp is a tie between 1 and the next value up
+        let tie = |env: &mut Env| add(Single, ONE, HALF_ULP, env);
+        assert_eq!(run(NearestEven, tie), (ONE, INEXACT));
+        assert_eq!(run(NearestMaxMagnitude, tie), (ONE + 1, INEXACT));
+        assert_eq!(run(TowardZero, tie), (ONE, INEXACT));
+        assert_eq!(run(Down, tie), (ONE, INEXACT));
+        assert_eq!(run(Up, tie), (ONE + 1, INEXACT));
+        // Ties go to the even neighbor, which is up from an odd value
+        let odd_tie = |env: &mut Env| add(Single, ONE + 1, HALF_ULP, env);
+        assert_eq!(run(NearestEven, odd_tie), (ONE + 2, INEXACT));
+        // Directed rounding depends on the sign
+        let negative =
+            |env: &mut Env| add(Single, NEGATIVE_ONE, HALF_ULP | 1 << 31, env);
+        assert_eq!(run(Down, negative), (NEGATIVE_ONE + 1, INEXACT));
+        assert_eq!(run(Up, negative), (NEGATIVE_ONE, INEXACT));
+        assert_eq!(run(TowardZero, negative), (NEGATIVE_ONE, INEXACT));
+        // 0.1 + 0.2 isn't exact in binary
+        let sum = run(NearestEven, |env| {
+            add(Double, 0x3fb9_9999_9999_999a, 0x3fc9_9999_9999_999a, env)
+        });
+        assert_eq!(sum, (0x3fd3_3333_3333_3334, INEXACT));
+    }
+
+    #[test]
+    fn exact_results_raise_no_flags() {
+        assert_eq!(
+            run(NearestEven, |env| add(Single, ONE, ONE, env)),
+            (TWO, 0)
+        );
+        assert_eq!(
+            run(NearestEven, |env| mul(Single, TWO, HALF, env)),
+            (ONE, 0)
+        );
+    }
+
+    #[test]
+    fn overflow_and_underflow() {
+        let overflow = |env: &mut Env| mul(Single, MAX, TWO, env);
+        assert_eq!(run(NearestEven, overflow), (INFINITY, OVERFLOW | INEXACT));
+        // Rounding toward zero never overflows to infinity
+        assert_eq!(run(TowardZero, overflow), (MAX, OVERFLOW | INEXACT));
+        // An exact subnormal result isn't an underflow
+        let exact = |env: &mut Env| mul(Single, MIN_NORMAL, HALF, env);
+        assert_eq!(run(NearestEven, exact), (0x0040_0000, 0));
+        let inexact = |env: &mut Env| mul(Single, MIN_NORMAL + 1, HALF, env);
+        assert_eq!(
+            run(NearestEven, inexact),
+            (0x0040_0000, UNDERFLOW | INEXACT)
+        );
+    }
+
+    #[test]
+    fn invalid_operations() {
+        let nan = Single.canonical_nan();
+        let root = |env: &mut Env| sqrt(Single, NEGATIVE_ONE, env);
+        assert_eq!(run(NearestEven, root), (nan, INVALID));
+        let zero_by_zero = |env: &mut Env| div(Single, 0, 0, env);
+        assert_eq!(run(NearestEven, zero_by_zero), (nan, INVALID));
+        let by_zero = |env: &mut Env| div(Single, ONE, 0, env);
+        assert_eq!(run(NearestEven, by_zero), (INFINITY, DIVIDE_BY_ZERO));
+    }
+
+    #[test]
+    fn integer_conversions() {
+        const TWO_AND_A_HALF: u64 = 0x4020_0000;
+        let word =
+            |env: &mut Env| to_int(Single, TWO_AND_A_HALF, IntType::Word, env);
+        assert_eq!(run(NearestEven, word), (2, INEXACT));
+        assert_eq!(run(NearestMaxMagnitude, word), (3, INEXACT));
+        // Out of range values saturate, and NaNs become the largest integer
+        let nan = |env: &mut Env| {
+            to_int(Single, Single.canonical_nan(), IntType::Word, env)
+        };
+        assert_eq!(run(NearestEven, nan), (0x7fff_ffff, INVALID));
+        let negative = |env: &mut Env| {
+            to_int(Single, NEGATIVE_ONE, IntType::UnsignedLong, env)
+        };
+        assert_eq!(run(NearestEven, negative), (0, INVALID));
+        let too_small =
+            |env: &mut Env| to_int(Single, 0xdf00_0000, IntType::Word, env);
+        assert_eq!(run(NearestEven, too_small), (i32::MIN as u64, INVALID));
+        // 2^24 + 1 needs more bits than single precision has
+        let inexact =
+            |env: &mut Env| from_int(Single, (1 << 24) + 1, IntType::Long, env);
+        assert_eq!(run(NearestEven, inexact), (0x4b80_0000, INEXACT));
+    }
+}
@@ -0,0 +1,473 @@
+//! A stub implementing the GDB remote serial protocol, so that a debugger such
+//! as `riscv64-unknown-elf-gdb` can attach to the guest with
+//! `target remote :<port>`.
+//!
+//! On a machine with several harts, the debugger only sees hart 0, while the
+//! other harts keep running alongside it.
+
+use crate::{
+    cpu::{Cpu, ExitReason},
+    error::Error,
+    machine::Machine,
+    mmu::Access,
+    register::RegisterName,
+    trap::Exception,
+};
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+};
+
+/// GDB's register number for `pc`, which comes after `x0` through `x31`.
+const PC_REGNUM: usize = 32;
+/// Memory is translated one page at a time.
+const PAGE_SIZE: u64 = 4096;
+/// How many instructions to run between checking for an interrupt request.
+const INTERRUPT_POLL_INTERVAL: usize = 4096;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+const SIGBUS: u8 = 7;
+const SIGSEGV: u8 = 11;
+
+/// How the guest stopped after being resumed.
+enum Stop {
+    Signal(u8),
+    Exited(ExitReason),
+}
+
+struct Stub<'a> {
+    machine: &'a mut Machine,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    breakpoints: HashSet<u64>,
+}
+
+/// Waits for a debugger to connect on the given port and lets it control the
+/// guest until the guest exits.
+pub fn serve(
+    machine: &mut Machine,
+    port: u16,
+) -> Result<ExitReason, Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    eprintln!("Waiting for GDB to connect on port {port}");
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    let mut stub = Stub {
+        machine,
+        reader: BufReader::new(stream.try_clone()?),
+        writer: stream,
+        breakpoints: HashSet::new(),
+    };
+
+    loop {
+        let Some(packet) = stub.receive_packet()? else {
+            // The debugger went away, so let the guest run on its own
+            return Ok(stub.machine.run()?);
+        };
+        match stub.handle_packet(&packet)? {
+            Some(Stop::Exited(exit_reason)) => return Ok(exit_reason),
+            Some(Stop::Signal(_)) | None => {}
+        }
+        if packet == "D" {
+            return Ok(stub.machine.run()?);
+        }
+    }
+}
+
+impl Stub<'_> {
+    fn hart(&mut self) -> &mut Cpu {
+        self.machine.debugged_hart()
+    }
+
+    /// Splits a range of the debugged hart's virtual memory into the
+    /// physical ranges of the pages that it covers, or returns `None` if any
+    /// of them can't be accessed.
+    fn translate_range(
+        &mut self,
+        address: u64,
+        len: usize,
+        access: Access,
+    ) -> Option<Vec<(u64, usize)>> {
+        let mut ranges = Vec::new();
+        let mut done = 0;
+        while done < len {
+            let address = address.checked_add(done as u64)?;
+            let chunk =
+                ((PAGE_SIZE - address % PAGE_SIZE) as usize).min(len - done);
+            let physical =
+                self.hart().translate_for_debugger(address, access)?;
+            ranges.push((physical, chunk));
+            done += chunk;
+        }
+        Some(ranges)
+    }
+
+    fn read_memory(&mut self, address: u64, len: usize) -> Option<Vec<u8>> {
+        let ranges = self.translate_range(address, len, Access::Load)?;
+        let memory = self.machine.memory();
+        let mut bytes = Vec::with_capacity(len);
+        for (physical, len) in ranges {
+            bytes.extend_from_slice(memory.slice(physical, len).ok()?);
+        }
+        Some(bytes)
+    }
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Option<()> {
+        let ranges =
+            self.translate_range(address, data.len(), Access::Store)?;
+        let mut memory = self.machine.memory_mut();
+        let mut rest = data;
+        for (physical, len) in ranges {
+            let (chunk, after) = rest.split_at(len);
+            memory.write_bytes(physical, chunk).ok()?;
+            rest = after;
+        }
+        Some(())
+    }
+
+    /// Handles a single packet, returning how the guest stopped if it was
+    /// resumed.
+    fn handle_packet(&mut self, packet: &str) -> io::Result<Option<Stop>> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => format!("S{SIGTRAP:02x}"),
+            "g" => {
+                let mut reply = String::new();
+                for reg in RegisterName::all() {
+                    push_hex_u64(&mut reply, self.hart()[reg]);
+                }
+                push_hex_u64(&mut reply, self.hart().pc());
+                reply
+            }
+            "G" => {
+                let values = decode_hex(args)
+                    .map(|bytes| {
+                        bytes
+                            .chunks_exact(8)
+                            .map(|chunk| {
+                                u64::from_le_bytes(chunk.try_into().unwrap())
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .filter(|values| values.len() > PC_REGNUM);
+                match values {
+                    Some(values) => {
+                        for (reg, value) in RegisterName::all().zip(&values) {
+                            self.hart()[reg] = *value;
+                        }
+                        self.hart().set_pc(values[PC_REGNUM]);
+                        "OK".to_owned()
+                    }
+                    None => "E01".to_owned(),
+                }
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(PC_REGNUM) => hex_u64(self.hart().pc()),
+                Ok(n) => RegisterName::all().nth(n).map_or_else(
+                    || "E01".to_owned(),
+                    |reg| hex_u64(self.hart()[reg]),
+                ),
+                Err(_) => "E01".to_owned(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, value)| {
+                    let n = usize::from_str_radix(n, 16).ok()?;
+                    let value = decode_hex(value)?;
+                    Some((n, u64::from_le_bytes(value.try_into().ok()?)))
+                });
+                match parsed {
+                    Some((PC_REGNUM, value)) => {
+                        self.hart().set_pc(value);
+                        "OK".to_owned()
+                    }
+                    Some((n, value)) => match RegisterName::all().nth(n) {
+                        Some(reg) => {
+                            self.hart()[reg] = value;
+                            "OK".to_owned()
+                        }
+                        None => "E01".to_owned(),
+                    },
+                    None => "E01".to_owned(),
+                }
+            }
+            "m" => match parse_address_and_length(args) {
+                Some((address, len)) => {
+                    self.read_memory(address, len).map_or_else(
+                        || "E14".to_owned(),
+                        |bytes| encode_hex(&bytes),
+                    )
+                }
+                None => "E01".to_owned(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    Some((parse_address_and_length(range)?, decode_hex(data)?))
+                });
+                match parsed {
+                    Some(((address, len), data)) if data.len() == len => {
+                        match self.write_memory(address, &data) {
+                            Some(()) => "OK".to_owned(),
+                            None => "E14".to_owned(),
+                        }
+                    }
+                    _ => "E01".to_owned(),
+                }
+            }
+            "s" | "c" => {
+                if let Ok(address) = u64::from_str_radix(args, 16) {
+                    self.hart().set_pc(address);
+                }
+                let stop = if command == "s" {
+                    self.step()
+                } else {
+                    self.resume()?
+                };
+                let reply = match &stop {
+                    Stop::Signal(signal) => format!("S{signal:02x}"),
+                    Stop::Exited(exit_reason) => {
+                        format!("W{:02x}", exit_reason.exit_code() as u8)
+                    }
+                };
+                self.send_packet(&reply)?;
+                return Ok(Some(stop));
+            }
+            "Z" | "z" => {
+                let address = args
+                    .strip_prefix("0,")
+                    .and_then(|args| args.split(',').next())
+                    .and_then(|address| u64::from_str_radix(address, 16).ok());
+                match address {
+                    Some(address) => {
+                        if command == "Z" {
+                            self.breakpoints.insert(address);
+                        } else {
+                            self.breakpoints.remove(&address);
+                        }
+                        "OK".to_owned()
+                    }
+                    // Only software breakpoints are supported
+                    None => String::new(),
+                }
+            }
+            "k" => return Ok(Some(Stop::Exited(ExitReason::Killed))),
+            "D" | "H" => "OK".to_owned(),
+            "q" => self.query(args),
+            _ => String::new(),
+        };
+        self.send_packet(&reply)?;
+        Ok(None)
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            "PacketSize=4000;qXfer:features:read+;swbreak+".to_owned()
+        } else if let Some(range) =
+            query.strip_prefix("Xfer:features:read:target.xml:")
+        {
+            let Some((offset, len)) = parse_address_and_length(range) else {
+                return "E01".to_owned();
+            };
+            let xml = target_xml();
+            let offset = (offset as usize).min(xml.len());
+            let chunk = &xml[offset..][..len.min(xml.len() - offset)];
+            let marker = if offset + chunk.len() == xml.len() {
+                'l'
+            } else {
+                'm'
+            };
+            format!("{marker}{chunk}")
+        } else if query == "Attached" {
+            "1".to_owned()
+        } else if query == "C" {
+            "QC1".to_owned()
+        } else if query == "fThreadInfo" {
+            "m1".to_owned()
+        } else if query == "sThreadInfo" {
+            "l".to_owned()
+        } else {
+            String::new()
+        }
+    }
+
+    fn step(&mut self) -> Stop {
+        match self.machine.step() {
+            Ok(Some(exit_reason)) => Stop::Exited(exit_reason),
+            Ok(None) => Stop::Signal(SIGTRAP),
+            Err(err) => Stop::Signal(signal_for_error(&err)),
+        }
+    }
+
+    /// Runs until a breakpoint is hit, the guest stops on its own or the
+    /// debugger asks to interrupt it.
+    fn resume(&mut self) -> io::Result<Stop> {
+        let mut steps = 0;
+        loop {
+            match self.machine.step() {
+                Ok(Some(exit_reason)) => return Ok(Stop::Exited(exit_reason)),
+                Ok(None) => {}
+                Err(err) => return Ok(Stop::Signal(signal_for_error(&err))),
+            }
+            let pc = self.hart().pc();
+            if self.breakpoints.contains(&pc) {
+                return Ok(Stop::Signal(SIGTRAP));
+            }
+            steps += 1;
+            if steps % INTERRUPT_POLL_INTERVAL == 0
+                && self.interrupt_requested()?
+            {
+                return Ok(Stop::Signal(SIGINT));
+            }
+        }
+    }
+
+    /// Checks whether the debugger has sent a break character without
+    /// blocking.
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_nonblocking(true)?;
+            let result = self.reader.fill_buf().map(|_| ());
+            self.reader.get_ref().set_nonblocking(false)?;
+            match result {
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    return Ok(false);
+                }
+                result => result?,
+            }
+        }
+        if self.reader.buffer().first() == Some(&0x03) {
+            self.reader.consume(1);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Reads the next packet, acknowledging it. Returns `None` if the
+    /// connection was closed.
+    fn receive_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            // Anything outside of a packet, such as acknowledgements or a
+            // stray interrupt request, is ignored
+            if byte[0] != b'$' {
+                continue;
+            }
+            let mut data = Vec::new();
+            if self.reader.read_until(b'#', &mut data)? == 0 {
+                return Ok(None);
+            }
+            data.pop();
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(compute_checksum(&data));
+            if valid {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.writer.write_all(b"-")?;
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let checksum = compute_checksum(data.as_bytes());
+        write!(self.writer, "${data}#{checksum:02x}")?;
+        self.writer.flush()
+    }
+}
+
+fn signal_for_error(err: &Error) -> u8 {
+    match err {
+        Error::UnknownInstruction(_)
+        | Error::UnknownCompressedInstruction(_)
+        | Error::UnhandledTrap {
+            exception: Exception::IllegalInstruction(_),
+            ..
+        } => SIGILL,
+        Error::UnhandledTrap {
+            exception: Exception::Breakpoint(_),
+            ..
+        } => SIGTRAP,
+        Error::UnhandledTrap {
+            exception:
+                Exception::InstructionAddressMisaligned(_)
+                | Exception::LoadAddressMisaligned(_)
+                | Exception::StoreAddressMisaligned(_),
+            ..
+        } => SIGBUS,
+        Error::AccessFault(_) | Error::UnhandledTrap { .. } => SIGSEGV,
+        // The emulator itself failed rather than the program
+        Error::StackSize(_) | Error::Trace(_) => SIGABRT,
+        #[cfg(feature = "jit")]
+        Error::Jit(_) => SIGABRT,
+    }
+}
+
+fn compute_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Parses the `address,length` pair used by several packets.
+fn parse_address_and_length(args: &str) -> Option<(u64, usize)> {
+    let (address, len) = args.split_once(',')?;
+    Some((
+        u64::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Registers are transferred in target byte order, which is little-endian.
+fn hex_u64(value: u64) -> String {
+    encode_hex(&value.to_le_bytes())
+}
+
+fn push_hex_u64(hex: &mut String, value: u64) {
+    hex.push_str(&hex_u64(value));
+}
+
+/// Describes the register file so that GDB doesn't have to guess.
+fn target_xml() -> String {
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0"?>"#,
+        r#"<!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+        r#"<target version="1.0">"#,
+        "<architecture>riscv:rv64</architecture>",
+        r#"<feature name="org.gnu.gdb.riscv.cpu">"#,
+    ));
+    for reg in RegisterName::all() {
+        let kind = match usize::from(reg) {
+            1 => "code_ptr",
+            2 => "data_ptr",
+            _ => "int",
+        };
+        let _ =
+            write!(xml, r#"<reg name="{reg:?}" bitsize="64" type="{kind}"/>"#);
+    }
+    xml.push_str(r#"<reg name="pc" bitsize="64" type="code_ptr"/>"#);
+    xml.push_str("</feature></target>");
+    xml
+}
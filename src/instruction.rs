@@ -1,10 +1,11 @@
 use crate::{
     bits::{u16_sms, u32_mask, u32_sms, SignExtend},
     error::Error,
-    register::RegisterName,
+    float::{IntType, Precision},
+    register::{FRegisterName, RegisterName},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Instruction {
     R {
         funct: RFunct,
@@ -39,7 +40,159 @@ pub enum Instruction {
         rd: RegisterName,
         imm: i32,
     },
+    FLoad {
+        precision: Precision,
+        rd: FRegisterName,
+        rs1: RegisterName,
+        imm: u32,
+    },
+    FStore {
+        precision: Precision,
+        rs2: FRegisterName,
+        rs1: RegisterName,
+        imm: u16,
+    },
+    FArith {
+        funct: FArithFunct,
+        precision: Precision,
+        rd: FRegisterName,
+        rs1: FRegisterName,
+        rs2: FRegisterName,
+        rm: u8,
+    },
+    FSqrt {
+        precision: Precision,
+        rd: FRegisterName,
+        rs1: FRegisterName,
+        rm: u8,
+    },
+    FSign {
+        funct: FSignFunct,
+        precision: Precision,
+        rd: FRegisterName,
+        rs1: FRegisterName,
+        rs2: FRegisterName,
+    },
+    FMinMax {
+        funct: FMinMaxFunct,
+        precision: Precision,
+        rd: FRegisterName,
+        rs1: FRegisterName,
+        rs2: FRegisterName,
+    },
+    FFma {
+        funct: FFmaFunct,
+        precision: Precision,
+        rd: FRegisterName,
+        rs1: FRegisterName,
+        rs2: FRegisterName,
+        rs3: FRegisterName,
+        rm: u8,
+    },
+    FCompare {
+        funct: FCompareFunct,
+        precision: Precision,
+        rd: RegisterName,
+        rs1: FRegisterName,
+        rs2: FRegisterName,
+    },
+    FClass {
+        precision: Precision,
+        rd: RegisterName,
+        rs1: FRegisterName,
+    },
+    /// Moves the raw bits of a floating point register to an integer
+    /// register.
+    FMvToInt {
+        precision: Precision,
+        rd: RegisterName,
+        rs1: FRegisterName,
+    },
+    FMvFromInt {
+        precision: Precision,
+        rd: FRegisterName,
+        rs1: RegisterName,
+    },
+    FCvtToInt {
+        precision: Precision,
+        int: IntType,
+        rd: RegisterName,
+        rs1: FRegisterName,
+        rm: u8,
+    },
+    FCvtFromInt {
+        precision: Precision,
+        int: IntType,
+        rd: FRegisterName,
+        rs1: RegisterName,
+        rm: u8,
+    },
+    /// Converts a value of the other precision to `precision`.
+    FCvtFloat {
+        precision: Precision,
+        rd: FRegisterName,
+        rs1: FRegisterName,
+        rm: u8,
+    },
+    /// Loads a value and reserves its address for a later
+    /// `StoreConditional`.
+    LoadReserved {
+        width: AmoWidth,
+        rd: RegisterName,
+        rs1: RegisterName,
+        aq: bool,
+        rl: bool,
+    },
+    /// Stores a value if the reservation is still held, writing 0 to `rd` on
+    /// success and 1 on failure.
+    StoreConditional {
+        width: AmoWidth,
+        rd: RegisterName,
+        rs1: RegisterName,
+        rs2: RegisterName,
+        aq: bool,
+        rl: bool,
+    },
+    /// Atomically combines a value in memory with `rs2`, writing the old
+    /// value to `rd`.
+    Amo {
+        funct: AmoFunct,
+        width: AmoWidth,
+        rd: RegisterName,
+        rs1: RegisterName,
+        rs2: RegisterName,
+        aq: bool,
+        rl: bool,
+    },
+    /// Orders the memory accesses in `pred` before those in `succ`, each
+    /// being a set of `FENCE_*` bits.
+    Fence {
+        fm: u8,
+        pred: u8,
+        succ: u8,
+    },
+    FenceI,
+    Csr {
+        funct: CsrFunct,
+        rd: RegisterName,
+        rs1: RegisterName,
+        csr: u16,
+    },
+    CsrImm {
+        funct: CsrFunct,
+        rd: RegisterName,
+        uimm: u8,
+        csr: u16,
+    },
     Ecall,
+    Ebreak,
+    Sret,
+    Mret,
+    Wfi,
+    SfenceVma {
+        rs1: RegisterName,
+        rs2: RegisterName,
+    },
 }
 
 impl TryFrom<u32> for Instruction {
@@ -49,20 +202,35 @@ impl TryFrom<u32> for Instruction {
         let raw_opcode = (word & u32_mask(7)) as u8;
         if word == 0x0000_0073 {
             Ok(Self::Ecall)
+        } else if word == 0x0010_0073 {
+            Ok(Self::Ebreak)
+        } else if word == 0x1020_0073 {
+            Ok(Self::Sret)
+        } else if word == 0x3020_0073 {
+            Ok(Self::Mret)
+        } else if word == 0x1050_0073 {
+            Ok(Self::Wfi)
+        } else if word & 0xfe00_7fff == 0x1200_0073 {
+            Ok(Self::SfenceVma {
+                rs1: RegisterName::rs1(word),
+                rs2: RegisterName::rs2(word),
+            })
         } else {
             match raw_opcode {
-                0b011_0011 => Ok(Self::R {
+                0b011_0011 | 0b011_1011 => Ok(Self::R {
                     funct: RFunct::try_from(word)?,
                     rs2: RegisterName::rs2(word),
                     rs1: RegisterName::rs1(word),
                     rd: RegisterName::rd(word),
                 }),
-                0b001_0011 | 0b000_0011 | 0b110_0111 => Ok(Self::I {
-                    imm: u32_sms(word, 20, 12, 0),
-                    rs1: RegisterName::rs1(word),
-                    funct: IFunct::try_from(word)?,
-                    rd: RegisterName::rd(word),
-                }),
+                0b001_0011 | 0b001_1011 | 0b000_0011 | 0b110_0111 => {
+                    Ok(Self::I {
+                        imm: u32_sms(word, 20, 12, 0),
+                        rs1: RegisterName::rs1(word),
+                        funct: IFunct::try_from(word)?,
+                        rd: RegisterName::rd(word),
+                    })
+                }
                 0b010_0011 => Ok(Self::S {
                     imm: (u32_sms(word, 25, 7, 5) | u32_sms(word, 7, 5, 0))
                         as u16,
@@ -100,6 +268,62 @@ impl TryFrom<u32> for Instruction {
                         >> 11,
                     rd: RegisterName::rd(word),
                 }),
+                0b000_0111 => Ok(Self::FLoad {
+                    precision: memory_precision(word)?,
+                    rd: FRegisterName::rd(word),
+                    rs1: RegisterName::rs1(word),
+                    imm: u32_sms(word, 20, 12, 0),
+                }),
+                0b010_0111 => Ok(Self::FStore {
+                    precision: memory_precision(word)?,
+                    rs2: FRegisterName::rs2(word),
+                    rs1: RegisterName::rs1(word),
+                    imm: (u32_sms(word, 25, 7, 5) | u32_sms(word, 7, 5, 0))
+                        as u16,
+                }),
+                0b100_0011 | 0b100_0111 | 0b100_1011 | 0b100_1111 => {
+                    Ok(Self::FFma {
+                        funct: FFmaFunct::try_from(word)?,
+                        precision: fp_precision(word)?,
+                        rd: FRegisterName::rd(word),
+                        rs1: FRegisterName::rs1(word),
+                        rs2: FRegisterName::rs2(word),
+                        rs3: FRegisterName::rs3(word),
+                        rm: u32_sms(word, 12, 3, 0) as u8,
+                    })
+                }
+                0b101_0011 => decode_op_fp(word),
+                0b010_1111 => decode_amo(word),
+                0b000_1111 => match u32_sms(word, 12, 3, 0) {
+                    // `fence.tso` and `pause` are encoded as fences too
+                    0b000 => Ok(Self::Fence {
+                        fm: u32_sms(word, 28, 4, 0) as u8,
+                        pred: u32_sms(word, 24, 4, 0) as u8,
+                        succ: u32_sms(word, 20, 4, 0) as u8,
+                    }),
+                    0b001 => Ok(Self::FenceI),
+                    _ => Err(Error::UnknownInstruction(word)),
+                },
+                0b111_0011 if u32_sms(word, 12, 3, 0) != 0 => {
+                    let funct = CsrFunct::try_from(word)?;
+                    let csr = u32_sms(word, 20, 12, 0) as u16;
+                    let rd = RegisterName::rd(word);
+                    if u32_sms(word, 14, 1, 0) == 0 {
+                        Ok(Self::Csr {
+                            funct,
+                            rd,
+                            rs1: RegisterName::rs1(word),
+                            csr,
+                        })
+                    } else {
+                        Ok(Self::CsrImm {
+                            funct,
+                            rd,
+                            uimm: u32_sms(word, 15, 5, 0) as u8,
+                            csr,
+                        })
+                    }
+                }
                 _ => Err(Error::UnknownInstruction(word)),
             }
         }
@@ -117,12 +341,57 @@ impl TryFrom<u16> for Instruction {
         match word & 0b11 {
             0b11 => Err(Ok(NeedMoreBytes)),
             0b00 => match funct3 {
-                0b000 => todo!("addi4spn"),
-                0b001 => todo!("fld"),
-                0b010 => todo!("lw"),
-                0b011 => todo!("ld"),
+                0b000 => {
+                    let imm = u16_sms(word, 11, 2, 4)
+                        | u16_sms(word, 7, 4, 6)
+                        | u16_sms(word, 6, 1, 2)
+                        | u16_sms(word, 5, 1, 3);
+                    if imm == 0 {
+                        // This includes the all-zero halfword, which is
+                        // defined to be illegal
+                        unknown_instruction
+                    } else {
+                        Ok(Self::I {
+                            imm: u32::from(imm),
+                            rs1: RegisterName::X2,
+                            funct: IFunct::Addi,
+                            rd: RegisterName::compressed_common_rs2(word),
+                        })
+                    }
+                }
+                0b001 => Ok(Self::FLoad {
+                    precision: Precision::Double,
+                    rd: FRegisterName::compressed_common_rs2(word),
+                    rs1: RegisterName::compressed_common_rs1(word),
+                    imm: u32::from(
+                        u16_sms(word, 5, 2, 6) | u16_sms(word, 10, 3, 3),
+                    ),
+                }),
+                0b010 => Ok(Self::I {
+                    imm: u32::from(
+                        u16_sms(word, 5, 1, 6)
+                            | u16_sms(word, 10, 3, 3)
+                            | u16_sms(word, 6, 1, 2),
+                    ),
+                    rs1: RegisterName::compressed_common_rs1(word),
+                    funct: IFunct::Lw,
+                    rd: RegisterName::compressed_common_rs2(word),
+                }),
+                0b011 => Ok(Self::I {
+                    imm: u32::from(
+                        u16_sms(word, 5, 2, 6) | u16_sms(word, 10, 3, 3),
+                    ),
+                    rs1: RegisterName::compressed_common_rs1(word),
+                    funct: IFunct::Ld,
+                    rd: RegisterName::compressed_common_rs2(word),
+                }),
                 0b100 => unknown_instruction,
-                0b101 => todo!("fsd"),
+                0b101 => Ok(Self::FStore {
+                    precision: Precision::Double,
+                    rs2: FRegisterName::compressed_common_rs2(word),
+                    rs1: RegisterName::compressed_common_rs1(word),
+                    imm: u16_sms(word, 5, 2, 6) | u16_sms(word, 10, 3, 3),
+                }),
                 0b110 => Ok(Self::S {
                     funct: SFunct::Sw,
                     rs2: RegisterName::compressed_common_rs2(word),
@@ -149,7 +418,19 @@ impl TryFrom<u16> for Instruction {
                         rd: reg,
                     })
                 }
-                0b001 => todo!("addiw"),
+                0b001 => {
+                    let reg = RegisterName::compressed_rd(word);
+                    if reg == RegisterName::X0 {
+                        unknown_instruction
+                    } else {
+                        Ok(Self::I {
+                            imm: compressed_6bit_imm(word),
+                            rs1: reg,
+                            funct: IFunct::Addiw,
+                            rd: reg,
+                        })
+                    }
+                }
                 0b010 => Ok(Self::I {
                     imm: compressed_6bit_imm(word),
                     rs1: RegisterName::X0,
@@ -157,7 +438,10 @@ impl TryFrom<u16> for Instruction {
                     rd: RegisterName::compressed_rd(word),
                 }),
                 0b011 => {
-                    if u16_sms(word, 7, 5, 0) == 2 {
+                    let rd = RegisterName::compressed_rd(word);
+                    if compressed_6bit_imm(word) == 0 {
+                        unknown_instruction
+                    } else if rd == RegisterName::X2 {
                         let high_imm: i32 = (u16_sms(word, 12, 1, 15)
                             | u16_sms(word, 3, 2, 13)
                             | u16_sms(word, 5, 1, 12)
@@ -171,15 +455,61 @@ impl TryFrom<u16> for Instruction {
                             rd: RegisterName::X2,
                         })
                     } else {
-                        todo!("lui")
+                        Ok(Self::U {
+                            opcode: UOpcode::Lui,
+                            rd,
+                            imm: (compressed_6bit_imm(word) << 12) as i32,
+                        })
+                    }
+                }
+                0b100 => {
+                    let reg = RegisterName::compressed_common_rs1(word);
+                    let rs2 = RegisterName::compressed_common_rs2(word);
+                    let shamt = u32::from(
+                        u16_sms(word, 12, 1, 5) | u16_sms(word, 2, 5, 0),
+                    );
+                    let i_type = |funct, imm| {
+                        Ok(Self::I {
+                            funct,
+                            rd: reg,
+                            rs1: reg,
+                            imm,
+                        })
+                    };
+                    let r_type = |funct| {
+                        Ok(Self::R {
+                            funct,
+                            rd: reg,
+                            rs2,
+                            rs1: reg,
+                        })
+                    };
+                    match (u16_sms(word, 10, 2, 0), u16_sms(word, 12, 1, 0)) {
+                        (0b00, _) => i_type(IFunct::Srli, shamt),
+                        (0b01, _) => i_type(IFunct::Srai, shamt),
+                        (0b10, _) => {
+                            i_type(IFunct::Andi, compressed_6bit_imm(word))
+                        }
+                        (0b11, 0) => match u16_sms(word, 5, 2, 0) {
+                            0b00 => r_type(RFunct::Sub),
+                            0b01 => r_type(RFunct::Xor),
+                            0b10 => r_type(RFunct::Or),
+                            0b11 => r_type(RFunct::And),
+                            _ => unreachable!(),
+                        },
+                        (0b11, _) => match u16_sms(word, 5, 2, 0) {
+                            0b00 => r_type(RFunct::Subw),
+                            0b01 => r_type(RFunct::Addw),
+                            _ => unknown_instruction,
+                        },
+                        _ => unreachable!(),
                     }
                 }
-                0b100 => todo!("misc-alu"),
                 0b101 => Ok(Self::Jal {
                     imm: SignExtend::<i32>::sign_extend(
                         u16_sms(word, 12, 1, 15)
                             | u16_sms(word, 8, 1, 14)
-                            | u16_sms(word, 10, 2, 12)
+                            | u16_sms(word, 9, 2, 12)
                             | u16_sms(word, 6, 1, 11)
                             | u16_sms(word, 7, 1, 10)
                             | u16_sms(word, 2, 1, 9)
@@ -190,67 +520,125 @@ impl TryFrom<u16> for Instruction {
                 }),
                 0b110 => Ok(Self::B {
                     funct: BFunct::Beq,
-                    rs2: RegisterName::compressed_common_rs1(word),
-                    rs1: RegisterName::X0,
-                    imm: (u16_sms(word, 2, 1, 5)
-                        | u16_sms(word, 3, 2, 1)
-                        | u16_sms(word, 5, 2, 6)
-                        | u16_sms(word, 10, 2, 3)
-                        | u16_sms(word, 12, 1, 8))
-                        as i16,
+                    rs2: RegisterName::X0,
+                    rs1: RegisterName::compressed_common_rs1(word),
+                    imm: compressed_branch_imm(word),
                 }),
                 0b111 => Ok(Self::B {
                     funct: BFunct::Bne,
-                    rs2: RegisterName::compressed_common_rs1(word),
-                    rs1: RegisterName::X0,
-                    imm: (u16_sms(word, 2, 1, 5)
-                        | u16_sms(word, 3, 2, 1)
-                        | u16_sms(word, 5, 2, 6)
-                        | u16_sms(word, 10, 2, 3)
-                        | u16_sms(word, 12, 1, 8))
-                        as i16,
+                    rs2: RegisterName::X0,
+                    rs1: RegisterName::compressed_common_rs1(word),
+                    imm: compressed_branch_imm(word),
                 }),
                 _ => unreachable!(),
             },
             0b10 => match funct3 {
-                0b000 => todo!("slli"),
-                0b001 => todo!("fldsp"),
-                0b010 => todo!("lwsp"),
-                0b011 => Ok(Self::I {
+                0b000 => {
+                    let reg = RegisterName::compressed_rd(word);
+                    Ok(Self::I {
+                        imm: u32::from(
+                            u16_sms(word, 12, 1, 5) | u16_sms(word, 2, 5, 0),
+                        ),
+                        rs1: reg,
+                        funct: IFunct::Slli,
+                        rd: reg,
+                    })
+                }
+                0b001 => Ok(Self::FLoad {
+                    precision: Precision::Double,
+                    rd: FRegisterName::compressed_rd(word),
+                    rs1: RegisterName::X2,
                     imm: u32::from(
                         u16_sms(word, 2, 3, 6)
                             | u16_sms(word, 12, 1, 5)
                             | u16_sms(word, 5, 2, 3),
                     ),
-                    rs1: RegisterName::X2,
-                    funct: IFunct::Ld,
-                    rd: RegisterName::compressed_rd(word),
                 }),
-                0b100 => Ok({
+                0b010 => {
+                    let rd = RegisterName::compressed_rd(word);
+                    if rd == RegisterName::X0 {
+                        unknown_instruction
+                    } else {
+                        Ok(Self::I {
+                            imm: u32::from(
+                                u16_sms(word, 2, 2, 6)
+                                    | u16_sms(word, 12, 1, 5)
+                                    | u16_sms(word, 4, 3, 2),
+                            ),
+                            rs1: RegisterName::X2,
+                            funct: IFunct::Lw,
+                            rd,
+                        })
+                    }
+                }
+                0b011 => {
+                    let rd = RegisterName::compressed_rd(word);
+                    if rd == RegisterName::X0 {
+                        unknown_instruction
+                    } else {
+                        Ok(Self::I {
+                            imm: u32::from(
+                                u16_sms(word, 2, 3, 6)
+                                    | u16_sms(word, 12, 1, 5)
+                                    | u16_sms(word, 5, 2, 3),
+                            ),
+                            rs1: RegisterName::X2,
+                            funct: IFunct::Ld,
+                            rd,
+                        })
+                    }
+                }
+                0b100 => {
                     let rd = RegisterName::compressed_rd(word);
                     let rs2 = RegisterName::compressed_rs2(word);
                     if u16_sms(word, 12, 1, 0) == 0 {
-                        if rs2 == RegisterName::X0 {
-                            Self::I {
+                        if rs2 != RegisterName::X0 {
+                            Ok(Self::R {
+                                funct: RFunct::Add,
+                                rs2,
+                                rs1: RegisterName::X0,
+                                rd,
+                            })
+                        } else if rd != RegisterName::X0 {
+                            Ok(Self::I {
                                 imm: 0,
                                 rs1: rd,
                                 funct: IFunct::Jalr,
                                 rd: RegisterName::X0,
-                            }
+                            })
                         } else {
-                            Self::R {
-                                funct: RFunct::Add,
-                                rs2,
-                                rs1: RegisterName::X0,
-                                rd,
-                            }
+                            unknown_instruction
                         }
+                    } else if rs2 != RegisterName::X0 {
+                        Ok(Self::R {
+                            funct: RFunct::Add,
+                            rs2,
+                            rs1: rd,
+                            rd,
+                        })
+                    } else if rd != RegisterName::X0 {
+                        Ok(Self::I {
+                            imm: 0,
+                            rs1: rd,
+                            funct: IFunct::Jalr,
+                            rd: RegisterName::X1,
+                        })
                     } else {
-                        todo!("ebreak/jalr/add")
+                        Ok(Self::Ebreak)
                     }
+                }
+                0b101 => Ok(Self::FStore {
+                    precision: Precision::Double,
+                    rs2: FRegisterName::compressed_rs2(word),
+                    rs1: RegisterName::X2,
+                    imm: u16_sms(word, 7, 3, 6) | u16_sms(word, 10, 3, 3),
+                }),
+                0b110 => Ok(Self::S {
+                    imm: u16_sms(word, 7, 2, 6) | u16_sms(word, 9, 4, 2),
+                    rs2: RegisterName::compressed_rs2(word),
+                    rs1: RegisterName::X2,
+                    funct: SFunct::Sw,
                 }),
-                0b101 => todo!("fsdsp"),
-                0b110 => todo!("swsp"),
                 0b111 => Ok(Self::S {
                     imm: u16_sms(word, 7, 3, 6) | u16_sms(word, 10, 3, 3),
                     rs2: RegisterName::compressed_rs2(word),
@@ -264,13 +652,212 @@ impl TryFrom<u16> for Instruction {
     }
 }
 
+/// Decodes the instructions with the `OP-FP` major opcode, which are all
+/// floating point instructions other than loads, stores and fused
+/// multiply-add.
+fn decode_op_fp(word: u32) -> Result<Instruction, Error> {
+    let unknown_instruction = Err(Error::UnknownInstruction(word));
+    let precision = fp_precision(word)?;
+    let funct3 = u32_sms(word, 12, 3, 0);
+    let rs2_field = u32_sms(word, 20, 5, 0);
+    let rd = FRegisterName::rd(word);
+    let rs1 = FRegisterName::rs1(word);
+    let rs2 = FRegisterName::rs2(word);
+    let rm = funct3 as u8;
+    match u32_sms(word, 27, 5, 0) {
+        0b00000..=0b00011 => Ok(Instruction::FArith {
+            funct: FArithFunct::try_from(word)?,
+            precision,
+            rd,
+            rs1,
+            rs2,
+            rm,
+        }),
+        0b01011 if rs2_field == 0 => Ok(Instruction::FSqrt {
+            precision,
+            rd,
+            rs1,
+            rm,
+        }),
+        0b00100 => Ok(Instruction::FSign {
+            funct: match funct3 {
+                0b000 => FSignFunct::Sgnj,
+                0b001 => FSignFunct::Sgnjn,
+                0b010 => FSignFunct::Sgnjx,
+                _ => return unknown_instruction,
+            },
+            precision,
+            rd,
+            rs1,
+            rs2,
+        }),
+        0b00101 => Ok(Instruction::FMinMax {
+            funct: match funct3 {
+                0b000 => FMinMaxFunct::Min,
+                0b001 => FMinMaxFunct::Max,
+                _ => return unknown_instruction,
+            },
+            precision,
+            rd,
+            rs1,
+            rs2,
+        }),
+        // The source precision goes in the `rs2` field
+        0b01000 => match (precision, rs2_field) {
+            (Precision::Single, 0b00001) | (Precision::Double, 0b00000) => {
+                Ok(Instruction::FCvtFloat {
+                    precision,
+                    rd,
+                    rs1,
+                    rm,
+                })
+            }
+            _ => unknown_instruction,
+        },
+        0b10100 => Ok(Instruction::FCompare {
+            funct: match funct3 {
+                0b010 => FCompareFunct::Eq,
+                0b001 => FCompareFunct::Lt,
+                0b000 => FCompareFunct::Le,
+                _ => return unknown_instruction,
+            },
+            precision,
+            rd: RegisterName::rd(word),
+            rs1,
+            rs2,
+        }),
+        0b11000 => Ok(Instruction::FCvtToInt {
+            precision,
+            int: int_type(word)?,
+            rd: RegisterName::rd(word),
+            rs1,
+            rm,
+        }),
+        0b11010 => Ok(Instruction::FCvtFromInt {
+            precision,
+            int: int_type(word)?,
+            rd,
+            rs1: RegisterName::rs1(word),
+            rm,
+        }),
+        0b11100 if rs2_field == 0 && funct3 == 0b000 => {
+            Ok(Instruction::FMvToInt {
+                precision,
+                rd: RegisterName::rd(word),
+                rs1,
+            })
+        }
+        0b11100 if rs2_field == 0 && funct3 == 0b001 => {
+            Ok(Instruction::FClass {
+                precision,
+                rd: RegisterName::rd(word),
+                rs1,
+            })
+        }
+        0b11110 if rs2_field == 0 && funct3 == 0b000 => {
+            Ok(Instruction::FMvFromInt {
+                precision,
+                rd,
+                rs1: RegisterName::rs1(word),
+            })
+        }
+        _ => unknown_instruction,
+    }
+}
+
+/// Decodes the A extension's load-reserved, store-conditional and atomic
+/// memory operation instructions.
+fn decode_amo(word: u32) -> Result<Instruction, Error> {
+    let width = match u32_sms(word, 12, 3, 0) {
+        0b010 => AmoWidth::Word,
+        0b011 => AmoWidth::Double,
+        _ => return Err(Error::UnknownInstruction(word)),
+    };
+    let rd = RegisterName::rd(word);
+    let rs1 = RegisterName::rs1(word);
+    let rs2 = RegisterName::rs2(word);
+    let aq = u32_sms(word, 26, 1, 0) != 0;
+    let rl = u32_sms(word, 25, 1, 0) != 0;
+    match u32_sms(word, 27, 5, 0) {
+        0b00010 if rs2 == RegisterName::X0 => Ok(Instruction::LoadReserved {
+            width,
+            rd,
+            rs1,
+            aq,
+            rl,
+        }),
+        0b00011 => Ok(Instruction::StoreConditional {
+            width,
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        }),
+        _ => Ok(Instruction::Amo {
+            funct: AmoFunct::try_from(word)?,
+            width,
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        }),
+    }
+}
+
+/// Decodes the `fmt` field of floating point computational instructions.
+fn fp_precision(word: u32) -> Result<Precision, Error> {
+    match u32_sms(word, 25, 2, 0) {
+        0b00 => Ok(Precision::Single),
+        0b01 => Ok(Precision::Double),
+        _ => Err(Error::UnknownInstruction(word)),
+    }
+}
+
+/// Floating point loads and stores encode the width in funct3.
+fn memory_precision(word: u32) -> Result<Precision, Error> {
+    match u32_sms(word, 12, 3, 0) {
+        0b010 => Ok(Precision::Single),
+        0b011 => Ok(Precision::Double),
+        _ => Err(Error::UnknownInstruction(word)),
+    }
+}
+
+/// Decodes the integer type of conversions, which is in the `rs2` field.
+fn int_type(word: u32) -> Result<IntType, Error> {
+    match u32_sms(word, 20, 5, 0) {
+        0b00000 => Ok(IntType::Word),
+        0b00001 => Ok(IntType::UnsignedWord),
+        0b00010 => Ok(IntType::Long),
+        0b00011 => Ok(IntType::UnsignedLong),
+        _ => Err(Error::UnknownInstruction(word)),
+    }
+}
+
 fn compressed_6bit_imm(word: u16) -> u32 {
     (SignExtend::<i32>::sign_extend(
         u16_sms(word, 12, 1, 15) | u16_sms(word, 2, 5, 10),
     ) >> 10) as u32
 }
 
-#[derive(Debug)]
+/// Decodes the offset of `c.beqz` and `c.bnez`.
+fn compressed_branch_imm(word: u16) -> i16 {
+    (u16_sms(word, 12, 1, 15)
+        | u16_sms(word, 5, 2, 13)
+        | u16_sms(word, 2, 1, 12)
+        | u16_sms(word, 10, 2, 10)
+        | u16_sms(word, 3, 2, 8)) as i16
+        >> 7
+}
+
+// The sets of accesses that a fence orders
+pub const FENCE_I: u8 = 1 << 3;
+pub const FENCE_O: u8 = 1 << 2;
+pub const FENCE_R: u8 = 1 << 1;
+pub const FENCE_W: u8 = 1 << 0;
+
+#[derive(Debug, Clone, Copy)]
 pub enum RFunct {
     Add,
     Sub,
@@ -282,6 +869,24 @@ pub enum RFunct {
     Sra,
     Or,
     And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+    Mulw,
+    Divw,
+    Divuw,
+    Remw,
+    Remuw,
+    Addw,
+    Subw,
+    Sllw,
+    Srlw,
+    Sraw,
 }
 
 impl TryFrom<u32> for RFunct {
@@ -289,23 +894,48 @@ impl TryFrom<u32> for RFunct {
 
     fn try_from(word: u32) -> Result<Self, Self::Error> {
         let raw_funct = u32_sms(word, 12, 3, 0) | u32_sms(word, 25, 7, 3);
-        match raw_funct {
-            0b0000000_000 => Ok(Self::Add),
-            0b0100000_000 => Ok(Self::Sub),
-            0b0000000_001 => Ok(Self::Sll),
-            0b0000000_010 => Ok(Self::Slt),
-            0b0000000_011 => Ok(Self::Sltu),
-            0b0000000_100 => Ok(Self::Xor),
-            0b0000000_101 => Ok(Self::Srl),
-            0b0100000_101 => Ok(Self::Sra),
-            0b0000000_110 => Ok(Self::Or),
-            0b0000000_111 => Ok(Self::And),
+        let raw_opcode = (word & u32_mask(7)) as u8;
+        match raw_opcode {
+            0b011_0011 => match raw_funct {
+                0b0000000_000 => Ok(Self::Add),
+                0b0100000_000 => Ok(Self::Sub),
+                0b0000000_001 => Ok(Self::Sll),
+                0b0000000_010 => Ok(Self::Slt),
+                0b0000000_011 => Ok(Self::Sltu),
+                0b0000000_100 => Ok(Self::Xor),
+                0b0000000_101 => Ok(Self::Srl),
+                0b0100000_101 => Ok(Self::Sra),
+                0b0000000_110 => Ok(Self::Or),
+                0b0000000_111 => Ok(Self::And),
+                0b0000001_000 => Ok(Self::Mul),
+                0b0000001_001 => Ok(Self::Mulh),
+                0b0000001_010 => Ok(Self::Mulhsu),
+                0b0000001_011 => Ok(Self::Mulhu),
+                0b0000001_100 => Ok(Self::Div),
+                0b0000001_101 => Ok(Self::Divu),
+                0b0000001_110 => Ok(Self::Rem),
+                0b0000001_111 => Ok(Self::Remu),
+                _ => Err(Error::UnknownInstruction(word)),
+            },
+            0b011_1011 => match raw_funct {
+                0b0000000_000 => Ok(Self::Addw),
+                0b0100000_000 => Ok(Self::Subw),
+                0b0000000_001 => Ok(Self::Sllw),
+                0b0000000_101 => Ok(Self::Srlw),
+                0b0100000_101 => Ok(Self::Sraw),
+                0b0000001_000 => Ok(Self::Mulw),
+                0b0000001_100 => Ok(Self::Divw),
+                0b0000001_101 => Ok(Self::Divuw),
+                0b0000001_110 => Ok(Self::Remw),
+                0b0000001_111 => Ok(Self::Remuw),
+                _ => Err(Error::UnknownInstruction(word)),
+            },
             _ => Err(Error::UnknownInstruction(word)),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum IFunct {
     Addi,
     Slti,
@@ -316,12 +946,17 @@ pub enum IFunct {
     Slli,
     Srli,
     Srai,
+    Addiw,
+    Slliw,
+    Srliw,
+    Sraiw,
     Lb,
     Lh,
     Lw,
     Ld,
     Lbu,
     Lhu,
+    Lwu,
     Jalr,
 }
 
@@ -344,6 +979,12 @@ impl TryFrom<u32> for IFunct {
                 0b101 => Ok(if srai_bit { Self::Srai } else { Self::Srli }),
                 _ => unreachable!(),
             },
+            0b001_1011 => match raw_funct {
+                0b000 => Ok(Self::Addiw),
+                0b001 => Ok(Self::Slliw),
+                0b101 => Ok(if srai_bit { Self::Sraiw } else { Self::Srliw }),
+                _ => Err(Error::UnknownInstruction(word)),
+            },
             0b000_0011 => match raw_funct {
                 0b000 => Ok(Self::Lb),
                 0b001 => Ok(Self::Lh),
@@ -351,6 +992,7 @@ impl TryFrom<u32> for IFunct {
                 0b011 => Ok(Self::Ld),
                 0b100 => Ok(Self::Lbu),
                 0b101 => Ok(Self::Lhu),
+                0b110 => Ok(Self::Lwu),
                 _ => Err(Error::UnknownInstruction(word)),
             },
             0b110_0111 => match raw_funct {
@@ -362,7 +1004,7 @@ impl TryFrom<u32> for IFunct {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum SFunct {
     Sb,
     Sh,
@@ -377,15 +1019,15 @@ impl TryFrom<u32> for SFunct {
         let raw_funct = u32_sms(word, 12, 3, 0);
         match raw_funct {
             0b000 => Ok(Self::Sb),
-            0b010 => Ok(Self::Sh),
-            0b100 => Ok(Self::Sw),
+            0b001 => Ok(Self::Sh),
+            0b010 => Ok(Self::Sw),
             0b011 => Ok(Self::Sd),
             _ => Err(Error::UnknownInstruction(word)),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum BFunct {
     Beq,
     Bne,
@@ -412,16 +1054,270 @@ impl TryFrom<u32> for BFunct {
     }
 }
 
-#[derive(Debug)]
-pub enum SOpcode {}
+/// The operation performed by both the register and immediate forms of a CSR
+/// instruction.
+#[derive(Debug, Clone, Copy)]
+pub enum CsrFunct {
+    Csrrw,
+    Csrrs,
+    Csrrc,
+}
+
+impl TryFrom<u32> for CsrFunct {
+    type Error = Error;
+
+    fn try_from(word: u32) -> Result<Self, Self::Error> {
+        // The top bit of funct3 selects the immediate form
+        let raw_funct = u32_sms(word, 12, 2, 0);
+        match raw_funct {
+            0b01 => Ok(Self::Csrrw),
+            0b10 => Ok(Self::Csrrs),
+            0b11 => Ok(Self::Csrrc),
+            _ => Err(Error::UnknownInstruction(word)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FArithFunct {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl TryFrom<u32> for FArithFunct {
+    type Error = Error;
+
+    fn try_from(word: u32) -> Result<Self, Self::Error> {
+        match u32_sms(word, 27, 5, 0) {
+            0b00000 => Ok(Self::Add),
+            0b00001 => Ok(Self::Sub),
+            0b00010 => Ok(Self::Mul),
+            0b00011 => Ok(Self::Div),
+            _ => Err(Error::UnknownInstruction(word)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FSignFunct {
+    Sgnj,
+    Sgnjn,
+    Sgnjx,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FMinMaxFunct {
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FFmaFunct {
+    Madd,
+    Msub,
+    Nmsub,
+    Nmadd,
+}
+
+impl TryFrom<u32> for FFmaFunct {
+    type Error = Error;
+
+    fn try_from(word: u32) -> Result<Self, Self::Error> {
+        let raw_opcode = (word & u32_mask(7)) as u8;
+        match raw_opcode {
+            0b100_0011 => Ok(Self::Madd),
+            0b100_0111 => Ok(Self::Msub),
+            0b100_1011 => Ok(Self::Nmsub),
+            0b100_1111 => Ok(Self::Nmadd),
+            _ => Err(Error::UnknownInstruction(word)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FCompareFunct {
+    Eq,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AmoWidth {
+    Word,
+    Double,
+}
+
+impl AmoWidth {
+    /// Size of the accessed value in bytes.
+    pub const fn size(self) -> u64 {
+        match self {
+            Self::Word => 4,
+            Self::Double => 8,
+        }
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
+pub enum AmoFunct {
+    Swap,
+    Add,
+    Xor,
+    And,
+    Or,
+    Min,
+    Max,
+    Minu,
+    Maxu,
+}
+
+impl TryFrom<u32> for AmoFunct {
+    type Error = Error;
+
+    fn try_from(word: u32) -> Result<Self, Self::Error> {
+        match u32_sms(word, 27, 5, 0) {
+            0b00001 => Ok(Self::Swap),
+            0b00000 => Ok(Self::Add),
+            0b00100 => Ok(Self::Xor),
+            0b01100 => Ok(Self::And),
+            0b01000 => Ok(Self::Or),
+            0b10000 => Ok(Self::Min),
+            0b10100 => Ok(Self::Max),
+            0b11000 => Ok(Self::Minu),
+            0b11100 => Ok(Self::Maxu),
+            _ => Err(Error::UnknownInstruction(word)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum UOpcode {
     Lui,
     Auipc,
 }
 
-#[derive(Debug)]
-pub enum JOpcode {}
-
 pub struct NeedMoreBytes;
+
+#[cfg(test)]
+mod tests {
+    use super::{IFunct, Instruction, SFunct};
+
+    /// Compressed instructions and the instructions they expand to.
+    const EXPANSIONS: [(u16, u32); 41] = [
+        (0x1fe0, 0x3fc1_0413), // c.addi4spn s0, sp, 1020
+        (0x3de8, 0x0f85_b507), // c.fld fa0, 248(a1)
+        (0x5de8, 0x07c5_a503), // c.lw a0, 124(a1)
+        (0x7cfc, 0x0f84_b783), // c.ld a5, 248(s1)
+        (0xa588, 0x00a5_b427), // c.fsd fa0, 8(a1)
+        (0xc1c8, 0x00a5_a223), // c.sw a0, 4(a1)
+        (0xe588, 0x00a5_b423), // c.sd a0, 8(a1)
+        (0x0001, 0x0000_0013), // c.nop
+        (0x1501, 0xfe05_0513), // c.addi a0, -32
+        (0x257d, 0x01f5_051b), // c.addiw a0, 31
+        (0x2581, 0x0005_859b), // c.addiw a1, 0
+        (0x5565, 0xff90_0513), // c.li a0, -7
+        (0x7101, 0xe001_0113), // c.addi16sp sp, -512
+        (0x617d, 0x1f01_0113), // c.addi16sp sp, 496
+        (0x7501, 0xfffe_0537), // c.lui a0, 1048544
+        (0x62fd, 0x0001_f2b7), // c.lui t0, 31
+        (0x917d, 0x03f5_5513), // c.srli a0, 63
+        (0x8785, 0x4017_d793), // c.srai a5, 1
+        (0x9979, 0xffe5_7513), // c.andi a0, -2
+        (0x8d0d, 0x40b5_0533), // c.sub a0, a1
+        (0x8d2d, 0x00b5_4533), // c.xor a0, a1
+        (0x8d4d, 0x00b5_6533), // c.or a0, a1
+        (0x8d6d, 0x00b5_7533), // c.and a0, a1
+        (0x9d0d, 0x40b5_053b), // c.subw a0, a1
+        (0x9d2d, 0x00b5_053b), // c.addw a0, a1
+        (0xb001, 0x801f_f06f), // c.j -2048
+        (0xaffd, 0x7fe0_006f), // c.j 2046
+        (0xd101, 0xf005_00e3), // c.beqz a0, -256
+        (0xedfd, 0x0e05_9f63), // c.bnez a1, 254
+        (0x157e, 0x03f5_1513), // c.slli a0, 63
+        (0x357e, 0x1f81_3507), // c.fldsp fa0, 504(sp)
+        (0x557e, 0x0fc1_2503), // c.lwsp a0, 252(sp)
+        (0x70fe, 0x1f81_3083), // c.ldsp ra, 504(sp)
+        (0x8502, 0x0005_0067), // c.jr a0
+        (0x852e, 0x00b0_0533), // c.mv a0, a1
+        (0x9002, 0x0010_0073), // c.ebreak
+        (0x9282, 0x0002_80e7), // c.jalr t0
+        (0x952e, 0x00b5_0533), // c.add a0, a1
+        (0xbfaa, 0x1ea1_3c27), // c.fsdsp fa0, 504(sp)
+        (0xdfaa, 0x0ea1_2e23), // c.swsp a0, 252(sp)
+        (0xff86, 0x1e11_3c23), // c.sdsp ra, 504(sp)
+    ];
+
+    fn store_funct(word: u32) -> SFunct {
+        match Instruction::try_from(word) {
+            Ok(Instruction::S { funct, .. }) => funct,
+            _ => panic!("0x{word:08x} isn't a store"),
+        }
+    }
+
+    #[test]
+    fn stores_decode_by_width() {
+        // sb, sh, sw and sd a1, 8(a0)
+        assert!(matches!(store_funct(0x00b5_0423), SFunct::Sb));
+        assert!(matches!(store_funct(0x00b5_1423), SFunct::Sh));
+        assert!(matches!(store_funct(0x00b5_2423), SFunct::Sw));
+        assert!(matches!(store_funct(0x00b5_3423), SFunct::Sd));
+    }
+
+    /// Keeps only the bits of I-type immediates that get used. Compressed
+    /// instructions sign-extend theirs past 12 bits, and full shifts keep the
+    /// top bits of `funct7` in theirs.
+    fn used_imm_bits(instruction: Instruction) -> Instruction {
+        match instruction {
+            Instruction::I {
+                funct,
+                rd,
+                rs1,
+                imm,
+            } => {
+                let shift =
+                    matches!(funct, IFunct::Slli | IFunct::Srli | IFunct::Srai);
+                Instruction::I {
+                    funct,
+                    rd,
+                    rs1,
+                    imm: imm & if shift { 0x3f } else { 0xfff },
+                }
+            }
+            instruction => instruction,
+        }
+    }
+
+    #[test]
+    fn compressed_instructions_decode_like_their_expansions() {
+        for (compressed, expanded) in EXPANSIONS {
+            let Ok(decoded) = Instruction::try_from(compressed) else {
+                panic!("failed to decode 0x{compressed:04x}");
+            };
+            let expected = Instruction::try_from(expanded).unwrap();
+            assert_eq!(
+                format!("{:?}", used_imm_bits(decoded)),
+                format!("{:?}", used_imm_bits(expected)),
+                "0x{compressed:04x}"
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_compressed_instructions_are_rejected() {
+        for word in [
+            0x0000_u16, // All zeros
+            0x0010,     // c.addi4spn with a zero immediate
+            0x6101,     // c.addi16sp with a zero immediate
+            0x6501,     // c.lui with a zero immediate
+            0x4002,     // c.lwsp into x0
+            0x6002,     // c.ldsp into x0
+            0x8002,     // c.jr to x0
+        ] {
+            assert!(
+                matches!(Instruction::try_from(word), Err(Err(_))),
+                "0x{word:04x} was decoded"
+            );
+        }
+    }
+}
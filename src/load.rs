@@ -1,16 +1,77 @@
 mod elf;
 
-use std::{fs, path::Path};
+use crate::memory::Memory;
+use std::{collections::HashMap, fs, path::Path};
+
+/// Address at which flat binaries get loaded, matching the start of RAM on
+/// most bare-metal RISC-V platforms.
+const FLAT_BINARY_BASE: u64 = 0x8000_0000;
+
+/// A program that has been loaded into guest memory.
+pub struct Program {
+    pub memory: Memory,
+    pub entry: u64,
+    /// The first address past the highest loaded segment.
+    pub end: u64,
+    /// Address of the program headers in guest memory, or zero if they
+    /// weren't loaded.
+    pub phdr: u64,
+    /// Size of each program header.
+    pub phent: u64,
+    /// Number of program headers.
+    pub phnum: u64,
+    /// Address of the HTIF `tohost` location, if the program has one.
+    pub tohost: Option<u64>,
+    /// Addresses of the symbols the program defines, by name. Flat binaries
+    /// have none.
+    pub symbols: HashMap<String, u64>,
+}
+
+/// A section of a file that holds instructions.
+pub struct CodeSection {
+    pub name: String,
+    /// Where the section gets loaded.
+    pub address: u64,
+    pub bytes: Vec<u8>,
+}
 
 pub fn load_program(
     path: &Path,
-) -> Result<(Vec<u8>, *const u16), Box<dyn std::error::Error>> {
+) -> Result<Program, Box<dyn std::error::Error>> {
     let raw_program = fs::read(path)?;
     if raw_program.starts_with(b"\x7fELF") {
         Ok(elf::load_elf_file(&raw_program))
     } else {
         // Not an ELF; treat it as a flat binary
-        let pc = raw_program.as_ptr().cast();
-        Ok((raw_program, pc))
+        let mut memory = Memory::default();
+        memory.map(FLAT_BINARY_BASE, raw_program.len());
+        memory.write_bytes(FLAT_BINARY_BASE, &raw_program)?;
+        Ok(Program {
+            memory,
+            entry: FLAT_BINARY_BASE,
+            end: FLAT_BINARY_BASE + raw_program.len() as u64,
+            phdr: 0,
+            phent: 0,
+            phnum: 0,
+            tohost: None,
+            symbols: HashMap::new(),
+        })
+    }
+}
+
+/// Reads the executable sections of an ELF file, or the whole file if it's a
+/// flat binary.
+pub fn load_code_sections(
+    path: &Path,
+) -> Result<Vec<CodeSection>, Box<dyn std::error::Error>> {
+    let raw_file = fs::read(path)?;
+    if raw_file.starts_with(b"\x7fELF") {
+        Ok(elf::code_sections(&raw_file))
+    } else {
+        Ok(vec![CodeSection {
+            name: "flat binary".to_owned(),
+            address: FLAT_BINARY_BASE,
+            bytes: raw_file,
+        }])
     }
 }
@@ -1,46 +1,94 @@
-use elf::types::PT_LOAD;
-use std::io::Cursor;
+use super::{CodeSection, Program};
+use crate::memory::Memory;
+use elf::types::{PT_LOAD, PT_PHDR, SHF_EXECINSTR, SHT_PROGBITS, SHT_SYMTAB};
+use std::{collections::HashMap, io::Cursor};
 
-pub fn load_elf_file(raw_file: &[u8]) -> (Vec<u8>, *const u16) {
+pub fn load_elf_file(raw_file: &[u8]) -> Program {
     let file = elf::File::open_stream(&mut Cursor::new(raw_file)).unwrap();
 
-    // Save some memory by moving all of the segment starting addresses so that
-    // the lowest one ends up at zero
-    let address_offset = file
+    let mut memory = Memory::default();
+    let mut end = 0;
+    for segment in &file.phdrs {
+        if segment.progtype == PT_LOAD {
+            memory.map(segment.vaddr, segment.memsz as usize);
+            memory
+                .write_bytes(
+                    segment.vaddr,
+                    &raw_file[segment.offset as usize..]
+                        [..segment.filesz as usize],
+                )
+                .unwrap();
+            end = end.max(segment.vaddr + segment.memsz);
+        }
+    }
+
+    // The ELF crate doesn't expose these header fields
+    let phoff = u64::from_le_bytes(raw_file[0x20..0x28].try_into().unwrap());
+    let phent = u16::from_le_bytes(raw_file[0x36..0x38].try_into().unwrap());
+    let phnum = u16::from_le_bytes(raw_file[0x38..0x3a].try_into().unwrap());
+
+    // Find where the program headers ended up in memory, either through an
+    // explicit `PT_PHDR` or by looking for the segment that contains them
+    let phdr = file
         .phdrs
         .iter()
+        .find(|segment| segment.progtype == PT_PHDR)
         .map(|segment| segment.vaddr)
-        .min()
+        .or_else(|| {
+            file.phdrs
+                .iter()
+                .find(|segment| {
+                    segment.progtype == PT_LOAD
+                        && (segment.offset..segment.offset + segment.filesz)
+                            .contains(&phoff)
+                })
+                .map(|segment| segment.vaddr + (phoff - segment.offset))
+        })
         .unwrap_or_default();
 
-    let total_size = file
-        .phdrs
-        .iter()
-        .map(|segment| segment.vaddr - address_offset + segment.memsz)
-        .max()
-        .unwrap_or_default() as usize;
-    let mut bytes = vec![0u8; total_size];
-
-    for segment in &file.phdrs {
-        match segment.progtype {
-            PT_LOAD => {
-                bytes[(segment.vaddr - address_offset) as usize..]
-                    [..segment.filesz as usize]
-                    .copy_from_slice(
-                        &raw_file[segment.offset as usize..]
-                            [..segment.filesz as usize],
-                    );
-            }
-            _ => {}
-        }
+    Program {
+        memory,
+        entry: file.ehdr.entry,
+        end,
+        phdr,
+        phent: phent.into(),
+        phnum: phnum.into(),
+        tohost: file.get_section(".tohost").map(|section| section.shdr.addr),
+        symbols: symbols(&file),
     }
+}
 
-    // Convert the entry address requested in the ELF to where it actually gets
-    // allocated
-    let entry = bytes
-        .as_ptr()
-        .wrapping_add((file.ehdr.entry - address_offset) as usize)
-        .cast();
+/// The addresses of the symbols defined in the file's symbol table, which
+/// stripped files don't have.
+fn symbols(file: &elf::File) -> HashMap<String, u64> {
+    let Some(symtab) = file
+        .sections
+        .iter()
+        .find(|section| section.shdr.shtype == SHT_SYMTAB)
+    else {
+        return HashMap::new();
+    };
+    file.get_symbols(symtab)
+        .unwrap()
+        .into_iter()
+        // Undefined symbols are in section zero
+        .filter(|symbol| symbol.shndx != 0 && !symbol.name.is_empty())
+        .map(|symbol| (symbol.name, symbol.value))
+        .collect()
+}
 
-    (bytes, entry)
+pub fn code_sections(raw_file: &[u8]) -> Vec<CodeSection> {
+    let file = elf::File::open_stream(&mut Cursor::new(raw_file)).unwrap();
+    file.sections
+        .into_iter()
+        .filter(|section| {
+            section.shdr.shtype == SHT_PROGBITS
+                && section.shdr.flags.0 & SHF_EXECINSTR.0 != 0
+        })
+        .map(|section| CodeSection {
+            name: section.shdr.name,
+            address: section.shdr.addr,
+            bytes: section.data,
+        })
+        .collect()
 }
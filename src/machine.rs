@@ -0,0 +1,327 @@
+//! The harts that make up the emulated system, along with the memory and
+//! devices that they share.
+//!
+//! Harts take turns running instructions, in order of their hart IDs, which
+//! keeps runs deterministic no matter how many harts there are. Each turn is
+//! a single instruction when stepping, printing or tracing, and a chain of
+//! translated blocks otherwise.
+//! Harts that are stalled in `wfi` or stopped through the SBI are skipped,
+//! and once none are left running, the host sleeps until an interrupt might
+//! arrive.
+
+#[cfg(feature = "jit")]
+use crate::cpu::jit;
+use crate::{
+    cpu::{
+        block::{Block, BlockCache},
+        sbi, Cpu, ExitReason,
+    },
+    decode_cache::{DecodeCache, Decoded},
+    error::{Error, Result},
+    load::Program,
+    memory::Memory,
+    stack,
+    syscall::Process,
+    trace::Trace,
+    virt::{Boot, Virt},
+    Opts,
+};
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    rc::Rc,
+    time::Duration,
+};
+
+/// How many rounds of instructions to run between polling devices for
+/// interrupts.
+const DEVICE_POLL_INTERVAL: u64 = 1024;
+/// The longest that the host sleeps while every hart is idle, so that input
+/// is still noticed.
+const MAX_IDLE_SLEEP: Duration = Duration::from_millis(10);
+
+/// The state that every hart has access to.
+pub struct Shared {
+    pub memory: Memory,
+    /// The Linux process being emulated, if not running on a whole machine.
+    pub process: Option<Process>,
+    /// Devices of the machine, if running on one.
+    pub virt: Option<Virt>,
+    pub tohost: Option<u64>,
+    /// Physical address reserved by each hart's last `lr`, if any.
+    reservations: Vec<Option<u64>>,
+    /// What the built-in SBI implementation keeps track of for each hart.
+    pub sbi_harts: Vec<sbi::HartState>,
+    /// Where retired instructions are logged, if anywhere.
+    pub trace: Option<Trace>,
+    /// Instructions decoded by any hart, which all harts get to use.
+    decode_cache: DecodeCache,
+    /// Blocks translated by any hart, which are shared the same way.
+    block_cache: BlockCache,
+    /// Compiles blocks to native code, if asked to.
+    #[cfg(feature = "jit")]
+    pub jit: Option<jit::Compiler>,
+}
+
+impl Shared {
+    pub fn new(
+        memory: Memory,
+        process: Option<Process>,
+        virt: Option<Virt>,
+        tohost: Option<u64>,
+        harts: usize,
+        trace: Option<Trace>,
+    ) -> Self {
+        Self {
+            memory,
+            process,
+            virt,
+            tohost,
+            reservations: vec![None; harts],
+            sbi_harts: vec![sbi::HartState::default(); harts],
+            trace,
+            decode_cache: DecodeCache::default(),
+            block_cache: BlockCache::default(),
+            #[cfg(feature = "jit")]
+            jit: None,
+        }
+    }
+
+    /// Looks up an already decoded instruction at a physical address.
+    pub fn cached_instruction(&mut self, physical: u64) -> Option<Decoded> {
+        self.forget_written_code();
+        self.decode_cache.get(physical)
+    }
+
+    /// Remembers an instruction that was decoded from a physical address,
+    /// until its page is written to.
+    pub fn cache_instruction(&mut self, physical: u64, decoded: Decoded) {
+        self.memory.watch_code_page(physical);
+        self.decode_cache.insert(physical, decoded);
+    }
+
+    /// Looks up an already translated block that starts at a physical
+    /// address.
+    pub fn cached_block(&mut self, physical: u64) -> Option<Rc<Block>> {
+        self.forget_written_code();
+        self.block_cache.get(physical)
+    }
+
+    /// Remembers a block that was translated from a physical address, until
+    /// its page is written to.
+    pub fn cache_block(&mut self, physical: u64, block: Rc<Block>) {
+        self.memory.watch_code_page(physical);
+        self.block_cache.insert(physical, block);
+    }
+
+    /// Drops what was decoded from pages that have been written to since.
+    fn forget_written_code(&mut self) {
+        for page in self.memory.take_written_code_pages() {
+            self.decode_cache.invalidate_page(page);
+            self.block_cache.invalidate_page(page);
+        }
+    }
+
+    /// Forgets every decoded instruction and translated block, as `fence.i`
+    /// requires.
+    pub fn flush_code_caches(&mut self) {
+        self.decode_cache.clear();
+        self.block_cache.clear();
+    }
+
+    pub fn reserve(&mut self, hart: usize, address: u64) {
+        self.reservations[hart] = Some(address);
+    }
+
+    /// Gives up a hart's reservation, returning the reserved address.
+    pub fn take_reservation(&mut self, hart: usize) -> Option<u64> {
+        self.reservations[hart].take()
+    }
+
+    /// Breaks the reservations of every hart on the doubleword that a store
+    /// writes to.
+    pub fn invalidate_reservations(&mut self, address: u64) {
+        for reservation in &mut self.reservations {
+            if reservation
+                .is_some_and(|reserved| reserved & !0b111 == address & !0b111)
+            {
+                *reservation = None;
+            }
+        }
+    }
+}
+
+pub struct Machine {
+    opts: Rc<Opts>,
+    harts: Vec<Cpu>,
+    shared: Rc<RefCell<Shared>>,
+    /// Number of rounds run so far.
+    rounds: u64,
+}
+
+impl Machine {
+    /// Sets up a Linux process, which runs on a single hart.
+    pub fn new(opts: Opts, mut program: Program) -> Result<Self> {
+        let args = std::iter::once(opts.file.display().to_string())
+            .chain(opts.args.iter().cloned())
+            .collect::<Vec<_>>();
+        let env = guest_environment(&opts.env);
+        let sp =
+            stack::setup_stack(&mut program, &args, &env, opts.stack_size)?;
+        let trace = create_trace(&opts)?;
+        let shared = Rc::new(RefCell::new(Shared::new(
+            program.memory,
+            Some(Process::new(program.end)),
+            None,
+            program.tohost,
+            1,
+            trace,
+        )));
+        let opts = Rc::new(opts);
+        let hart = Cpu::with_process(
+            Rc::clone(&opts),
+            Rc::clone(&shared),
+            program.entry,
+            sp,
+        );
+        Ok(Self {
+            opts,
+            harts: vec![hart],
+            shared,
+            rounds: 0,
+        })
+    }
+
+    /// Creates the harts of a machine, ready to jump into its firmware.
+    pub fn with_virt(opts: Opts, boot: Boot) -> Result<Self> {
+        let trace = create_trace(&opts)?;
+        let opts = Rc::new(opts);
+        let epoch = boot.virt.clint.epoch();
+        let shared = Rc::new(RefCell::new(Shared::new(
+            boot.memory,
+            None,
+            Some(boot.virt),
+            boot.tohost,
+            opts.harts,
+            trace,
+        )));
+        let harts = (0..opts.harts)
+            .map(|hart_id| {
+                Cpu::with_machine(
+                    Rc::clone(&opts),
+                    Rc::clone(&shared),
+                    hart_id,
+                    epoch,
+                    boot.entry,
+                    boot.dtb,
+                )
+            })
+            .collect();
+        Ok(Self {
+            opts,
+            harts,
+            shared,
+            rounds: 0,
+        })
+    }
+
+    pub fn run(&mut self) -> Result<ExitReason> {
+        // Every instruction has to be seen when printing or tracing them
+        let advance = if self.opts.verbose || self.opts.trace.is_some() {
+            Cpu::step
+        } else {
+            Cpu::run_blocks
+        };
+        #[cfg(feature = "jit")]
+        if self.opts.jit {
+            self.shared.borrow_mut().jit = Some(jit::Compiler::new()?);
+        }
+        loop {
+            if let Some(exit_reason) = self.round(advance)? {
+                return Ok(exit_reason);
+            }
+        }
+    }
+
+    /// Lets every hart run a single instruction, returning the reason for
+    /// exiting if the guest wants to stop.
+    pub fn step(&mut self) -> Result<Option<ExitReason>> {
+        self.round(Cpu::step)
+    }
+
+    /// Lets every hart take a turn at running instructions with `advance`.
+    fn round(
+        &mut self,
+        advance: fn(&mut Cpu) -> Result<Option<ExitReason>>,
+    ) -> Result<Option<ExitReason>> {
+        if self.rounds.is_multiple_of(DEVICE_POLL_INTERVAL) {
+            for hart in &mut self.harts {
+                hart.update_interrupts();
+            }
+        }
+        self.rounds = self.rounds.wrapping_add(1);
+        let mut any_running = false;
+        for hart in &mut self.harts {
+            if let Some(exit_reason) = advance(hart)? {
+                return Ok(Some(exit_reason));
+            }
+            any_running |= hart.is_running();
+        }
+        if !any_running {
+            self.wait_for_interrupt();
+        }
+        Ok(None)
+    }
+
+    /// Sleeps until an interrupt might have become pending, since no hart
+    /// has anything else to do.
+    fn wait_for_interrupt(&mut self) {
+        let timeout = match &self.shared.borrow().virt {
+            Some(virt) => (0..self.harts.len())
+                .map(|hart| virt.clint.time_until_interrupt(hart))
+                .min()
+                .unwrap_or(MAX_IDLE_SLEEP)
+                .min(MAX_IDLE_SLEEP),
+            None => return,
+        };
+        std::thread::sleep(timeout);
+        for hart in &mut self.harts {
+            hart.update_interrupts();
+        }
+    }
+
+    /// The hart that a debugger gets to see, which is hart 0.
+    pub fn debugged_hart(&mut self) -> &mut Cpu {
+        &mut self.harts[0]
+    }
+
+    pub fn memory(&self) -> Ref<'_, Memory> {
+        Ref::map(self.shared.borrow(), |shared| &shared.memory)
+    }
+
+    pub fn memory_mut(&self) -> RefMut<'_, Memory> {
+        RefMut::map(self.shared.borrow_mut(), |shared| &mut shared.memory)
+    }
+}
+
+fn create_trace(opts: &Opts) -> Result<Option<Trace>> {
+    opts.trace
+        .as_deref()
+        .map(Trace::create)
+        .transpose()
+        .map_err(Error::Trace)
+}
+
+/// Builds the guest's environment from that of the host, with the given
+/// `KEY=VALUE` pairs added on top.
+fn guest_environment(overrides: &[String]) -> Vec<String> {
+    let mut env = std::env::vars()
+        .filter(|(key, _)| {
+            !overrides.iter().any(|var| {
+                var.split_once('=').map_or(var.as_str(), |(k, _)| k) == key
+            })
+        })
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>();
+    env.extend(overrides.iter().cloned());
+    env
+}
@@ -3,41 +3,252 @@
 
 mod bits;
 mod cpu;
+mod csr;
+mod decode_cache;
+mod device;
+mod disasm;
+mod dtb;
 mod error;
+mod float;
+mod gdb;
 mod instruction;
 mod load;
+mod machine;
+mod memory;
+mod mmu;
 mod register;
+mod signature;
+mod stack;
+mod syscall;
+mod trace;
+mod trap;
+mod virt;
 
-use cpu::Cpu;
-use gumdrop::Options;
-use std::path::PathBuf;
+use cpu::ExitReason;
+use gumdrop::{Options, ParsingStyle};
+use machine::Machine;
+use signature::Signature;
+use std::{fs, path::PathBuf, str::FromStr};
 
 #[derive(Options)]
 pub struct Opts {
     /// Display this message
     help: bool,
 
-    /// ELF or flat binary to execute
+    /// ELF or flat binary to execute, or firmware when emulating a machine
     #[options(free, required)]
     file: PathBuf,
 
+    /// Arguments to pass to the program
+    #[options(free)]
+    args: Vec<String>,
+
+    /// Add a variable to the program's environment
+    #[options(short = "E", meta = "KEY=VALUE")]
+    env: Vec<String>,
+
+    /// Size of the program's stack in bytes
+    #[options(no_short, default = "8388608", meta = "BYTES")]
+    stack_size: usize,
+
     /// Print extra debug information
     verbose: bool,
+
+    /// Compile hot code to native code with Cranelift (needs the `jit` feature)
+    #[options(no_short)]
+    jit: bool,
+
+    /// Log every retired instruction to a file in Spike's commit log format
+    #[options(no_short, meta = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Write the memory between the `begin_signature` and `end_signature`
+    /// symbols to a file when the program exits
+    #[options(no_short, meta = "FILE")]
+    signature: Option<PathBuf>,
+
+    /// Number of bytes on each line of the signature
+    #[options(no_short, default = "4", meta = "BYTES")]
+    signature_granularity: usize,
+
+    /// Wait for GDB to attach on the given port before running
+    #[options(no_short, meta = "PORT")]
+    gdb: Option<u16>,
+
+    /// Emulate a whole machine instead of a single Linux process
+    #[options(no_short, meta = "NAME")]
+    machine: Option<MachineType>,
+
+    /// Number of harts in the machine
+    #[options(no_short, default = "1", meta = "N")]
+    harts: usize,
+
+    /// Size of the machine's RAM in bytes
+    #[options(no_short, default = "134217728", meta = "BYTES")]
+    memory_size: usize,
+
+    /// Kernel image to load into the machine's RAM
+    #[options(no_short, meta = "FILE")]
+    kernel: Option<PathBuf>,
+
+    /// Initial ramdisk to load into the machine's RAM
+    #[options(no_short, meta = "FILE")]
+    initrd: Option<PathBuf>,
+
+    /// Device tree blob to use instead of the generated one
+    #[options(no_short, meta = "FILE")]
+    dtb: Option<PathBuf>,
+
+    /// Write the machine's generated device tree blob to a file and exit
+    #[options(no_short, meta = "FILE")]
+    dump_dtb: Option<PathBuf>,
+
+    /// Provide the SBI to the program, which then runs in S-mode
+    #[options(no_short, meta = "NAME")]
+    sbi: Option<SbiImplementation>,
+}
+
+/// Print the instructions in a file instead of running it.
+#[derive(Options)]
+struct DisasmOpts {
+    /// Display this message
+    help: bool,
+
+    /// ELF or flat binary to disassemble
+    #[options(free, required)]
+    file: PathBuf,
+}
+
+/// The kinds of machines that can be emulated.
+#[derive(Clone, Copy)]
+pub enum MachineType {
+    Virt,
+}
+
+impl FromStr for MachineType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "virt" => Ok(Self::Virt),
+            _ => Err(format!("unknown machine `{s}`")),
+        }
+    }
+}
+
+/// Where S-mode software gets its SBI from, when not from firmware.
+#[derive(Clone, Copy)]
+pub enum SbiImplementation {
+    /// SBI calls are handled by the emulator itself.
+    Builtin,
+}
+
+impl FromStr for SbiImplementation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "builtin" => Ok(Self::Builtin),
+            _ => Err(format!("unknown SBI implementation `{s}`")),
+        }
+    }
 }
 
 fn main() {
-    if let Err(err) = (|| {
-        let opts = Opts::parse_args_default_or_exit();
-        let (program_bytes, pc) = load::load_program(&opts.file)?;
-        let mut cpu = Cpu::new(opts, pc);
-        unsafe {
-            cpu.run()?;
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).is_some_and(|arg| arg == "disasm") {
+        disasm_command(&args);
+    }
+    match (|| {
+        // Everything after the program name belongs to the program itself
+        let mut opts = Opts::parse_args_or_exit(ParsingStyle::StopAtFirstFree);
+        // SBI calls come from kernels, which need a machine to run on
+        if opts.sbi.is_some() && opts.machine.is_none() {
+            opts.machine = Some(MachineType::Virt);
+        }
+        if opts.jit && !cfg!(feature = "jit") {
+            return Err(
+                "--jit requires rv to be built with the `jit` feature".into()
+            );
+        }
+        if opts.harts != 1 && opts.machine.is_none() {
+            return Err("--harts requires --machine".into());
+        }
+        if !(1..=virt::MAX_HARTS).contains(&opts.harts) {
+            return Err(format!(
+                "--harts must be between 1 and {}",
+                virt::MAX_HARTS
+            )
+            .into());
+        }
+        if let Some(path) = &opts.dump_dtb {
+            let Some(MachineType::Virt) = opts.machine else {
+                return Err("--dump-dtb requires --machine".into());
+            };
+            fs::write(path, virt::device_tree(&opts)?)?;
+            return Ok(ExitReason::Exit(0));
+        }
+        let verbose = opts.verbose;
+        let gdb_port = opts.gdb;
+        let program = load::load_program(&opts.file)?;
+        let signature = match &opts.signature {
+            Some(path) => Some(Signature::locate(
+                path,
+                &program.symbols,
+                opts.signature_granularity,
+            )?),
+            None => None,
+        };
+        let mut machine = match opts.machine {
+            None => Machine::new(opts, program)?,
+            Some(MachineType::Virt) => {
+                let boot = virt::boot(&opts, program)?;
+                Machine::with_virt(opts, boot)?
+            }
+        };
+        let exit_reason = match gdb_port {
+            Some(port) => gdb::serve(&mut machine, port)?,
+            None => machine.run()?,
+        };
+        if let Some(signature) = signature {
+            signature.write(&machine.memory())?;
+        }
+        if verbose {
+            eprintln!("Exiting: {exit_reason:?}");
         }
-        // Keep the program allocated while the CPU is running
-        drop(program_bytes);
 
-        Ok::<(), Box<dyn std::error::Error>>(())
+        Ok::<_, Box<dyn std::error::Error>>(exit_reason)
     })() {
-        eprintln!("{err}");
+        Ok(exit_reason) => std::process::exit(exit_reason.exit_code()),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs `rv disasm`. This is told apart from the program to run by hand,
+/// since gumdrop doesn't allow subcommands alongside free arguments.
+fn disasm_command(args: &[String]) -> ! {
+    let opts = DisasmOpts::parse_args(&args[2..], ParsingStyle::default())
+        .unwrap_or_else(|err| {
+            eprintln!("{}: {err}", args[0]);
+            std::process::exit(2);
+        });
+    if opts.help {
+        println!("Usage: {} disasm FILE", args[0]);
+        println!();
+        println!("{}", DisasmOpts::usage());
+        std::process::exit(0);
+    }
+    match load::load_code_sections(&opts.file) {
+        Ok(sections) => {
+            disasm::disassemble(&sections);
+            std::process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
     }
 }
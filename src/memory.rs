@@ -0,0 +1,243 @@
+use crate::error::{Error, Result};
+
+/// Guest physical memory.
+///
+/// Memory is made up of a set of non-overlapping regions, each backed by its
+/// own buffer. Every access is bounds-checked against these regions, so a bad
+/// guest address results in an [`Error::AccessFault`] rather than touching
+/// host memory.
+///
+/// Pages that instructions have been decoded from are watched, so that the
+/// decoded instructions can be thrown away once those pages change.
+#[derive(Default)]
+pub struct Memory {
+    regions: Vec<Region>,
+    /// Watched pages that have been written to or unmapped since they were
+    /// last taken.
+    written_code_pages: Vec<u64>,
+}
+
+struct Region {
+    base: u64,
+    bytes: Vec<u8>,
+    /// Whether each page that the region overlaps is being watched, starting
+    /// with the page that contains `base`.
+    code_pages: Vec<bool>,
+}
+
+impl Region {
+    fn end(&self) -> u64 {
+        self.base + self.bytes.len() as u64
+    }
+
+    /// Index into `code_pages` of the page that contains `address`.
+    const fn page_index(&self, address: u64) -> usize {
+        ((address >> PAGE_SHIFT) - (self.base >> PAGE_SHIFT)) as usize
+    }
+
+    /// Addresses of the pages that are being watched.
+    fn watched_pages(&self) -> impl Iterator<Item = u64> + '_ {
+        ((self.base >> PAGE_SHIFT)..)
+            .zip(&self.code_pages)
+            .filter(|&(_, &watched)| watched)
+            .map(|(page, _)| page << PAGE_SHIFT)
+    }
+
+    /// Splits the region in two at `at`, returning the upper part. A page
+    /// that straddles `at` stays watched in both parts.
+    fn split_off(&mut self, at: u64) -> Self {
+        let upper = Self {
+            base: at,
+            bytes: self.bytes.split_off((at - self.base) as usize),
+            code_pages: self.code_pages[self.page_index(at)..].to_vec(),
+        };
+        self.code_pages.truncate(page_count(self.base, at));
+        upper
+    }
+}
+
+const PAGE_SHIFT: u32 = 12;
+
+/// Number of pages that the given range overlaps.
+const fn page_count(base: u64, end: u64) -> usize {
+    (end.div_ceil(1 << PAGE_SHIFT) - (base >> PAGE_SHIFT)) as usize
+}
+
+impl Memory {
+    /// Maps a zero-filled region of `size` bytes starting at `base`.
+    ///
+    /// Regions that end where the new one starts, or start where it ends,
+    /// are merged with it, so that accesses can span what was mapped
+    /// separately. Growing upwards only extends the buffer of the region
+    /// below, while growing downwards copies the region above.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new region would overlap an existing one.
+    pub fn map(&mut self, base: u64, size: usize) {
+        assert!(
+            self.is_unmapped(base, size),
+            "memory region at 0x{base:016x} overlaps an existing region"
+        );
+        // Regions are kept sorted by their base address so that they can be
+        // binary searched
+        let index = self.regions.partition_point(|region| region.base < base);
+        self.regions.insert(
+            index,
+            Region {
+                base,
+                bytes: vec![0; size],
+                code_pages: vec![false; page_count(base, base + size as u64)],
+            },
+        );
+        self.merge_with_next(index);
+        if let Some(previous) = index.checked_sub(1) {
+            self.merge_with_next(previous);
+        }
+    }
+
+    /// Merges the region after the one at `index` into it, if the two are
+    /// adjacent.
+    fn merge_with_next(&mut self, index: usize) {
+        match self.regions.get(index..=index + 1) {
+            Some([region, next]) if region.end() == next.base => {}
+            _ => return,
+        }
+        let next = self.regions.remove(index + 1);
+        let region = &mut self.regions[index];
+        let first_page = region.page_index(next.base);
+        region.bytes.extend_from_slice(&next.bytes);
+        for (index, &watched) in (first_page..).zip(&next.code_pages) {
+            match region.code_pages.get_mut(index) {
+                Some(merged) => *merged |= watched,
+                None => region.code_pages.push(watched),
+            }
+        }
+    }
+
+    /// Unmaps the given range, splitting the regions that only partly
+    /// overlap it.
+    pub fn unmap(&mut self, base: u64, size: usize) {
+        let end = base.saturating_add(size as u64);
+        let first = self.regions.partition_point(|region| region.end() <= base);
+        let last = self.regions.partition_point(|region| region.base < end);
+        if first == last {
+            return;
+        }
+        let mut removed = self.regions.drain(first..last).collect::<Vec<_>>();
+        let mut kept = Vec::new();
+        let head = &mut removed[0];
+        if head.base < base {
+            let unmapped = head.split_off(base);
+            kept.push(std::mem::replace(head, unmapped));
+        }
+        let tail = removed.last_mut().unwrap();
+        if end < tail.end() {
+            kept.push(tail.split_off(end));
+        }
+        self.written_code_pages
+            .extend(removed.iter().flat_map(Region::watched_pages));
+        self.regions.splice(first..first, kept);
+    }
+
+    /// Checks whether no part of the given range is mapped.
+    pub fn is_unmapped(&self, base: u64, size: usize) -> bool {
+        let end = base.saturating_add(size as u64);
+        self.regions
+            .iter()
+            .all(|region| end <= region.base || region.end() <= base)
+    }
+
+    /// Iterates over the base address and contents of every region.
+    pub fn regions(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        self.regions
+            .iter()
+            .map(|region| (region.base, region.bytes.as_slice()))
+    }
+
+    /// Finds the region that fully contains `len` bytes at `address`, returning
+    /// its index along with the offset of `address` into it.
+    fn locate(&self, address: u64, len: usize) -> Result<(usize, usize)> {
+        self.regions
+            .partition_point(|region| region.base <= address)
+            .checked_sub(1)
+            .filter(|&i| {
+                address
+                    .checked_add(len as u64)
+                    .is_some_and(|end| end <= self.regions[i].end())
+            })
+            .map(|i| (i, (address - self.regions[i].base) as usize))
+            .ok_or(Error::AccessFault(address))
+    }
+
+    pub fn slice(&self, address: u64, len: usize) -> Result<&[u8]> {
+        let (i, offset) = self.locate(address, len)?;
+        Ok(&self.regions[i].bytes[offset..][..len])
+    }
+
+    pub fn slice_mut(&mut self, address: u64, len: usize) -> Result<&mut [u8]> {
+        let (i, offset) = self.locate(address, len)?;
+        let region = &mut self.regions[i];
+        if len != 0 {
+            let first = region.page_index(address);
+            let last = region.page_index(address + len as u64 - 1);
+            for (index, watched) in
+                region.code_pages[first..=last].iter_mut().enumerate()
+            {
+                if *watched {
+                    *watched = false;
+                    let page =
+                        (region.base >> PAGE_SHIFT) + (first + index) as u64;
+                    self.written_code_pages.push(page << PAGE_SHIFT);
+                }
+            }
+        }
+        Ok(&mut region.bytes[offset..][..len])
+    }
+
+    /// Starts watching the page that contains `address` for changes, since
+    /// instructions have been decoded from it. Does nothing if the address
+    /// isn't mapped.
+    pub fn watch_code_page(&mut self, address: u64) {
+        if let Ok((i, _)) = self.locate(address, 1) {
+            let region = &mut self.regions[i];
+            let index = region.page_index(address);
+            region.code_pages[index] = true;
+        }
+    }
+
+    /// Returns the addresses of the watched pages that have changed, which
+    /// are no longer watched.
+    pub fn take_written_code_pages(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.written_code_pages)
+    }
+
+    pub fn has_written_code_pages(&self) -> bool {
+        !self.written_code_pages.is_empty()
+    }
+
+    pub fn write_bytes(&mut self, address: u64, bytes: &[u8]) -> Result<()> {
+        self.slice_mut(address, bytes.len())?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn read<const N: usize>(&self, address: u64) -> Result<[u8; N]> {
+        Ok(self.slice(address, N)?.try_into().unwrap())
+    }
+
+    pub fn read_u8(&self, address: u64) -> Result<u8> {
+        self.read(address).map(u8::from_le_bytes)
+    }
+
+    pub fn read_u16(&self, address: u64) -> Result<u16> {
+        self.read(address).map(u16::from_le_bytes)
+    }
+
+    pub fn read_u64(&self, address: u64) -> Result<u64> {
+        self.read(address).map(u64::from_le_bytes)
+    }
+
+    pub fn write_u64(&mut self, address: u64, value: u64) -> Result<()> {
+        self.write_bytes(address, &value.to_le_bytes())
+    }
+}
@@ -0,0 +1,240 @@
+//! Virtual address translation through Sv39 and Sv48 page tables.
+//!
+//! Successful translations are cached in a direct-mapped TLB with one entry
+//! per 4 KiB page, so that superpages take up as many entries as the number of
+//! distinct pages touched within them. The TLB is only flushed by
+//! `sfence.vma` and writes to `satp`, so like on real hardware, changes to the
+//! page tables aren't noticed until the guest asks for it.
+
+use crate::{csr::Privilege, memory::Memory, trap::Exception};
+
+pub const SATP_MODE_BARE: u64 = 0;
+pub const SATP_MODE_SV39: u64 = 8;
+pub const SATP_MODE_SV48: u64 = 9;
+
+const PAGE_SHIFT: u32 = 12;
+const TLB_SIZE: usize = 256;
+
+// Fields of a page table entry
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+/// Bits used by the Svnapot and Svpbmt extensions, along with reserved ones.
+const PTE_RESERVED: u64 = 0x3ff << 54;
+const PTE_PPN_MASK: u64 = (1 << 44) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl Access {
+    const fn page_fault(self, address: u64) -> Exception {
+        match self {
+            Self::Fetch => Exception::InstructionPageFault(address),
+            Self::Load => Exception::LoadPageFault(address),
+            Self::Store => Exception::StorePageFault(address),
+        }
+    }
+
+    const fn access_fault(self, address: u64) -> Exception {
+        match self {
+            Self::Fetch => Exception::InstructionAccessFault(address),
+            Self::Load => Exception::LoadAccessFault(address),
+            Self::Store => Exception::StoreAccessFault(address),
+        }
+    }
+}
+
+/// The parts of the hart's state that affect translation.
+pub struct Context {
+    pub satp: u64,
+    /// Effective privilege level of the access, taking `mstatus.MPRV` into
+    /// account.
+    pub privilege: Privilege,
+    /// Whether S-mode may access user pages.
+    pub sum: bool,
+    /// Whether executable pages may be read from.
+    pub mxr: bool,
+}
+
+#[derive(Clone, Copy)]
+struct TlbEntry {
+    /// Virtual page number, or `u64::MAX` for an empty entry.
+    vpn: u64,
+    /// Physical page number of the 4 KiB page containing the address.
+    ppn: u64,
+    /// Permission bits of the leaf page table entry.
+    flags: u64,
+}
+
+impl TlbEntry {
+    const EMPTY: Self = Self {
+        vpn: u64::MAX,
+        ppn: 0,
+        flags: 0,
+    };
+}
+
+pub struct Mmu {
+    tlb: Box<[TlbEntry; TLB_SIZE]>,
+    /// Bumped whenever translations are flushed, so that anything derived
+    /// from earlier translations can tell that it may be stale.
+    generation: u64,
+}
+
+impl Mmu {
+    pub fn new() -> Self {
+        Self {
+            tlb: Box::new([TlbEntry::EMPTY; TLB_SIZE]),
+            generation: 0,
+        }
+    }
+
+    /// Translates a virtual address into a physical one.
+    pub fn translate(
+        &mut self,
+        memory: &mut Memory,
+        context: &Context,
+        address: u64,
+        access: Access,
+    ) -> Result<u64, Exception> {
+        let mode = context.satp >> 60;
+        if mode == SATP_MODE_BARE || context.privilege == Privilege::Machine {
+            return Ok(address);
+        }
+
+        let vpn = address >> PAGE_SHIFT;
+        let offset = address & ((1 << PAGE_SHIFT) - 1);
+        let entry = self.tlb[vpn as usize % TLB_SIZE];
+        // Setting the dirty bit requires going through the page table
+        if entry.vpn == vpn
+            && (access != Access::Store || entry.flags & PTE_D != 0)
+        {
+            return if is_permitted(entry.flags, context, access) {
+                Ok(entry.ppn << PAGE_SHIFT | offset)
+            } else {
+                Err(access.page_fault(address))
+            };
+        }
+
+        let (ppn, flags) = walk(memory, context, address, access)?;
+        self.tlb[vpn as usize % TLB_SIZE] = TlbEntry { vpn, ppn, flags };
+        Ok(ppn << PAGE_SHIFT | offset)
+    }
+
+    /// Forgets every cached translation.
+    pub fn flush(&mut self) {
+        self.tlb.fill(TlbEntry::EMPTY);
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Forgets the cached translation of a single virtual address.
+    pub fn flush_page(&mut self, address: u64) {
+        let vpn = address >> PAGE_SHIFT;
+        let entry = &mut self.tlb[vpn as usize % TLB_SIZE];
+        if entry.vpn == vpn {
+            *entry = TlbEntry::EMPTY;
+        }
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+/// Walks the page table, returning the physical page number of the 4 KiB page
+/// containing `address` along with the flags of the leaf entry.
+fn walk(
+    memory: &mut Memory,
+    context: &Context,
+    address: u64,
+    access: Access,
+) -> Result<(u64, u64), Exception> {
+    let levels = if context.satp >> 60 == SATP_MODE_SV48 {
+        4
+    } else {
+        3
+    };
+    // The unused upper bits must all be copies of the topmost used one
+    let va_bits = PAGE_SHIFT + 9 * levels;
+    let unused_bits = 64 - va_bits;
+    if ((address << unused_bits) as i64 >> unused_bits) as u64 != address {
+        return Err(access.page_fault(address));
+    }
+
+    let mut table = (context.satp & PTE_PPN_MASK) << PAGE_SHIFT;
+    for level in (0..levels).rev() {
+        let index = address >> (PAGE_SHIFT + 9 * level) & 0x1ff;
+        let pte_address = table + index * 8;
+        let pte = memory
+            .read_u64(pte_address)
+            .map_err(|_| access.access_fault(address))?;
+        if pte & PTE_V == 0
+            || pte & (PTE_R | PTE_W) == PTE_W
+            || pte & PTE_RESERVED != 0
+        {
+            return Err(access.page_fault(address));
+        }
+        let ppn = pte >> 10 & PTE_PPN_MASK;
+
+        if pte & (PTE_R | PTE_X) == 0 {
+            // A pointer to the next level of the page table
+            if level == 0 || pte & (PTE_A | PTE_D | PTE_U) != 0 {
+                return Err(access.page_fault(address));
+            }
+            table = ppn << PAGE_SHIFT;
+            continue;
+        }
+
+        if !is_permitted(pte, context, access) {
+            return Err(access.page_fault(address));
+        }
+        // Superpages must be aligned to their own size
+        let superpage_mask = (1 << (9 * level)) - 1;
+        if ppn & superpage_mask != 0 {
+            return Err(access.page_fault(address));
+        }
+
+        let mut new_pte = pte | PTE_A;
+        if access == Access::Store {
+            new_pte |= PTE_D;
+        }
+        if new_pte != pte {
+            memory
+                .write_u64(pte_address, new_pte)
+                .map_err(|_| access.access_fault(address))?;
+        }
+
+        let page = ppn | (address >> PAGE_SHIFT & superpage_mask);
+        return Ok((page, new_pte));
+    }
+    unreachable!("the last level always either returns or faults")
+}
+
+/// Checks the permission bits of a leaf page table entry.
+fn is_permitted(pte: u64, context: &Context, access: Access) -> bool {
+    let is_user_page = pte & PTE_U != 0;
+    let privilege_ok = match context.privilege {
+        Privilege::User => is_user_page,
+        // S-mode may never execute user pages, and only read or write them
+        // if `mstatus.SUM` is set
+        Privilege::Supervisor => {
+            !is_user_page || context.sum && access != Access::Fetch
+        }
+        Privilege::Machine => true,
+    };
+    privilege_ok
+        && match access {
+            Access::Fetch => pte & PTE_X != 0,
+            Access::Load => pte & PTE_R != 0 || context.mxr && pte & PTE_X != 0,
+            Access::Store => pte & PTE_W != 0,
+        }
+}
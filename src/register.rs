@@ -7,7 +7,21 @@ pub struct RegisterName(u8);
 
 impl RegisterName {
     pub const X0: Self = Self(0);
+    pub const X1: Self = Self(1);
     pub const X2: Self = Self(2);
+    pub const A0: Self = Self(10);
+    pub const A1: Self = Self(11);
+    pub const A2: Self = Self(12);
+    pub const A3: Self = Self(13);
+    pub const A4: Self = Self(14);
+    pub const A5: Self = Self(15);
+    pub const A6: Self = Self(16);
+    pub const A7: Self = Self(17);
+
+    /// Every register, in order from `x0` to `x31`.
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..32).map(Self)
+    }
 
     pub const fn rd(word: u32) -> Self {
         Self(u32_sms(word, 7, 5, 0) as u8)
@@ -56,3 +70,58 @@ impl From<RegisterName> for usize {
         reg.0.into()
     }
 }
+
+/// A floating point register. These share their encoding with the integer
+/// registers, but not their contents.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FRegisterName(u8);
+
+impl FRegisterName {
+    pub const fn rd(word: u32) -> Self {
+        Self(RegisterName::rd(word).0)
+    }
+
+    pub const fn rs1(word: u32) -> Self {
+        Self(RegisterName::rs1(word).0)
+    }
+
+    pub const fn rs2(word: u32) -> Self {
+        Self(RegisterName::rs2(word).0)
+    }
+
+    /// The third source register of fused multiply-add instructions.
+    pub const fn rs3(word: u32) -> Self {
+        Self(u32_sms(word, 27, 5, 0) as u8)
+    }
+
+    pub const fn compressed_rd(word: u16) -> Self {
+        Self(RegisterName::compressed_rd(word).0)
+    }
+
+    pub const fn compressed_rs2(word: u16) -> Self {
+        Self(RegisterName::compressed_rs2(word).0)
+    }
+
+    pub const fn compressed_common_rs2(word: u16) -> Self {
+        Self(RegisterName::compressed_common_rs2(word).0)
+    }
+}
+
+impl fmt::Debug for FRegisterName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            [
+                "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0",
+                "fs1", "fa0", "fa1", "fa2", "fa3", "fa4", "fa5", "fa6", "fa7",
+                "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9", "fs10",
+                "fs11", "ft8", "ft9", "ft10", "ft11",
+            ][usize::from(self.0)],
+        )
+    }
+}
+
+impl From<FRegisterName> for usize {
+    fn from(reg: FRegisterName) -> Self {
+        reg.0.into()
+    }
+}
@@ -0,0 +1,72 @@
+//! Signatures left in memory by the RISC-V architecture tests, which `riscof`
+//! compares against those of a reference model.
+//!
+//! A test's signature is the memory between its `begin_signature` and
+//! `end_signature` symbols. It's written out in hex with a fixed number of
+//! bytes per line, most significant byte first, the same way Spike and Sail
+//! write theirs.
+
+use crate::memory::Memory;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub struct Signature {
+    path: PathBuf,
+    begin: u64,
+    end: u64,
+    /// Number of bytes on each line.
+    granularity: usize,
+}
+
+impl Signature {
+    /// Finds the signature among a program's symbols, to be written to
+    /// `path` later.
+    pub fn locate(
+        path: &Path,
+        symbols: &HashMap<String, u64>,
+        granularity: usize,
+    ) -> Result<Self, String> {
+        if granularity == 0 {
+            return Err("--signature-granularity must be at least 1".into());
+        }
+        let symbol = |name| {
+            symbols.get(name).copied().ok_or_else(|| {
+                format!("--signature requires a `{name}` symbol")
+            })
+        };
+        let begin = symbol("begin_signature")?;
+        let end = symbol("end_signature")?;
+        if end < begin {
+            return Err("the signature ends before it begins".into());
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            begin,
+            end,
+            granularity,
+        })
+    }
+
+    pub fn write(&self, memory: &Memory) -> Result<(), Box<dyn Error>> {
+        let bytes =
+            memory.slice(self.begin, (self.end - self.begin) as usize)?;
+        let mut out = String::new();
+        for line in bytes.chunks(self.granularity) {
+            // A short last line is padded at its most significant end
+            for _ in line.len()..self.granularity {
+                out.push_str("00");
+            }
+            for byte in line.iter().rev() {
+                write!(out, "{byte:02x}").unwrap();
+            }
+            out.push('\n');
+        }
+        fs::write(&self.path, out)?;
+        Ok(())
+    }
+}
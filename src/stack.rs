@@ -0,0 +1,172 @@
+//! Construction of the initial process stack as laid out by the System V ABI.
+//!
+//! From the stack pointer upwards, the guest finds `argc`, the
+//! null-terminated `argv` and `envp` arrays, and the auxiliary vector. The
+//! strings and other data they point to live above that.
+
+use crate::{
+    csr::isa_bit,
+    error::{Error, Result},
+    load::Program,
+    memory::Memory,
+};
+
+/// The stack grows downwards from this address.
+const STACK_TOP: u64 = 0x3f_ffff_f000;
+const PAGE_SIZE: u64 = 4096;
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_ENTRY: u64 = 9;
+const AT_UID: u64 = 11;
+const AT_EUID: u64 = 12;
+const AT_GID: u64 = 13;
+const AT_EGID: u64 = 14;
+const AT_HWCAP: u64 = 16;
+const AT_CLKTCK: u64 = 17;
+const AT_SECURE: u64 = 23;
+const AT_RANDOM: u64 = 25;
+const AT_EXECFN: u64 = 31;
+
+/// One bit per supported single-letter ISA extension, with bit 0 being 'A'.
+const HWCAP: u64 = isa_bit(b'I')
+    | isa_bit(b'M')
+    | isa_bit(b'A')
+    | isa_bit(b'F')
+    | isa_bit(b'D')
+    | isa_bit(b'C');
+
+/// Maps a stack of `size` bytes and fills it in, returning the initial stack
+/// pointer.
+pub fn setup_stack(
+    program: &mut Program,
+    args: &[String],
+    env: &[String],
+    size: usize,
+) -> Result<u64> {
+    let Program {
+        memory,
+        entry,
+        phdr,
+        phent,
+        phnum,
+        ..
+    } = program;
+    // The stack can't wrap around or run into the program
+    let base = (size as u64)
+        .checked_next_multiple_of(PAGE_SIZE)
+        .and_then(|size| STACK_TOP.checked_sub(size))
+        .filter(|&base| memory.is_unmapped(base, (STACK_TOP - base) as usize))
+        .ok_or(Error::StackSize(size))?;
+    memory.map(base, (STACK_TOP - base) as usize);
+
+    let mut sp = STACK_TOP;
+    let mut push_bytes = |memory: &mut Memory, bytes: &[u8]| {
+        sp -= bytes.len() as u64;
+        memory.write_bytes(sp, bytes).map(|()| sp)
+    };
+
+    let mut push_string = |memory: &mut Memory, string: &str| {
+        push_bytes(memory, b"\0")?;
+        push_bytes(memory, string.as_bytes())
+    };
+    let arg_pointers = args
+        .iter()
+        .map(|arg| push_string(memory, arg))
+        .collect::<Result<Vec<_>>>()?;
+    let env_pointers = env
+        .iter()
+        .map(|var| push_string(memory, var))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut random_bytes = [0u8; 16];
+    // SAFETY: the buffer is valid for writes of its entire length
+    unsafe {
+        libc::getrandom(
+            random_bytes.as_mut_ptr().cast(),
+            random_bytes.len(),
+            0,
+        );
+    }
+    let random = push_bytes(memory, &random_bytes)?;
+
+    // SAFETY: these functions are always successful
+    let (uid, euid, gid, egid) = unsafe {
+        (
+            libc::getuid(),
+            libc::geteuid(),
+            libc::getgid(),
+            libc::getegid(),
+        )
+    };
+    let auxv = [
+        (AT_PHDR, *phdr),
+        (AT_PHENT, *phent),
+        (AT_PHNUM, *phnum),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_ENTRY, *entry),
+        (AT_UID, uid.into()),
+        (AT_EUID, euid.into()),
+        (AT_GID, gid.into()),
+        (AT_EGID, egid.into()),
+        (AT_HWCAP, HWCAP),
+        (AT_CLKTCK, 100),
+        (AT_SECURE, 0),
+        (AT_RANDOM, random),
+        (AT_EXECFN, arg_pointers.first().copied().unwrap_or_default()),
+        (AT_NULL, 0),
+    ];
+
+    let words = std::iter::once(args.len() as u64)
+        .chain(arg_pointers)
+        .chain([0])
+        .chain(env_pointers)
+        .chain([0])
+        .chain(auxv.into_iter().flat_map(|(key, value)| [key, value]))
+        .collect::<Vec<_>>();
+    // The stack pointer must be 16-byte aligned once everything is in place
+    let sp = (sp - words.len() as u64 * 8) & !0xf;
+    for (i, word) in words.into_iter().enumerate() {
+        memory.write_u64(sp + i as u64 * 8, word)?;
+    }
+
+    Ok(sp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{setup_stack, STACK_TOP};
+    use crate::{error::Error, load::Program, memory::Memory};
+    use std::collections::HashMap;
+
+    #[test]
+    fn stacks_that_do_not_fit_are_rejected() {
+        let mut memory = Memory::default();
+        memory.map(0x1_0000, 0x1000);
+        let mut program = Program {
+            memory,
+            entry: 0x1_0000,
+            end: 0x1_1000,
+            phdr: 0,
+            phent: 0,
+            phnum: 0,
+            tohost: None,
+            symbols: HashMap::new(),
+        };
+        let args = ["test".to_owned()];
+        // Overflowing when rounded up, wrapping around below zero, and
+        // running into the program
+        for size in [usize::MAX, STACK_TOP as usize + 1, STACK_TOP as usize] {
+            assert!(matches!(
+                setup_stack(&mut program, &args, &[], size),
+                Err(Error::StackSize(_))
+            ));
+        }
+        assert!(setup_stack(&mut program, &args, &[], 8 << 20).is_ok());
+    }
+}
@@ -0,0 +1,722 @@
+//! Emulation of the RISC-V Linux system call ABI on top of the host kernel.
+//!
+//! The system call number is taken from `a7` and its arguments from `a0`
+//! through `a5`. Arguments that point into guest memory are translated into
+//! host buffers, and structures whose layout differs between RISC-V and the
+//! host are converted field by field.
+
+use crate::{cpu::ExitReason, error::Error, memory::Memory};
+use std::{ffi::CString, io, mem, ops::ControlFlow};
+
+const PAGE_SIZE: u64 = 4096;
+/// Anonymous memory mappings are handed out upwards from this address, so
+/// that each one extends the region of the last.
+const MMAP_BASE: u64 = 0x20_0000_0000;
+
+// System call numbers from the asm-generic table used by RISC-V
+const SYS_GETCWD: u64 = 17;
+const SYS_DUP: u64 = 23;
+const SYS_DUP3: u64 = 24;
+const SYS_FCNTL: u64 = 25;
+const SYS_IOCTL: u64 = 29;
+const SYS_MKDIRAT: u64 = 34;
+const SYS_UNLINKAT: u64 = 35;
+const SYS_FACCESSAT: u64 = 48;
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_READV: u64 = 65;
+const SYS_WRITEV: u64 = 66;
+const SYS_PREAD64: u64 = 67;
+const SYS_PWRITE64: u64 = 68;
+const SYS_READLINKAT: u64 = 78;
+const SYS_NEWFSTATAT: u64 = 79;
+const SYS_FSTAT: u64 = 80;
+const SYS_EXIT: u64 = 93;
+const SYS_EXIT_GROUP: u64 = 94;
+const SYS_SET_TID_ADDRESS: u64 = 96;
+const SYS_FUTEX: u64 = 98;
+const SYS_SET_ROBUST_LIST: u64 = 99;
+const SYS_CLOCK_GETTIME: u64 = 113;
+const SYS_SCHED_YIELD: u64 = 124;
+const SYS_KILL: u64 = 129;
+const SYS_TGKILL: u64 = 131;
+const SYS_SIGALTSTACK: u64 = 132;
+const SYS_RT_SIGACTION: u64 = 134;
+const SYS_RT_SIGPROCMASK: u64 = 135;
+const SYS_TIMES: u64 = 153;
+const SYS_UNAME: u64 = 160;
+const SYS_GETRLIMIT: u64 = 163;
+const SYS_GETTIMEOFDAY: u64 = 169;
+const SYS_GETPID: u64 = 172;
+const SYS_GETPPID: u64 = 173;
+const SYS_GETUID: u64 = 174;
+const SYS_GETEUID: u64 = 175;
+const SYS_GETGID: u64 = 176;
+const SYS_GETEGID: u64 = 177;
+const SYS_GETTID: u64 = 178;
+const SYS_BRK: u64 = 214;
+const SYS_MUNMAP: u64 = 215;
+const SYS_MREMAP: u64 = 216;
+const SYS_MMAP: u64 = 222;
+const SYS_MPROTECT: u64 = 226;
+const SYS_MADVISE: u64 = 233;
+const SYS_PRLIMIT64: u64 = 261;
+const SYS_GETRANDOM: u64 = 278;
+
+const MAP_FIXED: u64 = 0x10;
+const FUTEX_WAIT: u64 = 0;
+const FUTEX_WAKE: u64 = 1;
+const TCGETS: u64 = 0x5401;
+const TIOCGWINSZ: u64 = 0x5413;
+/// Size of the kernel's `struct termios`, which matches between RISC-V and
+/// x86-64.
+const TERMIOS_SIZE: usize = 36;
+const WINSIZE_SIZE: usize = 8;
+const SIGSET_SIZE: usize = 8;
+/// Size of the kernel's `struct sigaction`, which has no `sa_restorer` on
+/// RISC-V.
+const SIGACTION_SIZE: usize = 16 + SIGSET_SIZE;
+const PATH_MAX: usize = 4096;
+/// Most bytes a single read or write transfers, like Linux's `MAX_RW_COUNT`.
+const MAX_RW_COUNT: u64 = 0x7fff_f000;
+/// Most buffers that `readv` and `writev` take.
+const IOV_MAX: u64 = 1024;
+
+/// A negated error number as returned by failing system calls.
+struct Errno(i32);
+
+impl From<Error> for Errno {
+    fn from(_: Error) -> Self {
+        Self(libc::EFAULT)
+    }
+}
+
+type SyscallResult = Result<u64, Errno>;
+
+/// Per-process state that the kernel would normally keep track of.
+pub struct Process {
+    brk_start: u64,
+    brk: u64,
+    /// End of the memory that has been mapped for the heap so far. The heap
+    /// is mapped from the end of the program, so that the two are one region
+    /// of memory.
+    brk_mapped: u64,
+    mmap_next: u64,
+}
+
+impl Process {
+    pub const fn new(program_end: u64) -> Self {
+        let brk_start = page_align_up(program_end)
+            .expect("program ends at the top of the address space");
+        Self {
+            brk_start,
+            brk: brk_start,
+            brk_mapped: program_end,
+            mmap_next: MMAP_BASE,
+        }
+    }
+
+    /// Performs a system call, returning the value to place in `a0` or
+    /// breaking if the process wants to exit.
+    pub fn syscall(
+        &mut self,
+        memory: &mut Memory,
+        number: u64,
+        args: [u64; 6],
+    ) -> ControlFlow<ExitReason, u64> {
+        if let SYS_EXIT | SYS_EXIT_GROUP = number {
+            return ControlFlow::Break(ExitReason::Exit(args[0] as i32));
+        }
+        ControlFlow::Continue(match self.dispatch(memory, number, args) {
+            Ok(ret) => ret,
+            Err(Errno(errno)) => (-i64::from(errno)) as u64,
+        })
+    }
+
+    fn dispatch(
+        &mut self,
+        memory: &mut Memory,
+        number: u64,
+        args: [u64; 6],
+    ) -> SyscallResult {
+        let [a0, a1, a2, a3, a4, a5] = args;
+        match number {
+            SYS_GETCWD => {
+                let buf = memory.slice_mut(a0, a1 as usize)?;
+                host_syscall(libc::SYS_getcwd, [buf.as_mut_ptr() as _, a1 as _])
+            }
+            SYS_DUP => host_syscall(libc::SYS_dup, [a0 as _]),
+            SYS_DUP3 => {
+                host_syscall(libc::SYS_dup3, [a0 as _, a1 as _, a2 as _])
+            }
+            SYS_FCNTL => match a1 as i32 {
+                libc::F_DUPFD
+                | libc::F_DUPFD_CLOEXEC
+                | libc::F_GETFD
+                | libc::F_SETFD
+                | libc::F_GETFL
+                | libc::F_SETFL => {
+                    host_syscall(libc::SYS_fcntl, [a0 as _, a1 as _, a2 as _])
+                }
+                _ => Err(Errno(libc::EINVAL)),
+            },
+            SYS_IOCTL => {
+                let size = match a1 {
+                    TCGETS => TERMIOS_SIZE,
+                    TIOCGWINSZ => WINSIZE_SIZE,
+                    _ => return Err(Errno(libc::ENOTTY)),
+                };
+                let buf = memory.slice_mut(a2, size)?;
+                host_syscall(
+                    libc::SYS_ioctl,
+                    [a0 as _, a1 as _, buf.as_mut_ptr() as _],
+                )
+            }
+            SYS_MKDIRAT => {
+                let path = read_c_string(memory, a1)?;
+                host_syscall(
+                    libc::SYS_mkdirat,
+                    [a0 as _, path.as_ptr() as _, a2 as _],
+                )
+            }
+            SYS_UNLINKAT => {
+                let path = read_c_string(memory, a1)?;
+                host_syscall(
+                    libc::SYS_unlinkat,
+                    [a0 as _, path.as_ptr() as _, a2 as _],
+                )
+            }
+            SYS_FACCESSAT => {
+                let path = read_c_string(memory, a1)?;
+                host_syscall(
+                    libc::SYS_faccessat,
+                    [a0 as _, path.as_ptr() as _, a2 as _],
+                )
+            }
+            SYS_OPENAT => {
+                let path = read_c_string(memory, a1)?;
+                host_syscall(
+                    libc::SYS_openat,
+                    [a0 as _, path.as_ptr() as _, a2 as _, a3 as _],
+                )
+            }
+            SYS_CLOSE => host_syscall(libc::SYS_close, [a0 as _]),
+            SYS_LSEEK => {
+                host_syscall(libc::SYS_lseek, [a0 as _, a1 as _, a2 as _])
+            }
+            SYS_READ => {
+                let buf = memory.slice_mut(a1, a2 as usize)?;
+                host_syscall(
+                    libc::SYS_read,
+                    [a0 as _, buf.as_mut_ptr() as _, a2 as _],
+                )
+            }
+            SYS_WRITE => {
+                let buf = memory.slice(a1, a2 as usize)?;
+                host_syscall(
+                    libc::SYS_write,
+                    [a0 as _, buf.as_ptr() as _, a2 as _],
+                )
+            }
+            SYS_PREAD64 => {
+                let buf = memory.slice_mut(a1, a2 as usize)?;
+                host_syscall(
+                    libc::SYS_pread64,
+                    [a0 as _, buf.as_mut_ptr() as _, a2 as _, a3 as _],
+                )
+            }
+            SYS_PWRITE64 => {
+                let buf = memory.slice(a1, a2 as usize)?;
+                host_syscall(
+                    libc::SYS_pwrite64,
+                    [a0 as _, buf.as_ptr() as _, a2 as _, a3 as _],
+                )
+            }
+            SYS_READV => {
+                let iovecs = read_iovecs(memory, a1, a2)?;
+                // Every buffer has to be checked up front, since whatever
+                // gets read can't be given back
+                let mut total = 0u64;
+                for &(base, len) in &iovecs {
+                    memory.slice_mut(base, len as usize)?;
+                    total =
+                        total.checked_add(len).ok_or(Errno(libc::EINVAL))?;
+                }
+                let mut buf = vec![0; total.min(MAX_RW_COUNT) as usize];
+                let count = host_syscall(
+                    libc::SYS_read,
+                    [a0 as _, buf.as_mut_ptr() as _, buf.len() as _],
+                )?;
+                // Scatter what was read across the guest buffers
+                let mut remaining = &buf[..count as usize];
+                for (base, len) in iovecs {
+                    let (chunk, rest) =
+                        remaining.split_at(remaining.len().min(len as usize));
+                    memory.write_bytes(base, chunk)?;
+                    remaining = rest;
+                }
+                Ok(count)
+            }
+            SYS_WRITEV => {
+                let mut buf = Vec::new();
+                for (base, len) in read_iovecs(memory, a1, a2)? {
+                    buf.extend_from_slice(memory.slice(base, len as usize)?);
+                }
+                host_syscall(
+                    libc::SYS_write,
+                    [a0 as _, buf.as_ptr() as _, buf.len() as _],
+                )
+            }
+            SYS_READLINKAT => {
+                let path = read_c_string(memory, a1)?;
+                let buf = memory.slice_mut(a2, a3 as usize)?;
+                host_syscall(
+                    libc::SYS_readlinkat,
+                    [
+                        a0 as _,
+                        path.as_ptr() as _,
+                        buf.as_mut_ptr() as _,
+                        a3 as _,
+                    ],
+                )
+            }
+            SYS_NEWFSTATAT => {
+                let path = read_c_string(memory, a1)?;
+                // SAFETY: `struct stat` is plain old data
+                let mut stat: libc::stat = unsafe { mem::zeroed() };
+                host_syscall(
+                    libc::SYS_newfstatat,
+                    [
+                        a0 as _,
+                        path.as_ptr() as _,
+                        &mut stat as *mut _ as _,
+                        a3 as _,
+                    ],
+                )?;
+                write_stat(memory, a2, &stat)
+            }
+            SYS_FSTAT => {
+                // SAFETY: `struct stat` is plain old data
+                let mut stat: libc::stat = unsafe { mem::zeroed() };
+                host_syscall(
+                    libc::SYS_fstat,
+                    [a0 as _, &mut stat as *mut _ as _],
+                )?;
+                write_stat(memory, a1, &stat)
+            }
+            SYS_SET_TID_ADDRESS | SYS_GETTID => {
+                host_syscall(libc::SYS_gettid, [])
+            }
+            SYS_FUTEX => match a1 & 0x7f {
+                // There is only a single thread, so nobody could wake us up
+                FUTEX_WAIT => Err(Errno(libc::EAGAIN)),
+                FUTEX_WAKE => Ok(0),
+                _ => Err(Errno(libc::ENOSYS)),
+            },
+            SYS_SET_ROBUST_LIST | SYS_SCHED_YIELD | SYS_SIGALTSTACK
+            | SYS_MPROTECT | SYS_MADVISE => Ok(0),
+            // Signals are never delivered, so every action is still the
+            // default one and no signal is blocked
+            SYS_RT_SIGACTION | SYS_RT_SIGPROCMASK => {
+                let size = match number {
+                    SYS_RT_SIGACTION => SIGACTION_SIZE,
+                    _ => SIGSET_SIZE,
+                };
+                if a2 != 0 {
+                    memory.slice_mut(a2, size)?.fill(0);
+                }
+                Ok(0)
+            }
+            SYS_CLOCK_GETTIME => {
+                // SAFETY: `struct timespec` is plain old data
+                let mut time: libc::timespec = unsafe { mem::zeroed() };
+                host_syscall(
+                    libc::SYS_clock_gettime,
+                    [a0 as _, &mut time as *mut _ as _],
+                )?;
+                write_timespec(memory, a1, time.tv_sec, time.tv_nsec)
+            }
+            SYS_GETTIMEOFDAY => {
+                // SAFETY: `struct timeval` is plain old data
+                let mut time: libc::timeval = unsafe { mem::zeroed() };
+                host_syscall(
+                    libc::SYS_gettimeofday,
+                    [&mut time as *mut _ as _, 0],
+                )?;
+                if a0 != 0 {
+                    write_timespec(memory, a0, time.tv_sec, time.tv_usec)?;
+                }
+                if a1 != 0 {
+                    memory.write_bytes(a1, &[0; 8])?;
+                }
+                Ok(0)
+            }
+            SYS_KILL => host_syscall(libc::SYS_kill, [a0 as _, a1 as _]),
+            SYS_TGKILL => {
+                host_syscall(libc::SYS_tgkill, [a0 as _, a1 as _, a2 as _])
+            }
+            SYS_TIMES => {
+                // `struct tms` consists of four longs on both architectures
+                let mut tms = [0i64; 4];
+                let ret =
+                    host_syscall(libc::SYS_times, [tms.as_mut_ptr() as _])?;
+                if a0 != 0 {
+                    for (i, field) in tms.into_iter().enumerate() {
+                        memory.write_u64(a0 + i as u64 * 8, field as u64)?;
+                    }
+                }
+                Ok(ret)
+            }
+            SYS_UNAME => {
+                // SAFETY: `struct utsname` is plain old data
+                let mut uts: libc::utsname = unsafe { mem::zeroed() };
+                host_syscall(libc::SYS_uname, [&mut uts as *mut _ as _])?;
+                uts.machine = [0; 65];
+                for (dest, &byte) in uts.machine.iter_mut().zip(b"riscv64") {
+                    *dest = byte as libc::c_char;
+                }
+                // SAFETY: `struct utsname` is plain old data with the same
+                // layout on both architectures
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(
+                        (&uts as *const libc::utsname).cast::<u8>(),
+                        mem::size_of::<libc::utsname>(),
+                    )
+                };
+                memory.write_bytes(a0, bytes)?;
+                Ok(0)
+            }
+            SYS_GETRLIMIT => {
+                self.dispatch(memory, SYS_PRLIMIT64, [0, a0, 0, a1, 0, 0])
+            }
+            SYS_PRLIMIT64 => {
+                // Changing limits is silently ignored
+                if a3 != 0 {
+                    let mut limit = [0u64; 2];
+                    host_syscall(
+                        libc::SYS_prlimit64,
+                        [a0 as _, a1 as _, 0, limit.as_mut_ptr() as _],
+                    )?;
+                    memory.write_u64(a3, limit[0])?;
+                    memory.write_u64(a3 + 8, limit[1])?;
+                }
+                Ok(0)
+            }
+            SYS_GETPID => host_syscall(libc::SYS_getpid, []),
+            SYS_GETPPID => host_syscall(libc::SYS_getppid, []),
+            SYS_GETUID => host_syscall(libc::SYS_getuid, []),
+            SYS_GETEUID => host_syscall(libc::SYS_geteuid, []),
+            SYS_GETGID => host_syscall(libc::SYS_getgid, []),
+            SYS_GETEGID => host_syscall(libc::SYS_getegid, []),
+            SYS_BRK => Ok(self.brk(memory, a0)),
+            SYS_MUNMAP => {
+                if !a0.is_multiple_of(PAGE_SIZE) || a1 == 0 {
+                    return Err(Errno(libc::EINVAL));
+                }
+                let len = page_align_up(a1).ok_or(Errno(libc::EINVAL))?;
+                memory.unmap(a0, len as usize);
+                Ok(0)
+            }
+            // Callers such as `realloc` fall back to copying when remapping
+            // fails
+            SYS_MREMAP => Err(Errno(libc::ENOMEM)),
+            SYS_MMAP => self.mmap(memory, a0, a1, a3, a4, a5),
+            SYS_GETRANDOM => {
+                let buf = memory.slice_mut(a0, a1 as usize)?;
+                host_syscall(
+                    libc::SYS_getrandom,
+                    [buf.as_mut_ptr() as _, a1 as _, a2 as _],
+                )
+            }
+            _ => Err(Errno(libc::ENOSYS)),
+        }
+    }
+
+    fn brk(&mut self, memory: &mut Memory, address: u64) -> u64 {
+        if address < self.brk_start {
+            return self.brk;
+        }
+        let Some(new_mapped) = page_align_up(address) else {
+            return self.brk;
+        };
+        if new_mapped > self.brk_mapped {
+            let size = (new_mapped - self.brk_mapped) as usize;
+            if !memory.is_unmapped(self.brk_mapped, size) {
+                return self.brk;
+            }
+            memory.map(self.brk_mapped, size);
+            self.brk_mapped = new_mapped;
+        }
+        self.brk = address;
+        self.brk
+    }
+
+    fn mmap(
+        &mut self,
+        memory: &mut Memory,
+        address: u64,
+        len: u64,
+        flags: u64,
+        fd: u64,
+        offset: u64,
+    ) -> SyscallResult {
+        if len == 0 {
+            return Err(Errno(libc::EINVAL));
+        }
+        let len = page_align_up(len).ok_or(Errno(libc::ENOMEM))?;
+        let address = if flags & MAP_FIXED != 0 {
+            if !address.is_multiple_of(PAGE_SIZE) {
+                return Err(Errno(libc::EINVAL));
+            }
+            if address.checked_add(len).is_none() {
+                return Err(Errno(libc::ENOMEM));
+            }
+            // Whatever was mapped there before gets replaced, even if the
+            // new mapping only covers part of it
+            memory.unmap(address, len as usize);
+            address
+        } else {
+            let address = self.mmap_next;
+            // Mappings grow up towards the stack
+            let next = address.checked_add(len).ok_or(Errno(libc::ENOMEM))?;
+            if !memory.is_unmapped(address, len as usize) {
+                return Err(Errno(libc::ENOMEM));
+            }
+            self.mmap_next = next;
+            address
+        };
+        memory.map(address, len as usize);
+
+        if fd as i32 != -1 {
+            // File mappings are always private copies
+            let buf = memory.slice_mut(address, len as usize)?;
+            host_syscall(
+                libc::SYS_pread64,
+                [fd as _, buf.as_mut_ptr() as _, len as _, offset as _],
+            )?;
+        }
+
+        Ok(address)
+    }
+}
+
+fn host_syscall<const N: usize>(
+    number: libc::c_long,
+    args: [libc::c_long; N],
+) -> SyscallResult {
+    let mut all_args = [0; 6];
+    all_args[..N].copy_from_slice(&args);
+    let [a0, a1, a2, a3, a4, a5] = all_args;
+    // SAFETY: every pointer argument refers to a live buffer that is large
+    // enough for the call in question
+    let ret = unsafe { libc::syscall(number, a0, a1, a2, a3, a4, a5) };
+    if ret < 0 {
+        Err(Errno(
+            io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EIO),
+        ))
+    } else {
+        Ok(ret as u64)
+    }
+}
+
+/// Rounds up to a page boundary, unless that's past the end of the address
+/// space.
+const fn page_align_up(address: u64) -> Option<u64> {
+    match address.checked_add(PAGE_SIZE - 1) {
+        Some(end) => Some(end & !(PAGE_SIZE - 1)),
+        None => None,
+    }
+}
+
+fn read_c_string(memory: &Memory, address: u64) -> Result<CString, Errno> {
+    let mut bytes = Vec::new();
+    loop {
+        match memory.read_u8(address + bytes.len() as u64)? {
+            0 => return Ok(CString::new(bytes).unwrap()),
+            _ if bytes.len() == PATH_MAX => {
+                return Err(Errno(libc::ENAMETOOLONG));
+            }
+            byte => bytes.push(byte),
+        }
+    }
+}
+
+/// Reads an array of `struct iovec` as `(base, len)` pairs.
+fn read_iovecs(
+    memory: &Memory,
+    address: u64,
+    count: u64,
+) -> Result<Vec<(u64, u64)>, Errno> {
+    if count > IOV_MAX {
+        return Err(Errno(libc::EINVAL));
+    }
+    (0..count)
+        .map(|i| {
+            let iovec = i
+                .checked_mul(16)
+                .and_then(|offset| address.checked_add(offset))
+                .ok_or(Errno(libc::EFAULT))?;
+            // Once the base has been read, `iovec + 8` is known not to wrap
+            Ok((memory.read_u64(iovec)?, memory.read_u64(iovec + 8)?))
+        })
+        .collect()
+}
+
+/// Writes a `struct timespec` or `struct timeval`, which consist of two longs.
+fn write_timespec(
+    memory: &mut Memory,
+    address: u64,
+    seconds: i64,
+    fraction: i64,
+) -> SyscallResult {
+    memory.write_u64(address, seconds as u64)?;
+    memory.write_u64(address + 8, fraction as u64)?;
+    Ok(0)
+}
+
+/// Converts the host's `struct stat` to the asm-generic layout used by RISC-V.
+fn write_stat(
+    memory: &mut Memory,
+    address: u64,
+    stat: &libc::stat,
+) -> SyscallResult {
+    let mut guest = Vec::with_capacity(128);
+    guest.extend(stat.st_dev.to_le_bytes());
+    guest.extend(stat.st_ino.to_le_bytes());
+    guest.extend(stat.st_mode.to_le_bytes());
+    guest.extend((stat.st_nlink as u32).to_le_bytes());
+    guest.extend(stat.st_uid.to_le_bytes());
+    guest.extend(stat.st_gid.to_le_bytes());
+    guest.extend(stat.st_rdev.to_le_bytes());
+    guest.extend([0; 8]); // __pad1
+    guest.extend(stat.st_size.to_le_bytes());
+    guest.extend((stat.st_blksize as i32).to_le_bytes());
+    guest.extend([0; 4]); // __pad2
+    guest.extend(stat.st_blocks.to_le_bytes());
+    guest.extend(stat.st_atime.to_le_bytes());
+    guest.extend(stat.st_atime_nsec.to_le_bytes());
+    guest.extend(stat.st_mtime.to_le_bytes());
+    guest.extend(stat.st_mtime_nsec.to_le_bytes());
+    guest.extend(stat.st_ctime.to_le_bytes());
+    guest.extend(stat.st_ctime_nsec.to_le_bytes());
+    guest.extend([0; 8]); // __unused
+    memory.write_bytes(address, &guest)?;
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Process, MAP_FIXED, PAGE_SIZE, SIGACTION_SIZE, SIGSET_SIZE, SYS_BRK,
+        SYS_MMAP, SYS_MUNMAP, SYS_READ, SYS_READV, SYS_RT_SIGACTION,
+        SYS_RT_SIGPROCMASK, SYS_WRITEV,
+    };
+    use crate::memory::Memory;
+    use std::{io::Write, ops::ControlFlow, os::fd::AsRawFd};
+
+    fn syscall(
+        process: &mut Process,
+        memory: &mut Memory,
+        number: u64,
+        args: [u64; 6],
+    ) -> u64 {
+        match process.syscall(memory, number, args) {
+            ControlFlow::Continue(ret) => ret,
+            ControlFlow::Break(reason) => panic!("exited: {reason:?}"),
+        }
+    }
+
+    #[test]
+    fn reads_span_brk_increments() {
+        const PROGRAM_END: u64 = 0x1_0800;
+        let mut memory = Memory::default();
+        memory.map(0x1_0000, 0x800);
+        let mut process = Process::new(PROGRAM_END);
+        let mut syscall = |memory: &mut Memory, number, args: [u64; 3]| {
+            let [a0, a1, a2] = args;
+            syscall(&mut process, memory, number, [a0, a1, a2, 0, 0, 0])
+        };
+        let start = syscall(&mut memory, SYS_BRK, [0; 3]);
+        for increment in 1..=2 {
+            let brk = start + increment * 0x1000;
+            assert_eq!(syscall(&mut memory, SYS_BRK, [brk, 0, 0]), brk);
+        }
+
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        // Across the two increments, and across the end of the program
+        for buf in [start + 0xffc, PROGRAM_END - 4] {
+            writer.write_all(b"spanning").unwrap();
+            let fd = reader.as_raw_fd() as u64;
+            assert_eq!(syscall(&mut memory, SYS_READ, [fd, buf, 8]), 8);
+            assert_eq!(memory.slice(buf, 8).unwrap(), b"spanning");
+        }
+    }
+
+    #[test]
+    fn mappings_can_be_partly_replaced_and_unmapped() {
+        const EINVAL: u64 = -libc::EINVAL as u64;
+        let mut memory = Memory::default();
+        let mut process = Process::new(0x1_0000);
+        let mut mmap = |memory: &mut Memory, address, len, flags| {
+            let args = [address, len, 0, flags, u64::MAX, 0];
+            syscall(&mut process, memory, SYS_MMAP, args)
+        };
+        let base = mmap(&mut memory, 0, 4 * PAGE_SIZE, 0);
+        memory
+            .write_bytes(base, &[1; 4 * PAGE_SIZE as usize])
+            .unwrap();
+        assert_eq!(mmap(&mut memory, base + 1, PAGE_SIZE, MAP_FIXED), EINVAL);
+        // Straddling the end of the mapping
+        let fixed = base + 3 * PAGE_SIZE;
+        assert_eq!(mmap(&mut memory, fixed, 2 * PAGE_SIZE, MAP_FIXED), fixed);
+        assert_eq!(memory.read_u8(fixed).unwrap(), 0);
+        assert_eq!(memory.read_u8(fixed - 1).unwrap(), 1);
+
+        let mut munmap = |memory: &mut Memory, address, len| {
+            let args = [address, len, 0, 0, 0, 0];
+            syscall(&mut process, memory, SYS_MUNMAP, args)
+        };
+        assert_eq!(munmap(&mut memory, base + 1, PAGE_SIZE), EINVAL);
+        assert_eq!(munmap(&mut memory, base + PAGE_SIZE, PAGE_SIZE), 0);
+        assert!(memory.is_unmapped(base + PAGE_SIZE, PAGE_SIZE as usize));
+        assert_eq!(memory.read_u8(base).unwrap(), 1);
+        assert_eq!(memory.read_u8(base + 2 * PAGE_SIZE).unwrap(), 1);
+    }
+
+    #[test]
+    fn old_signal_state_is_zeroed() {
+        let mut memory = Memory::default();
+        memory.map(0x1_0000, 0x1000);
+        let mut process = Process::new(0x1_1000);
+        for (number, size) in [
+            (SYS_RT_SIGACTION, SIGACTION_SIZE),
+            (SYS_RT_SIGPROCMASK, SIGSET_SIZE),
+        ] {
+            memory.write_bytes(0x1_0000, &[0xff; 0x100]).unwrap();
+            let args = [2, 0, 0x1_0000, SIGSET_SIZE as u64, 0, 0];
+            assert_eq!(syscall(&mut process, &mut memory, number, args), 0);
+            let old = memory.slice(0x1_0000, size + 1).unwrap();
+            assert!(old[..size].iter().all(|&byte| byte == 0));
+            // Nothing past the end gets touched
+            assert_eq!(old[size], 0xff);
+        }
+    }
+
+    #[test]
+    fn too_many_iovecs_are_rejected() {
+        const EINVAL: u64 = -libc::EINVAL as u64;
+        let mut memory = Memory::default();
+        memory.map(0x1_0000, 0x1000);
+        let mut process = Process::new(0x1_1000);
+        for number in [SYS_READV, SYS_WRITEV] {
+            let args = [0, 0x1_0000, u64::MAX, 0, 0, 0];
+            let ret = syscall(&mut process, &mut memory, number, args);
+            assert_eq!(ret, EINVAL);
+        }
+    }
+}
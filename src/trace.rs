@@ -0,0 +1,210 @@
+//! Logs of retired instructions in the format of Spike's `--log-commits`, so
+//! that runs can be compared against Spike and other simulators line by
+//! line.
+//!
+//! Each instruction gets two lines, like with Spike's `-l --log-commits`: one
+//! with its disassembly and one with the privilege mode it ran in and what it
+//! wrote back. Instructions that raise exceptions aren't logged. Writes to
+//! CSRs are only logged when done by CSR instructions.
+
+use crate::{
+    csr::Privilege,
+    disasm::CsrName,
+    instruction::Instruction,
+    register::{FRegisterName, RegisterName},
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+pub struct Trace {
+    out: BufWriter<File>,
+}
+
+impl Trace {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn log(&mut self, hart: usize, commit: &mut Commit) -> io::Result<()> {
+        let out = &mut self.out;
+        let encoding = Value::of_instruction(commit.bits);
+        writeln!(
+            out,
+            "core{hart:4}: {} ({encoding}) {}",
+            Value(commit.pc, 8),
+            commit.disassembly
+        )?;
+        write!(
+            out,
+            "core{hart:4}: {} {} ({encoding})",
+            commit.privilege as u8,
+            Value(commit.pc, 8)
+        )?;
+        // Spike orders writebacks by register file and number
+        commit.writes.sort_by_key(|(register, _)| register.key());
+        for (register, value) in &commit.writes {
+            match register {
+                Register::X(reg) => write!(out, " x{:<2}", usize::from(*reg))?,
+                Register::F(reg) => write!(out, " f{:<2}", usize::from(*reg))?,
+                Register::Csr(csr) => write!(out, " c{csr}_{}", CsrName(*csr))?,
+            }
+            write!(out, " {}", Value(*value, 8))?;
+        }
+        for address in &commit.loads {
+            write!(out, " mem {}", Value(*address, 8))?;
+        }
+        for store in &commit.stores {
+            write!(
+                out,
+                " mem {} {}",
+                Value(store.address, 8),
+                Value(store.value, store.size)
+            )?;
+        }
+        writeln!(out)
+    }
+}
+
+/// What the instruction being run has done so far, as far as the log is
+/// concerned.
+pub struct Commit {
+    pub privilege: Privilege,
+    pub pc: u64,
+    pub bits: u32,
+    pub disassembly: String,
+    /// The register that the instruction writes its result to.
+    pub destination: Option<Register>,
+    /// Registers written to, with their new values.
+    pub writes: Vec<(Register, u64)>,
+    /// Addresses loaded from.
+    pub loads: Vec<u64>,
+    pub stores: Vec<Store>,
+}
+
+impl Commit {
+    pub const fn new() -> Self {
+        Self {
+            privilege: Privilege::Machine,
+            pc: 0,
+            bits: 0,
+            disassembly: String::new(),
+            destination: None,
+            writes: Vec::new(),
+            loads: Vec::new(),
+            stores: Vec::new(),
+        }
+    }
+
+    /// Forgets about the last instruction and starts recording another one.
+    pub fn start(
+        &mut self,
+        privilege: Privilege,
+        pc: u64,
+        bits: u32,
+        disassembly: String,
+        instruction: &Instruction,
+    ) {
+        self.privilege = privilege;
+        self.pc = pc;
+        self.bits = bits;
+        self.disassembly = disassembly;
+        self.destination = destination(instruction);
+        self.writes.clear();
+        self.loads.clear();
+        self.stores.clear();
+    }
+}
+
+pub struct Store {
+    pub address: u64,
+    pub value: u64,
+    /// Size in bytes.
+    pub size: usize,
+}
+
+#[derive(Clone, Copy)]
+pub enum Register {
+    X(RegisterName),
+    F(FRegisterName),
+    Csr(u16),
+}
+
+impl Register {
+    /// The key that Spike sorts writebacks by.
+    fn key(self) -> usize {
+        match self {
+            Self::X(reg) => usize::from(reg) << 4,
+            Self::F(reg) => usize::from(reg) << 4 | 1,
+            Self::Csr(csr) => usize::from(csr) << 4 | 4,
+        }
+    }
+}
+
+/// A value printed in hex with as many digits as its size in bytes calls
+/// for.
+struct Value(u64, usize);
+
+impl Value {
+    const fn of_instruction(bits: u32) -> Self {
+        if bits & 0b11 == 0b11 {
+            Self(bits as u64, 4)
+        } else {
+            Self(bits as u64, 2)
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{:0width$x}", self.0, width = self.1 * 2)
+    }
+}
+
+/// The register that an instruction writes its result to, not counting
+/// `x0`.
+fn destination(instruction: &Instruction) -> Option<Register> {
+    let register = match *instruction {
+        Instruction::R { rd, .. }
+        | Instruction::I { rd, .. }
+        | Instruction::U { rd, .. }
+        | Instruction::Jal { rd, .. }
+        | Instruction::FCompare { rd, .. }
+        | Instruction::FClass { rd, .. }
+        | Instruction::FMvToInt { rd, .. }
+        | Instruction::FCvtToInt { rd, .. }
+        | Instruction::LoadReserved { rd, .. }
+        | Instruction::StoreConditional { rd, .. }
+        | Instruction::Amo { rd, .. }
+        | Instruction::Csr { rd, .. }
+        | Instruction::CsrImm { rd, .. } => Register::X(rd),
+        Instruction::FLoad { rd, .. }
+        | Instruction::FArith { rd, .. }
+        | Instruction::FSqrt { rd, .. }
+        | Instruction::FSign { rd, .. }
+        | Instruction::FMinMax { rd, .. }
+        | Instruction::FFma { rd, .. }
+        | Instruction::FMvFromInt { rd, .. }
+        | Instruction::FCvtFromInt { rd, .. }
+        | Instruction::FCvtFloat { rd, .. } => Register::F(rd),
+        Instruction::S { .. }
+        | Instruction::B { .. }
+        | Instruction::FStore { .. }
+        | Instruction::Fence { .. }
+        | Instruction::FenceI
+        | Instruction::Ecall
+        | Instruction::Ebreak
+        | Instruction::Sret
+        | Instruction::Mret
+        | Instruction::Wfi
+        | Instruction::SfenceVma { .. } => return None,
+    };
+    match register {
+        Register::X(RegisterName::X0) => None,
+        register => Some(register),
+    }
+}
@@ -0,0 +1,118 @@
+//! Exceptions and interrupts as defined by the RISC-V privileged architecture.
+
+/// An exception raised by an instruction, along with the value that ends up
+/// in `mtval` when it is taken.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+pub enum Exception {
+    #[error("misaligned instruction address 0x{0:016x}")]
+    InstructionAddressMisaligned(u64),
+    #[error("instruction access fault at 0x{0:016x}")]
+    InstructionAccessFault(u64),
+    #[error("illegal instruction 0x{0:08x}")]
+    IllegalInstruction(u32),
+    #[error("breakpoint at 0x{0:016x}")]
+    Breakpoint(u64),
+    #[error("misaligned load from 0x{0:016x}")]
+    LoadAddressMisaligned(u64),
+    #[error("load access fault at 0x{0:016x}")]
+    LoadAccessFault(u64),
+    #[error("misaligned store to 0x{0:016x}")]
+    StoreAddressMisaligned(u64),
+    #[error("store access fault at 0x{0:016x}")]
+    StoreAccessFault(u64),
+    #[error("environment call from U-mode")]
+    EnvironmentCallFromUMode,
+    #[error("environment call from S-mode")]
+    EnvironmentCallFromSMode,
+    #[error("environment call from M-mode")]
+    EnvironmentCallFromMMode,
+    #[error("instruction page fault at 0x{0:016x}")]
+    InstructionPageFault(u64),
+    #[error("load page fault at 0x{0:016x}")]
+    LoadPageFault(u64),
+    #[error("store page fault at 0x{0:016x}")]
+    StorePageFault(u64),
+}
+
+impl Exception {
+    /// The exception code written to `mcause`.
+    pub const fn cause(self) -> u64 {
+        match self {
+            Self::InstructionAddressMisaligned(_) => 0,
+            Self::InstructionAccessFault(_) => 1,
+            Self::IllegalInstruction(_) => 2,
+            Self::Breakpoint(_) => 3,
+            Self::LoadAddressMisaligned(_) => 4,
+            Self::LoadAccessFault(_) => 5,
+            Self::StoreAddressMisaligned(_) => 6,
+            Self::StoreAccessFault(_) => 7,
+            Self::EnvironmentCallFromUMode => 8,
+            Self::EnvironmentCallFromSMode => 9,
+            Self::EnvironmentCallFromMMode => 11,
+            Self::InstructionPageFault(_) => 12,
+            Self::LoadPageFault(_) => 13,
+            Self::StorePageFault(_) => 15,
+        }
+    }
+
+    /// The trap value written to `mtval`.
+    pub const fn tval(self) -> u64 {
+        match self {
+            Self::InstructionAddressMisaligned(address)
+            | Self::InstructionAccessFault(address)
+            | Self::Breakpoint(address)
+            | Self::LoadAddressMisaligned(address)
+            | Self::LoadAccessFault(address)
+            | Self::StoreAddressMisaligned(address)
+            | Self::StoreAccessFault(address)
+            | Self::InstructionPageFault(address)
+            | Self::LoadPageFault(address)
+            | Self::StorePageFault(address) => address,
+            Self::IllegalInstruction(bits) => bits as u64,
+            Self::EnvironmentCallFromUMode
+            | Self::EnvironmentCallFromSMode
+            | Self::EnvironmentCallFromMMode => 0,
+        }
+    }
+
+    pub const fn is_environment_call(self) -> bool {
+        matches!(
+            self,
+            Self::EnvironmentCallFromUMode
+                | Self::EnvironmentCallFromSMode
+                | Self::EnvironmentCallFromMMode
+        )
+    }
+}
+
+/// An asynchronous interrupt, numbered by its bit in `mip` and `mie`.
+#[derive(Debug, Clone, Copy)]
+pub enum Interrupt {
+    SupervisorSoftware = 1,
+    MachineSoftware = 3,
+    SupervisorTimer = 5,
+    MachineTimer = 7,
+    SupervisorExternal = 9,
+    MachineExternal = 11,
+}
+
+impl Interrupt {
+    /// Interrupts in decreasing order of priority.
+    pub const ALL: [Self; 6] = [
+        Self::MachineExternal,
+        Self::MachineSoftware,
+        Self::MachineTimer,
+        Self::SupervisorExternal,
+        Self::SupervisorSoftware,
+        Self::SupervisorTimer,
+    ];
+
+    /// The value written to `mcause`, with the interrupt bit set.
+    pub const fn cause(self) -> u64 {
+        1 << 63 | self as u64
+    }
+
+    pub const fn bit(self) -> u64 {
+        1 << self as u64
+    }
+}
@@ -0,0 +1,353 @@
+//! A machine modelled after QEMU's `virt` board, for running firmware and
+//! operating system kernels.
+//!
+//! The program given on the command line is the firmware, which starts
+//! executing in M-mode at the bottom of RAM. The kernel, initrd and device
+//! tree are placed at fixed locations in RAM for the firmware to find:
+//!
+//! - the kernel [`KERNEL_OFFSET`] bytes into RAM,
+//! - the initrd halfway through RAM,
+//! - the device tree at the highest 2 MiB boundary that leaves room for it.
+//!
+//! Unless one is given on the command line, the device tree is generated
+//! from the machine's configuration.
+//!
+//! Every hart starts executing the firmware at the same time, with its hart
+//! ID in `a0`. With `--sbi builtin`, the emulator takes the place of the
+//! firmware, and the program starts executing in S-mode on hart 0 instead,
+//! while the other harts wait to be started through the SBI.
+
+use crate::{
+    csr::{self, TIMEBASE_FREQUENCY},
+    device::{
+        clint::Clint,
+        finisher::Finisher,
+        plic::{Plic, NUM_SOURCES},
+        uart::Uart,
+        Device,
+    },
+    dtb::DtbWriter,
+    load::Program,
+    memory::Memory,
+    trap::Interrupt,
+    Opts,
+};
+use std::{error::Error, fs, time::Instant};
+
+pub const RAM_BASE: u64 = 0x8000_0000;
+/// Where the kernel goes, right after the 2 MiB reserved for the firmware.
+pub const KERNEL_OFFSET: u64 = 0x20_0000;
+const DTB_ALIGNMENT: u64 = 0x20_0000;
+
+pub const FINISHER_BASE: u64 = 0x10_0000;
+pub const FINISHER_SIZE: u64 = 0x1000;
+pub const CLINT_BASE: u64 = 0x200_0000;
+pub const CLINT_SIZE: u64 = 0x1_0000;
+pub const PLIC_BASE: u64 = 0xc00_0000;
+pub const PLIC_SIZE: u64 = 0x60_0000;
+pub const UART_BASE: u64 = 0x1000_0000;
+pub const UART_SIZE: u64 = 0x100;
+/// PLIC source number of the UART's interrupt line.
+pub const UART_IRQ: u32 = 10;
+/// The frequency reported for the UART's input clock. Baud rates aren't
+/// emulated, so this is only there to keep drivers happy.
+const UART_CLOCK_FREQUENCY: u32 = 3_686_400;
+/// The PLIC has room for the contexts of this many harts.
+pub const MAX_HARTS: usize = 512;
+
+// Handles through which device tree nodes refer to each other
+const PLIC_PHANDLE: u32 = 1;
+const FINISHER_PHANDLE: u32 = 2;
+/// The interrupt controller of hart 0, followed by those of the other harts.
+const CPU_INTC_PHANDLE: u32 = 3;
+
+/// The bits of `mip` that are driven by devices.
+pub const DEVICE_INTERRUPTS: u64 = Interrupt::MachineSoftware.bit()
+    | Interrupt::MachineTimer.bit()
+    | Interrupt::MachineExternal.bit()
+    | Interrupt::SupervisorExternal.bit();
+
+pub struct Virt {
+    pub clint: Clint,
+    plic: Plic,
+    pub uart: Uart,
+    finisher: Finisher,
+}
+
+/// Everything needed to start executing on a freshly set up machine.
+pub struct Boot {
+    pub virt: Virt,
+    pub memory: Memory,
+    pub entry: u64,
+    pub tohost: Option<u64>,
+    /// Address of the device tree, which is passed to the firmware in `a1`,
+    /// or zero if there is none.
+    pub dtb: u64,
+}
+
+impl Virt {
+    /// Finds the device mapped at a physical address, along with the offset
+    /// of the address into it.
+    pub fn device_at(
+        &mut self,
+        address: u64,
+    ) -> Option<(&mut dyn Device, u64)> {
+        let within = |base, size| (base..base + size).contains(&address);
+        let (device, base): (&mut dyn Device, u64) =
+            if within(CLINT_BASE, CLINT_SIZE) {
+                (&mut self.clint, CLINT_BASE)
+            } else if within(PLIC_BASE, PLIC_SIZE) {
+                (&mut self.plic, PLIC_BASE)
+            } else if within(UART_BASE, UART_SIZE) {
+                (&mut self.uart, UART_BASE)
+            } else if within(FINISHER_BASE, FINISHER_SIZE) {
+                (&mut self.finisher, FINISHER_BASE)
+            } else {
+                return None;
+            };
+        Some((device, address - base))
+    }
+
+    /// Polls the devices, returning the state of the interrupt lines going
+    /// to a hart as bits of `mip`.
+    pub fn update(&mut self, hart: usize) -> u64 {
+        self.uart.poll();
+        self.plic.set_level(UART_IRQ, self.uart.interrupt());
+
+        // Each hart has an M-mode context followed by an S-mode context
+        [
+            (
+                self.clint.software_interrupt(hart),
+                Interrupt::MachineSoftware,
+            ),
+            (self.clint.timer_interrupt(hart), Interrupt::MachineTimer),
+            (self.plic.is_pending(2 * hart), Interrupt::MachineExternal),
+            (
+                self.plic.is_pending(2 * hart + 1),
+                Interrupt::SupervisorExternal,
+            ),
+        ]
+        .into_iter()
+        .filter(|&(level, _)| level)
+        .fold(0, |mip, (_, interrupt)| mip | interrupt.bit())
+    }
+
+    /// Returns the exit status if the guest has asked to power off.
+    pub fn take_shutdown(&mut self) -> Option<i32> {
+        self.finisher.take_status()
+    }
+}
+
+/// Creates a machine with the firmware, kernel, initrd and device tree loaded
+/// into RAM.
+pub fn boot(opts: &Opts, firmware: Program) -> Result<Boot, Box<dyn Error>> {
+    let ram_size = opts.memory_size as u64;
+    let ram_end = RAM_BASE + ram_size;
+    let initrd_address = RAM_BASE + ram_size / 2;
+    let mut memory = Memory::default();
+    memory.map(RAM_BASE, opts.memory_size);
+
+    // Each image goes at a fixed address, so a big one can run into the next
+    let mut copied = Vec::<(&str, u64, u64)>::new();
+    let mut copy_into_ram = |address: u64, bytes: &[u8], what: &'static str| {
+        let end = address.saturating_add(bytes.len() as u64);
+        if let Some((other, ..)) = copied
+            .iter()
+            .find(|&&(_, start, other_end)| start < end && address < other_end)
+        {
+            return Err(format!(
+                "{what} at 0x{address:016x} overlaps the {other}"
+            ));
+        }
+        memory.write_bytes(address, bytes).map_err(|_| {
+            format!("{what} at 0x{address:016x} doesn't fit in RAM")
+        })?;
+        copied.push((what, address, end));
+        Ok(())
+    };
+
+    for (base, bytes) in firmware.memory.regions() {
+        copy_into_ram(base, bytes, "firmware")?;
+    }
+    if let Some(path) = &opts.kernel {
+        copy_into_ram(RAM_BASE + KERNEL_OFFSET, &fs::read(path)?, "kernel")?;
+    }
+    if let Some(path) = &opts.initrd {
+        copy_into_ram(initrd_address, &fs::read(path)?, "initrd")?;
+    }
+    let dtb = match &opts.dtb {
+        Some(path) => fs::read(path)?,
+        None => device_tree(opts)?,
+    };
+    let dtb_address =
+        ram_end.saturating_sub(dtb.len() as u64) & !(DTB_ALIGNMENT - 1);
+    copy_into_ram(dtb_address, &dtb, "device tree")?;
+
+    Ok(Boot {
+        virt: Virt {
+            clint: Clint::new(Instant::now(), opts.harts),
+            plic: Plic::new(2 * opts.harts),
+            uart: Uart::new(),
+            finisher: Finisher::default(),
+        },
+        memory,
+        entry: firmware.entry,
+        tohost: firmware.tohost,
+        dtb: dtb_address,
+    })
+}
+
+/// Generates a device tree describing the machine.
+pub fn device_tree(opts: &Opts) -> Result<Vec<u8>, Box<dyn Error>> {
+    let ram_size = opts.memory_size as u64;
+    let mut dtb = DtbWriter::default();
+    dtb.begin_node("");
+    dtb.property_u32("#address-cells", 2);
+    dtb.property_u32("#size-cells", 2);
+    dtb.property_string("compatible", "riscv-virtio");
+    dtb.property_string("model", "riscv-virtio,rv");
+
+    dtb.begin_node("chosen");
+    dtb.property_string("stdout-path", &format!("/soc/serial@{UART_BASE:x}"));
+    if let Some(path) = &opts.initrd {
+        let initrd_address = RAM_BASE + ram_size / 2;
+        let initrd_size = fs::metadata(path)?.len();
+        dtb.property_u64("linux,initrd-start", initrd_address);
+        dtb.property_u64("linux,initrd-end", initrd_address + initrd_size);
+    }
+    dtb.end_node();
+
+    dtb.begin_node(&format!("memory@{RAM_BASE:x}"));
+    dtb.property_string("device_type", "memory");
+    dtb.property_reg("reg", &[(RAM_BASE, ram_size)]);
+    dtb.end_node();
+
+    dtb.begin_node("cpus");
+    dtb.property_u32("#address-cells", 1);
+    dtb.property_u32("#size-cells", 0);
+    dtb.property_u32("timebase-frequency", TIMEBASE_FREQUENCY as u32);
+    let (isa, extensions) = isa_string();
+    for hart in 0..opts.harts {
+        dtb.begin_node(&format!("cpu@{hart}"));
+        dtb.property_string("device_type", "cpu");
+        dtb.property_u32("reg", hart as u32);
+        dtb.property_string("status", "okay");
+        dtb.property_string("compatible", "riscv");
+        dtb.property_string("riscv,isa", &isa);
+        dtb.property_string("riscv,isa-base", "rv64i");
+        dtb.property_strings("riscv,isa-extensions", &extensions);
+        dtb.property_string("mmu-type", "riscv,sv48");
+        dtb.begin_node("interrupt-controller");
+        dtb.property_u32("#interrupt-cells", 1);
+        dtb.property_empty("interrupt-controller");
+        dtb.property_string("compatible", "riscv,cpu-intc");
+        dtb.property_u32("phandle", CPU_INTC_PHANDLE + hart as u32);
+        dtb.end_node();
+        dtb.end_node();
+    }
+    dtb.end_node();
+
+    dtb.begin_node("soc");
+    dtb.property_u32("#address-cells", 2);
+    dtb.property_u32("#size-cells", 2);
+    dtb.property_string("compatible", "simple-bus");
+    dtb.property_empty("ranges");
+
+    dtb.begin_node(&format!("clint@{CLINT_BASE:x}"));
+    dtb.property_strings("compatible", &["sifive,clint0", "riscv,clint0"]);
+    dtb.property_reg("reg", &[(CLINT_BASE, CLINT_SIZE)]);
+    dtb.property_cells(
+        "interrupts-extended",
+        &per_hart_interrupts(
+            opts.harts,
+            [Interrupt::MachineSoftware, Interrupt::MachineTimer],
+        ),
+    );
+    dtb.end_node();
+
+    dtb.begin_node(&format!("plic@{PLIC_BASE:x}"));
+    dtb.property_strings("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+    dtb.property_reg("reg", &[(PLIC_BASE, PLIC_SIZE)]);
+    dtb.property_u32("#address-cells", 0);
+    dtb.property_u32("#interrupt-cells", 1);
+    dtb.property_empty("interrupt-controller");
+    // In the same order as the PLIC's contexts
+    dtb.property_cells(
+        "interrupts-extended",
+        &per_hart_interrupts(
+            opts.harts,
+            [Interrupt::MachineExternal, Interrupt::SupervisorExternal],
+        ),
+    );
+    dtb.property_u32("riscv,ndev", NUM_SOURCES as u32 - 1);
+    dtb.property_u32("phandle", PLIC_PHANDLE);
+    dtb.end_node();
+
+    dtb.begin_node(&format!("serial@{UART_BASE:x}"));
+    dtb.property_string("compatible", "ns16550a");
+    dtb.property_reg("reg", &[(UART_BASE, UART_SIZE)]);
+    dtb.property_u32("clock-frequency", UART_CLOCK_FREQUENCY);
+    dtb.property_u32("interrupt-parent", PLIC_PHANDLE);
+    dtb.property_u32("interrupts", UART_IRQ);
+    dtb.end_node();
+
+    dtb.begin_node(&format!("test@{FINISHER_BASE:x}"));
+    dtb.property_strings(
+        "compatible",
+        &["sifive,test1", "sifive,test0", "syscon"],
+    );
+    dtb.property_reg("reg", &[(FINISHER_BASE, FINISHER_SIZE)]);
+    dtb.property_u32("phandle", FINISHER_PHANDLE);
+    dtb.end_node();
+    dtb.end_node();
+
+    // Powering off and rebooting both go through the finisher
+    for (name, value) in [("poweroff", 0x5555), ("reboot", 0x7777)] {
+        dtb.begin_node(name);
+        dtb.property_string("compatible", &format!("syscon-{name}"));
+        dtb.property_u32("regmap", FINISHER_PHANDLE);
+        dtb.property_u32("offset", 0);
+        dtb.property_u32("value", value);
+        dtb.end_node();
+    }
+
+    dtb.end_node();
+    Ok(dtb.finish())
+}
+
+/// Lists the given interrupts of every hart's interrupt controller, as cells
+/// of an `interrupts-extended` property.
+fn per_hart_interrupts(harts: usize, interrupts: [Interrupt; 2]) -> Vec<u32> {
+    (0..harts as u32)
+        .flat_map(|hart| {
+            interrupts
+                .map(|interrupt| [CPU_INTC_PHANDLE + hart, interrupt as u32])
+        })
+        .flatten()
+        .collect()
+}
+
+/// Describes the supported extensions, both as an ISA string like `rv64imc`
+/// and as a list of extension names.
+fn isa_string() -> (String, Vec<&'static str>) {
+    const EXTENSIONS: [&str; 8] =
+        ["i", "m", "a", "f", "d", "c", "zicsr", "zifencei"];
+    let extensions = EXTENSIONS
+        .into_iter()
+        .filter(|extension| match extension.as_bytes() {
+            &[letter] => {
+                csr::MISA_VALUE & csr::isa_bit(letter.to_ascii_uppercase()) != 0
+            }
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    let mut isa = String::from("rv64");
+    for extension in &extensions {
+        // Multi-letter extensions are separated by underscores
+        if extension.len() > 1 {
+            isa.push('_');
+        }
+        isa.push_str(extension);
+    }
+    (isa, extensions)
+}
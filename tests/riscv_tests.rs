@@ -0,0 +1,132 @@
+//! Runs the ISA tests from `riscv-tests`, which have to be built separately.
+//!
+//! The suites are ignored by default. Point `RISCV_TESTS` at the directory
+//! that holds the built tests, like `riscv-tests/isa` or
+//! `$RISCV/share/riscv-tests/isa`, and run them with
+//! `cargo test --test riscv_tests -- --ignored`.
+//!
+//! Each test is a bare-metal ELF that reports through HTIF `tohost`, which
+//! `rv` turns into its exit status: zero if the test passed, or the number of
+//! the test case that failed otherwise.
+
+use std::{
+    env, fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long a single test may run before it's considered hung.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Outcome {
+    Passed,
+    /// The test case with the given number failed.
+    Failed(i32),
+    /// `rv` exited without a status, or with an error of its own.
+    Crashed(String),
+    TimedOut,
+}
+
+fn run_test(path: &Path) -> Outcome {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rv"))
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start rv");
+    // Drained while the test runs, so that `rv` never blocks on a full pipe
+    let mut pipe = child.stderr.take().unwrap();
+    let stderr = thread::spawn(move || {
+        let mut stderr = String::new();
+        let _ = pipe.read_to_string(&mut stderr);
+        stderr
+    });
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().expect("failed to wait for rv") {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Outcome::TimedOut;
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+    let stderr = stderr.join().unwrap();
+    match status.code() {
+        Some(0) => Outcome::Passed,
+        // Errors in the emulator itself are reported on stderr
+        Some(code) if stderr.is_empty() => Outcome::Failed(code),
+        _ => Outcome::Crashed(stderr.trim().to_owned()),
+    }
+}
+
+/// The tests of a suite, like `rv64ui`, that run in the `p` environment:
+/// physical memory only, with a single hart.
+fn tests_in(dir: &Path, suite: &str) -> Vec<PathBuf> {
+    let prefix = format!("{suite}-p-");
+    let mut tests = fs::read_dir(dir)
+        .expect("failed to read RISCV_TESTS")
+        .map(|entry| entry.expect("failed to read RISCV_TESTS").path())
+        .filter(|path| {
+            // Disassemblies of the tests are often kept alongside them
+            path.extension().is_none()
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(&prefix))
+        })
+        .collect::<Vec<_>>();
+    tests.sort();
+    tests
+}
+
+fn run_suite(suite: &str) {
+    let dir = env::var_os("RISCV_TESTS")
+        .map(PathBuf::from)
+        .expect("RISCV_TESTS has to point at the built tests");
+    let tests = tests_in(&dir, suite);
+    assert!(!tests.is_empty(), "no {suite} tests in {}", dir.display());
+    let mut failures = Vec::new();
+    for test in &tests {
+        let name = test.file_name().unwrap().to_string_lossy();
+        let failure = match run_test(test) {
+            Outcome::Passed => None,
+            Outcome::Failed(case) => Some(format!("test case {case} failed")),
+            Outcome::Crashed(message) => Some(message),
+            Outcome::TimedOut => Some(format!("timed out after {TIMEOUT:?}")),
+        };
+        match failure {
+            None => eprintln!("PASS {name}"),
+            Some(failure) => {
+                eprintln!("FAIL {name}: {failure}");
+                failures.push(name.into_owned());
+            }
+        }
+    }
+    eprintln!(
+        "{suite}: {} passed, {} failed",
+        tests.len() - failures.len(),
+        failures.len()
+    );
+    assert!(failures.is_empty(), "failed tests: {}", failures.join(", "));
+}
+
+macro_rules! suites {
+    ($($suite:ident),*) => {
+        $(
+            #[test]
+            #[ignore = "needs riscv-tests, see RISCV_TESTS"]
+            fn $suite() {
+                run_suite(stringify!($suite));
+            }
+        )*
+    };
+}
+
+suites!(rv64ui, rv64um, rv64ua, rv64uc, rv64uf, rv64ud);